@@ -0,0 +1,90 @@
+use cpal::{self, Sample, SampleFormat, Stream, StreamConfig};
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use log::error;
+use rustboy::AudioSink;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+// Maximum audio queued up for the device, in seconds.
+// The emulator runs slightly faster than the real hardware, so anything past this is dropped to keep latency down.
+const MAX_BUFFERED_SECONDS: f32 = 0.1;
+
+// Plays samples through the host's default output device.
+// Samples are queued up and pulled by cpal's audio thread.
+pub struct HostAudio {
+    _stream: Stream,            // Playback stops when the stream is dropped
+    buffer: Arc<Mutex<VecDeque<(f32, f32)>>>,
+    sample_rate: u32,
+    max_buffered: usize
+}
+
+impl HostAudio {
+    pub fn new() -> Result<Self, String> {
+        let device = cpal::default_host().default_output_device().ok_or("No audio output device found")?;
+        let supported = device.default_output_config().map_err(|err| err.to_string())?;
+
+        let sample_format = supported.sample_format();
+        let config: StreamConfig = supported.into();
+        let buffer = Arc::new(Mutex::new(VecDeque::new()));
+
+        let stream = match sample_format {
+            SampleFormat::F32 => build_stream::<f32>(&device, &config, buffer.clone()),
+            SampleFormat::I16 => build_stream::<i16>(&device, &config, buffer.clone()),
+            SampleFormat::U16 => build_stream::<u16>(&device, &config, buffer.clone())
+        }?;
+
+        stream.play().map_err(|err| err.to_string())?;
+
+        Ok(Self {
+            _stream: stream,
+            buffer,
+            sample_rate: config.sample_rate.0,
+            max_buffered: (config.sample_rate.0 as f32 * MAX_BUFFERED_SECONDS) as usize
+        })
+    }
+}
+
+impl AudioSink for HostAudio {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_sample(&mut self, left: f32, right: f32) {
+        let mut buffer = self.buffer.lock().unwrap();
+
+        if buffer.len() < self.max_buffered {
+            buffer.push_back((left, right));
+        }
+    }
+}
+
+fn build_stream<T: Sample>(device: &cpal::Device, config: &StreamConfig, buffer: Arc<Mutex<VecDeque<(f32, f32)>>>) -> Result<Stream, String> {
+    let channels = config.channels as usize;
+
+    let stream = device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            let mut buffer = buffer.lock().unwrap();
+
+            for frame in data.chunks_mut(channels) {
+                // Play silence if the emulator falls behind
+                let (left, right) = buffer.pop_front().unwrap_or((0.0, 0.0));
+
+                // Mono devices get both sides mixed, anything past stereo stays silent
+                for (i, sample) in frame.iter_mut().enumerate() {
+                    let value = match (channels, i) {
+                        (1, _) => (left + right) / 2.0,
+                        (_, 0) => left,
+                        (_, 1) => right,
+                        _ => 0.0
+                    };
+
+                    *sample = T::from(&value);
+                }
+            }
+        },
+        |err| error!("Audio stream error: {}", err)
+    );
+
+    stream.map_err(|err| err.to_string())
+}
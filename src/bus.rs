@@ -1,11 +1,20 @@
 use cartridge::Cartridge;
-use joypad::Joypad;
-use lcd::Lcd;
+use cpu::{Interrupt, Memory};
+use debugger::Watchpoints;
+use joypad::{Button, Joypad};
+use lcd::{Lcd, SCREEN_WIDTH, SCREEN_HEIGHT};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use log::warn;
+use savestate::{self, Savestate};
 use serial::Serial;
 use sound::Sound;
+use std::io::{self, Read, Write};
 use timer::Timer;
 
+const BOOT_ROM_START: u16 = 0;
+const BOOT_ROM_END: u16 = 0xFF;
+const BOOT_ROM_SIZE: usize = (BOOT_ROM_END as usize) - (BOOT_ROM_START as usize)+1;
+
 const CARTRIDGE_ROM_START: u16 = 0;
 const CARTRIDGE_ROM_END: u16 = 0x7FFF;
 
@@ -40,6 +49,9 @@ const IO_VIDEO_START: u16 = 0xFF40;
 const IO_VIDEO_END: u16 = 0xFF4B;
 
 const DMA_ADDR: u16 = 0xFF46;
+const DMA_LENGTH: u16 = 0xA0;
+
+const IO_BOOT_ROM_DISABLE: u16 = 0xFF50;
 
 pub const IO_IF_ADDR: u16 = 0xFF0F;
 
@@ -52,6 +64,26 @@ const HIGH_RAM_SIZE: usize = (HIGH_RAM_END as usize) - (HIGH_RAM_START as usize)
 
 pub const IO_IE_ADDR: u16 = 0xFFFF;
 
+// Clock cycles in a machine cycle, the time the CPU takes for one memory access
+const CYCLES_PER_TICK: usize = 4;
+
+// The DMG has separate buses for the cartridge and work RAM, and for video RAM.
+// OAM DMA occupies the bus it copies from, so the CPU can't use that one until the transfer is over.
+#[derive(Copy, Clone, PartialEq)]
+enum MemoryBus {
+    External,
+    Video
+}
+
+fn memory_bus(addr: u16) -> Option<MemoryBus> {
+    match addr {
+        VIDEO_RAM_START..=VIDEO_RAM_END => Some(MemoryBus::Video),
+        CARTRIDGE_ROM_START..=CARTRIDGE_ROM_END | SWITCHABLE_RAM_START..=ECHO_RAM_END => Some(MemoryBus::External),
+        // OAM, IO and high RAM are on the CPU's internal bus
+        _ => None
+    }
+}
+
 pub trait Addressable {
     fn read(&self, addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
@@ -65,7 +97,7 @@ struct Ram {
 impl Ram {
     fn new(start_addr: u16, size: usize) -> Self {
         Self {
-            start_addr: start_addr,
+            start_addr,
             data: vec![0; size]
         }
     }
@@ -81,23 +113,61 @@ impl Addressable for Ram {
     }
 }
 
+impl Savestate for Ram {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bytes(out, &self.data)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        savestate::read_bytes(input, &mut self.data)
+    }
+}
+
+// What the hardware did while the CPU was running, collected until the emulator takes it
+#[derive(Default)]
+pub struct Events {
+    pub cycles: usize,          // Clock cycles the hardware was stepped by
+    pub vblank: bool,           // LCD entered VBlank
+    pub serial: Option<u8>      // Byte sent out the serial port
+}
+
 pub struct Bus<'a> {
+    boot_rom: Vec<u8>,
+    boot_rom_mapped: bool,      // Cleared for good when the boot ROM writes to 0xFF50
+    buttons: Button,
     cartridge: &'a mut Cartridge,
+    dma: u8,                    // Last value written to 0xFF46
+    dma_source: u16,
+    dma_progress: Option<u16>,  // Bytes copied by the transfer in progress
+    dma_start: Option<u16>,     // Source of a transfer that starts on the next cycle
+    dma_byte: u8,               // Byte the transfer copied on the current cycle
+    events: Events,
     io_ie: u8,
     io_if: u8,
     high_ram: Ram,
     pub joypad: Joypad,
     pub lcd: Lcd,
     pub serial: Serial,
-    sound: Sound,
+    pub sound: Sound,
     pub timer: Timer,
+    screen_buffer: Vec<u32>,
+    pub watchpoints: Watchpoints,
     work_ram: Ram,
 }
 
 impl<'a> Bus<'a> {
     pub fn new(cart: &'a mut Cartridge) -> Self {
         Self {
+            boot_rom: Vec::new(),
+            boot_rom_mapped: false,
+            buttons: Button::empty(),
             cartridge: cart,
+            dma: 0xFF,
+            dma_source: 0,
+            dma_progress: None,
+            dma_start: None,
+            dma_byte: 0xFF,
+            events: Events::default(),
             io_ie: 0,
             io_if: 0,
             high_ram: Ram::new(HIGH_RAM_START, HIGH_RAM_SIZE),
@@ -106,21 +176,159 @@ impl<'a> Bus<'a> {
             serial: Serial::new(),
             sound: Sound::default(),
             timer: Timer::new(),
+            screen_buffer: vec![0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            watchpoints: Watchpoints::default(),
             work_ram: Ram::new(WORK_RAM_START, WORK_RAM_SIZE)
         }
     }
 
+    // Maps a DMG boot ROM over the start of the cartridge until it unmaps itself
+    pub fn map_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        if boot_rom.len() != BOOT_ROM_SIZE {
+            return Err(format!("Boot ROM is {} bytes, expected a {} byte DMG boot ROM", boot_rom.len(), BOOT_ROM_SIZE));
+        }
+
+        self.boot_rom = boot_rom;
+        self.boot_rom_mapped = true;
+
+        Ok(())
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom_mapped
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        self.cartridge
+    }
+
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        self.cartridge
+    }
+
+    // 160x144 RGB pixels of the most recently drawn frame
+    pub fn framebuffer(&self) -> &[u32] {
+        &self.screen_buffer
+    }
+
+    pub fn clear_framebuffer(&mut self) {
+        for i in self.screen_buffer.iter_mut() {
+            *i = 0xFFFFFF;
+        }
+    }
+
+    // Buttons currently held down. These are picked up by the joypad on the next cycle.
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.buttons = buttons;
+    }
+
+    // Returns everything that happened since the last call
+    pub fn take_events(&mut self) -> Events {
+        ::std::mem::take(&mut self.events)
+    }
+
+    // Advances everything attached to the bus by one machine cycle and requests any interrupts they raise
+    fn tick(&mut self) {
+        self.step_dma();
+
+        // Step cartridge hardware (MBC3 clock)
+        self.cartridge.step(CYCLES_PER_TICK);
+
+        // The timer interrupts when the counter reaches its goal
+        if self.timer.step(CYCLES_PER_TICK).interrupt {
+            self.io_if |= Interrupt::Timer as u8;
+        }
+
+        // Step APU. Its frame sequencer is clocked off of DIV.
+        let div = self.timer.div();
+        self.sound.step(CYCLES_PER_TICK, div);
+
+        // Serial interrupt happens after a byte is transferred
+        let serial_result = self.serial.step(CYCLES_PER_TICK);
+
+        if serial_result.interrupt {
+            self.io_if |= Interrupt::Serial as u8;
+        }
+
+        if serial_result.sent.is_some() {
+            self.events.serial = serial_result.sent;
+        }
+
+        // LCD can generate a STAT interrupt when modes change or when the cursor reaches a specific line.
+        // It interrupts when VBLANK is reached.
+        let lcd_result = self.lcd.step(CYCLES_PER_TICK, &mut self.screen_buffer);
+
+        if lcd_result.int_stat {
+            self.io_if |= Interrupt::Stat as u8;
+        }
+
+        if lcd_result.int_vblank {
+            self.io_if |= Interrupt::VBlank as u8;
+            self.events.vblank = true;
+        }
+
+        // Joypad interrupts when a button is pressed
+        if self.joypad.step(self.buttons).interrupt {
+            self.io_if |= Interrupt::Joypad as u8;
+        }
+
+        self.events.cycles += CYCLES_PER_TICK;
+    }
+
+    // Writing 0xFF46 copies 160 bytes from 0xXX00 to OAM, one byte per cycle, after a cycle to get started.
+    // Writing it again during a transfer restarts it, with the old transfer running until the new one starts.
     fn dma_transfer(&mut self, high_byte: u8) {
-        for low_byte in 0..0xA0 {
-            let src_val = self.read(((high_byte as u16) << 8) | low_byte);
-            self.write(0xFE00 | low_byte, src_val);
+        self.dma = high_byte;
+        self.dma_start = Some((high_byte as u16) << 8);
+    }
+
+    fn step_dma(&mut self) {
+        if let Some(progress) = self.dma_progress {
+            self.dma_byte = self.read_mapped(self.dma_source + progress);
+            self.lcd.dma_write(progress as usize, self.dma_byte);
+
+            self.dma_progress = if progress + 1 < DMA_LENGTH { Some(progress + 1) } else { None };
+        }
+
+        if let Some(source) = self.dma_start.take() {
+            // Sources past work RAM read the echo of it
+            self.dma_source = if source >= ECHO_RAM_START { source - 0x2000 } else { source };
+            self.dma_progress = Some(0);
         }
     }
-}
 
-impl<'a> Addressable for Bus<'a> {
-    fn read(&self, addr: u16) -> u8 {
+    // While DMA runs the CPU can't reach OAM, and accesses to the bus the transfer is using see the byte it's copying.
+    // IO registers and high RAM stay reachable, so the transfer can be restarted from code running in high RAM.
+    // Returns the value the CPU sees, or None if the access goes through normally.
+    fn dma_conflict(&self, addr: u16) -> Option<u8> {
+        self.dma_progress?;
+
         match addr {
+            OAM_START..=UNUSED_END => Some(0xFF),
+            _ if memory_bus(addr).is_some() && memory_bus(addr) == memory_bus(self.dma_source) => Some(self.dma_byte),
+            _ => None
+        }
+    }
+
+    // An OAM DMA transfer is copying memory
+    pub fn dma_active(&self) -> bool {
+        self.dma_progress.is_some()
+    }
+
+    // Reads memory without triggering watchpoints, for tools that inspect the machine
+    pub fn peek(&self, addr: u16) -> u8 {
+        self.read_mapped(addr)
+    }
+
+    // Writes memory without triggering watchpoints, for tools that modify the machine
+    pub fn poke(&mut self, addr: u16, val: u8) {
+        self.write_mapped(addr, val);
+    }
+
+    fn read_mapped(&self, addr: u16) -> u8 {
+        match addr {
+            // 0x0000 - 0x00FF Boot ROM, until it's unmapped
+            BOOT_ROM_START..=BOOT_ROM_END if self.boot_rom_mapped => self.boot_rom[addr as usize],
             // 0x0000 - 0x7FFF Cartridge ROM
             CARTRIDGE_ROM_START..=CARTRIDGE_ROM_END => self.cartridge.read(addr),
             // 0x8000 - 0x9FFF Video ROM
@@ -144,7 +352,9 @@ impl<'a> Addressable for Bus<'a> {
             // 0xFF40 - 0xFE9F Video IO ports (omit DMA)
             IO_VIDEO_START..=IO_VIDEO_END if addr != DMA_ADDR => self.lcd.read(addr),
             // 0xFF46 DMA
-            DMA_ADDR => 1,
+            DMA_ADDR => self.dma,
+            // 0xFF50 Boot ROM disable, write only
+            IO_BOOT_ROM_DISABLE => 0xFF,
             // 0xFF0F IF IO port
             IO_IF_ADDR => self.io_if | 0b11100000,
             // 0xFF10 - 0xFF3F Sound IO ports
@@ -157,7 +367,7 @@ impl<'a> Addressable for Bus<'a> {
         }
     }
 
-    fn write(&mut self, addr: u16, val: u8) {
+    fn write_mapped(&mut self, addr: u16, val: u8) {
         match addr {
             // 0x0000 - 0x7FFF Cartridge ROM
             CARTRIDGE_ROM_START..=CARTRIDGE_ROM_END => self.cartridge.write(addr, val),
@@ -183,6 +393,8 @@ impl<'a> Addressable for Bus<'a> {
             IO_VIDEO_START..=IO_VIDEO_END if addr != DMA_ADDR => self.lcd.write(addr, val),
             // 0xFF46 DMA 
             DMA_ADDR => self.dma_transfer(val),
+            // 0xFF50 Boot ROM disable. Any non-zero value unmaps the boot ROM until the next power cycle.
+            IO_BOOT_ROM_DISABLE => if val != 0 { self.boot_rom_mapped = false },
             // 0xFF0F IF IO port
             IO_IF_ADDR => self.io_if = val & 0b11111,
             // 0xFF10 - 0xFF3F Sound IO ports
@@ -194,4 +406,248 @@ impl<'a> Addressable for Bus<'a> {
             _ => warn!("Unimplemented write ({:#X} -> {:#X})", val, addr)
         }
     }
-}
\ No newline at end of file
+}
+
+// Accesses are checked against the debugger's watchpoints before being passed on to the mapped hardware
+impl<'a> Addressable for Bus<'a> {
+    fn read(&self, addr: u16) -> u8 {
+        let val = self.read_mapped(addr);
+
+        if !self.watchpoints.is_empty() {
+            self.watchpoints.check_read(addr, val);
+        }
+
+        val
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        if !self.watchpoints.is_empty() {
+            let old = self.read_mapped(addr);
+            self.watchpoints.check_write(addr, old, val);
+        }
+
+        self.write_mapped(addr, val);
+    }
+}
+
+// CPU accesses go through the watchpoints like any other, with the hardware stepped before each one
+impl<'a> Memory for Bus<'a> {
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        self.tick();
+
+        match self.dma_conflict(addr) {
+            Some(val) => val,
+            None => self.read(addr)
+        }
+    }
+
+    // Writes that conflict with DMA are lost
+    fn write_cycle(&mut self, addr: u16, val: u8) {
+        self.tick();
+
+        if self.dma_conflict(addr).is_none() {
+            self.write(addr, val);
+        }
+    }
+
+    fn idle_cycle(&mut self) {
+        self.tick();
+    }
+
+    fn stopped_cycle(&mut self) {
+        // The cartridge's clock has its own crystal, so it keeps time while the Game Boy's is stopped
+        self.cartridge.step(CYCLES_PER_TICK);
+
+        if self.joypad.step(self.buttons).interrupt {
+            self.io_if |= Interrupt::Joypad as u8;
+        }
+
+        self.events.cycles += CYCLES_PER_TICK;
+    }
+
+    fn cycles(&self) -> usize {
+        self.events.cycles
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.io_if & self.io_ie & 0b11111
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.io_if &= !(interrupt as u8);
+    }
+
+    // Writing anything to DIV resets it
+    fn stop(&mut self) {
+        self.timer.write(IO_TIMER_START, 0);
+    }
+
+    fn button_pressed(&self) -> bool {
+        self.joypad.pressed()
+    }
+}
+
+impl<'a> Savestate for Bus<'a> {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.io_ie)?;
+        out.write_u8(self.io_if)?;
+        savestate::write_bool(out, self.boot_rom_mapped)?;
+        out.write_u8(self.dma)?;
+        out.write_u16::<LittleEndian>(self.dma_source)?;
+        write_option_u16(out, self.dma_progress)?;
+        write_option_u16(out, self.dma_start)?;
+        out.write_u8(self.dma_byte)?;
+        self.work_ram.save_state(out)?;
+        self.high_ram.save_state(out)?;
+        self.joypad.save_state(out)?;
+        self.lcd.save_state(out)?;
+        self.serial.save_state(out)?;
+        self.sound.save_state(out)?;
+        self.timer.save_state(out)?;
+        self.cartridge.save_state(out)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.io_ie = input.read_u8()?;
+        self.io_if = input.read_u8()?;
+        self.boot_rom_mapped = savestate::read_bool(input)? && !self.boot_rom.is_empty();
+        self.dma = input.read_u8()?;
+        self.dma_source = input.read_u16::<LittleEndian>()?;
+        self.dma_progress = read_option_u16(input)?;
+        self.dma_start = read_option_u16(input)?;
+        self.dma_byte = input.read_u8()?;
+
+        // Transfers copy from the start of a page, and sources past work RAM have already been moved to the echo
+        let valid_source = |source: u16| source & 0xFF == 0;
+        let valid = valid_source(self.dma_source) && self.dma_source < ECHO_RAM_START
+            && self.dma_start.is_none_or(valid_source)
+            && self.dma_progress.is_none_or(|progress| progress < DMA_LENGTH);
+
+        if !valid {
+            return Err(savestate::invalid_data("Invalid OAM DMA state"));
+        }
+
+        self.work_ram.load_state(input)?;
+        self.high_ram.load_state(input)?;
+        self.joypad.load_state(input)?;
+        self.lcd.load_state(input)?;
+        self.serial.load_state(input)?;
+        self.sound.load_state(input)?;
+        self.timer.load_state(input)?;
+        self.cartridge.load_state(input)
+    }
+}
+
+fn write_option_u16(out: &mut dyn Write, val: Option<u16>) -> io::Result<()> {
+    savestate::write_bool(out, val.is_some())?;
+    out.write_u16::<LittleEndian>(val.unwrap_or(0))
+}
+
+fn read_option_u16(input: &mut dyn Read) -> io::Result<Option<u16>> {
+    let present = savestate::read_bool(input)?;
+    let val = input.read_u16::<LittleEndian>()?;
+
+    Ok(if present { Some(val) } else { None })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fill(bus: &mut Bus, start: u16, base: u8) {
+        for i in 0..DMA_LENGTH {
+            bus.write(start + i, base.wrapping_add(i as u8));
+        }
+    }
+
+    #[test]
+    fn dma_copies_a_byte_per_cycle() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+        let mut bus = Bus::new(&mut cartridge);
+
+        fill(&mut bus, 0xC000, 0x10);
+        bus.write_cycle(DMA_ADDR, 0xC0);
+
+        // A cycle to start up, then the first byte
+        bus.idle_cycle();
+        assert!(bus.dma_active());
+        assert_eq!(bus.peek(OAM_START), 0);
+
+        bus.idle_cycle();
+        assert_eq!(bus.peek(OAM_START), 0x10);
+        assert_eq!(bus.peek(OAM_START + 1), 0);
+
+        for _ in 1..DMA_LENGTH {
+            bus.idle_cycle();
+        }
+
+        assert!(!bus.dma_active());
+        assert_eq!(bus.peek(OAM_END), 0x10 + 0x9F);
+        assert_eq!(bus.read(DMA_ADDR), 0xC0);
+    }
+
+    #[test]
+    fn dma_blocks_its_bus() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+        let mut bus = Bus::new(&mut cartridge);
+
+        fill(&mut bus, 0xC000, 0x10);
+        bus.write(0xFF80, 0x42);
+        bus.lcd.write(0xFF40, 0);
+        bus.write(VIDEO_RAM_START, 0x24);
+
+        bus.write_cycle(DMA_ADDR, 0xC0);
+        bus.idle_cycle();
+        bus.idle_cycle();
+
+        // Reads from the external bus see the byte the transfer copies during them
+        assert_eq!(bus.read_cycle(0xD000), 0x11);
+        assert_eq!(bus.read_cycle(0x0000), 0x12);
+        assert_eq!(bus.read_cycle(OAM_START), 0xFF);
+        assert_eq!(bus.read_cycle(VIDEO_RAM_START), 0x24);
+        assert_eq!(bus.read_cycle(0xFF47), 0xFC);
+        assert_eq!(bus.read_cycle(0xFF80), 0x42);
+
+        bus.write_cycle(0xD000, 0x99);
+        bus.write_cycle(VIDEO_RAM_START, 0x99);
+        bus.write_cycle(0xFF47, 0x99);
+        bus.write_cycle(0xFF81, 0x99);
+        assert_eq!(bus.peek(0xD000), 0);
+        assert_eq!(bus.peek(VIDEO_RAM_START), 0x99);
+        assert_eq!(bus.peek(0xFF47), 0x99);
+        assert_eq!(bus.peek(0xFF81), 0x99);
+
+        // A transfer from video RAM blocks that bus instead
+        for _ in 0..DMA_LENGTH {
+            bus.idle_cycle();
+        }
+        bus.write_cycle(DMA_ADDR, 0x80);
+        bus.idle_cycle();
+        bus.idle_cycle();
+
+        assert_eq!(bus.read_cycle(VIDEO_RAM_START), 0x00);
+        assert_eq!(bus.read_cycle(0xC000), 0x10);
+    }
+
+    #[test]
+    fn dma_restarts() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+        let mut bus = Bus::new(&mut cartridge);
+
+        fill(&mut bus, 0xC000, 0x10);
+        fill(&mut bus, 0xD000, 0x80);
+
+        bus.write_cycle(DMA_ADDR, 0xC0);
+        bus.idle_cycle();
+        bus.idle_cycle();
+
+        // The old transfer copies one more byte while the new one starts up
+        bus.write_cycle(DMA_ADDR, 0xD0);
+        bus.idle_cycle();
+        assert_eq!(bus.peek(OAM_START + 2), 0x12);
+
+        bus.idle_cycle();
+        assert_eq!(bus.peek(OAM_START), 0x80);
+        assert_eq!(bus.peek(OAM_START + 3), 0);
+    }
+}
@@ -0,0 +1,121 @@
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use log::warn;
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use super::Mapper;
+
+// MBC2 has 512 half-bytes of RAM built into the mapper itself
+const RAM_SIZE: usize = 512;
+
+pub struct Mbc2 {
+    rom_banks: usize,
+    ram_enabled: bool,
+    ram_data: Vec<u8>,
+    rom_bank: u8                // 4 bit ROM bank number
+}
+
+impl Mbc2 {
+    pub fn new(rom_banks: usize) -> Self {
+        Self {
+            rom_banks,
+            ram_enabled: false,
+            ram_data: vec![0; RAM_SIZE],
+            rom_bank: 1
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let bank = if self.rom_banks == 0 {
+            1
+        } else {
+            (self.rom_bank as usize) & (self.rom_banks - 1)
+        };
+
+        ((addr as usize) - 0x4000) + (bank * 0x4000)
+    }
+
+    // Only the bottom 9 bits of the address are used, so the RAM repeats throughout 0xA000-0xBFFF
+    fn ram_index(&self, addr: u16) -> usize {
+        (addr as usize) & (RAM_SIZE - 1)
+    }
+}
+
+impl Savestate for Mbc2 {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.ram_enabled)?;
+        out.write_u8(self.rom_bank)?;
+        savestate::write_bytes(out, &self.ram_data)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.ram_enabled = savestate::read_bool(input)?;
+        self.rom_bank = input.read_u8()?;
+        savestate::read_bytes(input, &mut self.ram_data)
+    }
+}
+
+impl Mapper for Mbc2 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => {
+                // ROM bank 0
+                rom[addr as usize]
+            },
+            0x4000..=0x7FFF => {
+                // Switchable ROM bank
+                rom[self.rom_index(addr)]
+            },
+            0xA000..=0xBFFF => {
+                // Built-in RAM. Only the lower nibble is stored, the upper nibble reads as 1s.
+                if self.ram_enabled {
+                    0xF0 | self.ram_data[self.ram_index(addr)]
+                } else {
+                    0xFF
+                }
+            },
+            _ => {
+                warn!("Read from address {:#X} not handled by MBC2", addr);
+
+                0xFF
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x3FFF => {
+                // Bit 8 of the address selects the register.
+                // When clear, the value toggles RAM. When set, it switches the ROM bank.
+                if addr & 0x0100 == 0 {
+                    self.ram_enabled = (val & 0x0F) == 0x0A;
+                } else {
+                    self.rom_bank = match val & 0x0F {
+                        0 => 1,
+                        bank => bank
+                    };
+                }
+            },
+            0x4000..=0x7FFF => { },
+            0xA000..=0xBFFF => {
+                // Write to built-in RAM
+                if self.ram_enabled {
+                    let index = self.ram_index(addr);
+                    self.ram_data[index] = val & 0x0F;
+                }
+            },
+            _ => warn!("Write to address {:#X} not handled by MBC2", addr)
+        }
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_data
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_index(0x4000) / 0x4000
+    }
+}
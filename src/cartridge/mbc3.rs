@@ -0,0 +1,404 @@
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
+use log::warn;
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::Mapper;
+
+// The RTC is driven by a 32.768KHz crystal. Counting in CPU clock cycles, a second is 4194304 cycles.
+const CYCLES_PER_SECOND: usize = 4_194_304;
+
+// Battery saves end with the clock state in the format used by most emulators:
+// 5 current registers and 5 latched registers stored as 32 bit values, followed by a 64 bit UNIX timestamp.
+const RTC_FOOTER_SIZE: usize = 48;
+
+// Day high register
+// Bit 0 is the upper bit of the 9 bit day counter
+// Bit 6 halts the clock
+// Bit 7 is set when the day counter overflows
+const DH_DAY_UPPER: u8 = 0b0000_0001;
+const DH_HALT: u8      = 0b0100_0000;
+const DH_CARRY: u8     = 0b1000_0000;
+
+#[derive(Copy, Clone, Default)]
+struct RtcRegisters {
+    seconds: u8,
+    minutes: u8,
+    hours: u8,
+    days_low: u8,
+    days_high: u8
+}
+
+impl RtcRegisters {
+    fn days(&self) -> u16 {
+        (((self.days_high & DH_DAY_UPPER) as u16) << 8) | (self.days_low as u16)
+    }
+
+    fn set_days(&mut self, days: u16) {
+        self.days_low = (days & 0xFF) as u8;
+        self.days_high = (self.days_high & !DH_DAY_UPPER) | (((days >> 8) as u8) & DH_DAY_UPPER);
+    }
+
+    fn read(&self, select: u8) -> u8 {
+        match select {
+            0x08 => self.seconds,
+            0x09 => self.minutes,
+            0x0A => self.hours,
+            0x0B => self.days_low,
+            0x0C => self.days_high,
+            _ => unreachable!()
+        }
+    }
+
+    fn as_array(&self) -> [u8; 5] {
+        [self.seconds, self.minutes, self.hours, self.days_low, self.days_high]
+    }
+
+    fn from_slice(data: &[u8]) -> Self {
+        Self {
+            seconds: data[0] & 0x3F,
+            minutes: data[1] & 0x3F,
+            hours: data[2] & 0x1F,
+            days_low: data[3],
+            days_high: data[4] & (DH_DAY_UPPER | DH_HALT | DH_CARRY)
+        }
+    }
+}
+
+struct Rtc {
+    current: RtcRegisters,
+    latched: RtcRegisters,
+    cycles: usize,              // Cycles since the seconds counter last ticked
+    latch_ready: bool           // Writing 0 then 1 to the latch register copies the current time to the latched registers
+}
+
+impl Rtc {
+    fn new() -> Self {
+        Self {
+            current: RtcRegisters::default(),
+            latched: RtcRegisters::default(),
+            cycles: 0,
+            latch_ready: false
+        }
+    }
+
+    fn halted(&self) -> bool {
+        self.current.days_high & DH_HALT == DH_HALT
+    }
+
+    fn step(&mut self, cycles: usize) {
+        if !self.halted() {
+            self.cycles += cycles;
+
+            while self.cycles >= CYCLES_PER_SECOND {
+                self.cycles -= CYCLES_PER_SECOND;
+                self.tick();
+            }
+        }
+    }
+
+    // Advances the clock by one second.
+    // Registers can be written with out of range values. In that case they keep counting until their bits overflow without carrying.
+    fn tick(&mut self) {
+        let regs = &mut self.current;
+
+        if regs.seconds == 59 {
+            regs.seconds = 0;
+        } else {
+            regs.seconds = (regs.seconds + 1) & 0x3F;
+            return;
+        }
+
+        if regs.minutes == 59 {
+            regs.minutes = 0;
+        } else {
+            regs.minutes = (regs.minutes + 1) & 0x3F;
+            return;
+        }
+
+        if regs.hours == 23 {
+            regs.hours = 0;
+        } else {
+            regs.hours = (regs.hours + 1) & 0x1F;
+            return;
+        }
+
+        let days = regs.days();
+
+        if days == 0x1FF {
+            regs.set_days(0);
+            regs.days_high |= DH_CARRY;
+        } else {
+            regs.set_days(days + 1);
+        }
+    }
+
+    // Catches the clock up on time that passed while the emulator wasn't running
+    fn advance_seconds(&mut self, seconds: u64) {
+        if self.halted() || seconds == 0 {
+            return;
+        }
+
+        let regs = &mut self.current;
+        let total = (regs.seconds as u64)
+            + (regs.minutes as u64) * 60
+            + (regs.hours as u64) * 3600
+            + (regs.days() as u64) * 86400
+            + seconds;
+
+        let days = total / 86400;
+
+        regs.seconds = (total % 60) as u8;
+        regs.minutes = ((total / 60) % 60) as u8;
+        regs.hours = ((total / 3600) % 24) as u8;
+        regs.set_days((days % 512) as u16);
+
+        if days >= 512 {
+            regs.days_high |= DH_CARRY;
+        }
+    }
+
+    fn write_latch(&mut self, val: u8) {
+        if self.latch_ready && val == 1 {
+            self.latched = self.current;
+        }
+
+        self.latch_ready = val == 0;
+    }
+
+    fn write(&mut self, select: u8, val: u8) {
+        let regs = &mut self.current;
+
+        match select {
+            0x08 => {
+                // Writing the seconds also resets the sub-second counter
+                regs.seconds = val & 0x3F;
+                self.cycles = 0;
+            },
+            0x09 => regs.minutes = val & 0x3F,
+            0x0A => regs.hours = val & 0x1F,
+            0x0B => regs.days_low = val,
+            0x0C => regs.days_high = val & (DH_DAY_UPPER | DH_HALT | DH_CARRY),
+            _ => unreachable!()
+        }
+    }
+}
+
+pub struct Mbc3 {
+    rom_banks: usize,
+    ram_banks: usize,
+    ram_enabled: bool,          // Enables both the RAM and the RTC registers
+    ram_data: Vec<u8>,
+    rom_bank: u8,               // 7 bit ROM bank number
+    ram_select: u8,             // 0x00-0x03 selects a RAM bank, 0x08-0x0C selects an RTC register
+    rtc: Rtc,
+    has_rtc: bool               // Battery saves only carry the clock if the cartridge has one
+}
+
+impl Mbc3 {
+    pub fn new(rom_banks: usize, ram_banks: usize, has_rtc: bool) -> Self {
+        Self {
+            rom_banks,
+            ram_banks,
+            ram_enabled: false,
+            ram_data: vec![0; ram_banks * 0x2000],
+            rom_bank: 1,
+            ram_select: 0,
+            rtc: Rtc::new(),
+            has_rtc
+        }
+    }
+
+    fn rom_index(&self, addr: u16) -> usize {
+        let bank = if self.rom_banks == 0 {
+            1
+        } else {
+            (self.rom_bank as usize) & (self.rom_banks - 1)
+        };
+
+        ((addr as usize) - 0x4000) + (bank * 0x4000)
+    }
+
+    fn ram_index(&self, addr: u16) -> usize {
+        let bank = if self.ram_banks == 0 {
+            0
+        } else {
+            (self.ram_select as usize) & (self.ram_banks - 1)
+        };
+
+        ((addr as usize) - 0xA000) + (bank * 0x2000)
+    }
+}
+
+impl Savestate for Mbc3 {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.ram_enabled)?;
+        out.write_u8(self.rom_bank)?;
+        out.write_u8(self.ram_select)?;
+        savestate::write_bytes(out, &self.ram_data)?;
+
+        out.write_all(&self.rtc.current.as_array())?;
+        out.write_all(&self.rtc.latched.as_array())?;
+        savestate::write_usize(out, self.rtc.cycles)?;
+        savestate::write_bool(out, self.rtc.latch_ready)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.ram_enabled = savestate::read_bool(input)?;
+        self.rom_bank = input.read_u8()?;
+        self.ram_select = input.read_u8()?;
+        savestate::read_bytes(input, &mut self.ram_data)?;
+
+        let mut regs = [0; 5];
+        input.read_exact(&mut regs)?;
+        self.rtc.current = RtcRegisters::from_slice(&regs);
+        input.read_exact(&mut regs)?;
+        self.rtc.latched = RtcRegisters::from_slice(&regs);
+        self.rtc.cycles = savestate::read_usize(input)?;
+        self.rtc.latch_ready = savestate::read_bool(input)?;
+
+        Ok(())
+    }
+}
+
+impl Mapper for Mbc3 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x3FFF => {
+                // ROM bank 0
+                rom[addr as usize]
+            },
+            0x4000..=0x7FFF => {
+                // Switchable ROM bank
+                rom[self.rom_index(addr)]
+            },
+            0xA000..=0xBFFF => {
+                if !self.ram_enabled {
+                    return 0xFF;
+                }
+
+                match self.ram_select {
+                    // Switchable RAM bank
+                    0x00..=0x03 => {
+                        let index = self.ram_index(addr);
+                        if index < self.ram_data.len() { self.ram_data[index] } else { 0xFF }
+                    },
+                    // Reading RTC registers returns the latched time
+                    0x08..=0x0C => self.rtc.latched.read(self.ram_select),
+                    _ => 0xFF
+                }
+            },
+            _ => {
+                warn!("Read from address {:#X} not handled by MBC3", addr);
+
+                0xFF
+            }
+        }
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            0x0000..=0x1FFF => {
+                // Writing to this space toggles RAM and RTC registers
+                self.ram_enabled = (val & 0x0F) == 0x0A;
+            },
+            0x2000..=0x3FFF => {
+                // Writing to this space switches ROM bank. Bank 0 is treated as 1.
+                self.rom_bank = match val & 0x7F {
+                    0 => 1,
+                    bank => bank
+                };
+            },
+            0x4000..=0x5FFF => {
+                // Writing to this space selects the RAM bank or RTC register
+                self.ram_select = val;
+            },
+            0x6000..=0x7FFF => {
+                // Writing 0 followed by 1 latches the clock
+                self.rtc.write_latch(val);
+            },
+            0xA000..=0xBFFF => {
+                if self.ram_enabled {
+                    match self.ram_select {
+                        0x00..=0x03 => {
+                            let index = self.ram_index(addr);
+                            if index >= self.ram_data.len() {
+                                warn!("Attempted to write to out-of-bounds MBC3 RAM index {}, addr {:X}.", index, addr);
+                            } else {
+                                self.ram_data[index] = val;
+                            }
+                        },
+                        0x08..=0x0C => self.rtc.write(self.ram_select, val),
+                        _ => warn!("Write to unknown MBC3 RAM/RTC selection {:#X}", self.ram_select)
+                    }
+                }
+            },
+            _ => warn!("Write to address {:#X} not handled by MBC3", addr)
+        }
+    }
+
+    fn step(&mut self, cycles: usize) {
+        self.rtc.step(cycles);
+    }
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_data
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_index(0x4000) / 0x4000
+    }
+
+    fn battery_footer(&self) -> Vec<u8> {
+        if !self.has_rtc {
+            return Vec::new();
+        }
+
+        let mut footer = vec![0; RTC_FOOTER_SIZE];
+
+        for (i, val) in self.rtc.current.as_array().iter().chain(self.rtc.latched.as_array().iter()).enumerate() {
+            LittleEndian::write_u32(&mut footer[i * 4..], *val as u32);
+        }
+
+        LittleEndian::write_u64(&mut footer[40..], unix_time());
+
+        footer
+    }
+
+    fn load_battery_footer(&mut self, data: &[u8]) {
+        if !self.has_rtc {
+            return;
+        }
+
+        // Some emulators write a 32 bit timestamp, making the footer 44 bytes
+        if data.len() != RTC_FOOTER_SIZE && data.len() != RTC_FOOTER_SIZE - 4 {
+            warn!("Ignoring MBC3 clock data of unexpected size {}", data.len());
+            return;
+        }
+
+        let mut regs = [0; 10];
+        for (i, reg) in regs.iter_mut().enumerate() {
+            *reg = LittleEndian::read_u32(&data[i * 4..]) as u8;
+        }
+
+        self.rtc.current = RtcRegisters::from_slice(&regs[0..5]);
+        self.rtc.latched = RtcRegisters::from_slice(&regs[5..10]);
+
+        let saved_time = if data.len() == RTC_FOOTER_SIZE {
+            LittleEndian::read_u64(&data[40..])
+        } else {
+            LittleEndian::read_u32(&data[40..]) as u64
+        };
+
+        // The clock kept running while the game was off
+        self.rtc.advance_seconds(unix_time().saturating_sub(saved_time));
+    }
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
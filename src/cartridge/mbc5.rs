@@ -1,5 +1,8 @@
-use super::Mapper;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use log::warn;
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use super::Mapper;
 
 // Bank selection uses 16 bits to store all bank state
 // Upper 4 bits store current RAM bank
@@ -58,8 +61,8 @@ pub struct Mbc5 {
 impl Mbc5 {
     pub fn new(rom_banks: usize, ram_banks: usize) -> Self {
         Self {
-            rom_banks: rom_banks,
-            ram_banks: ram_banks,
+            rom_banks,
+            ram_banks,
             ram_enabled: false,
             ram_data: vec![0; ram_banks * 0x2000],
             bank_selection: BankSelection::new()
@@ -87,8 +90,22 @@ impl Mbc5 {
     }
 }
 
+impl Savestate for Mbc5 {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.ram_enabled)?;
+        out.write_u16::<LittleEndian>(self.bank_selection.0)?;
+        savestate::write_bytes(out, &self.ram_data)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.ram_enabled = savestate::read_bool(input)?;
+        self.bank_selection = BankSelection(input.read_u16::<LittleEndian>()?);
+        savestate::read_bytes(input, &mut self.ram_data)
+    }
+}
+
 impl Mapper for Mbc5 {
-    fn read(&self, rom: &Vec<u8>, addr: u16) -> u8 {
+    fn read(&self, rom: &[u8], addr: u16) -> u8 {
         match addr {
             0x0000..=0x3FFF => {
                 // ROM bank 0
@@ -149,4 +166,16 @@ impl Mapper for Mbc5 {
              _ => warn!("Write to address {:#X} not handled by MBC5", addr)
         }
     }
-}
\ No newline at end of file
+
+    fn ram(&self) -> &[u8] {
+        &self.ram_data
+    }
+
+    fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram_data
+    }
+
+    fn rom_bank(&self) -> usize {
+        self.rom_index(0x4000) / 0x4000
+    }
+}
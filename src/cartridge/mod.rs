@@ -1,12 +1,22 @@
 mod mbc1;
+mod mbc2;
+mod mbc3;
 mod mbc5;
 
+#[cfg(test)]
+mod tests;
+
 use bus::Addressable;
+use log::warn;
+use savestate::Savestate;
 use self::mbc1::Mbc1;
+use self::mbc2::Mbc2;
+use self::mbc3::Mbc3;
 use self::mbc5::Mbc5;
 use std::fmt;
 use std::fs::File;
-use std::io::Read;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
 pub enum MapperType {
     Mbc1,
@@ -32,7 +42,8 @@ impl fmt::Display for MapperType {
 
 pub struct Cartridge {
     rom: Vec<u8>,
-    mapper: Option<Box<dyn Mapper>>
+    mapper: Option<Box<dyn Mapper>>,
+    ram_dirty: bool             // Cartridge RAM has been written to since the last battery save
 }
 
 impl Cartridge {
@@ -45,20 +56,17 @@ impl Cartridge {
     }
 
     pub fn from_vec(data: Vec<u8>) -> Self {
-        let mapper = if let Some(mapper_type) = mapper_type(data[0x147]) {
-            Some(create_mapper(mapper_type, total_rom_banks(data[0x148]), total_ram_banks(data[0x149])))
-        } else {
-            None
-        };
+        let mapper = mapper_type(data[0x147]).map(|mapper_type| create_mapper(mapper_type, total_rom_banks(data[0x148]), total_ram_banks(data[0x149]), has_rtc(data[0x147])));
 
         Self {
-            mapper: mapper,
-            rom: data
+            mapper,
+            rom: data,
+            ram_dirty: false
         }
     }
 
     pub fn name(&self) -> String {
-        String::from_utf8((&self.rom[0x0134..0x0143]).to_vec()).unwrap_or("UNKNOWN".to_string())
+        String::from_utf8(self.rom[0x0134..0x0143].to_vec()).unwrap_or("UNKNOWN".to_string())
     }
 
     pub fn gbc(&self) -> bool {
@@ -80,6 +88,101 @@ impl Cartridge {
     pub fn total_ram_banks(&self) -> usize {
         total_ram_banks(self.rom[0x149])
     }
+
+    // Cartridges with a battery keep their RAM contents when powered off
+    pub fn battery(&self) -> bool {
+        has_battery(self.rom[0x147])
+    }
+
+    // Contents of the cartridge RAM. Empty if the cartridge has none.
+    pub fn ram(&self) -> &[u8] {
+        match self.mapper {
+            Some(ref mapper) => mapper.ram(),
+            None => &[]
+        }
+    }
+
+    // Replaces the contents of the cartridge RAM.
+    // If the image is a different size than the RAM only the overlapping bytes are copied.
+    pub fn load_ram(&mut self, data: &[u8]) {
+        if let Some(ref mut mapper) = self.mapper {
+            let ram = mapper.ram_mut();
+
+            if ram.len() != data.len() {
+                warn!("RAM image is {} bytes but cartridge RAM is {} bytes.", data.len(), ram.len());
+            }
+
+            let len = ram.len().min(data.len());
+            ram[..len].copy_from_slice(&data[..len]);
+        }
+
+        self.ram_dirty = false;
+    }
+
+    pub fn ram_dirty(&self) -> bool {
+        self.ram_dirty
+    }
+
+    pub fn step(&mut self, cycles: usize) {
+        if let Some(ref mut mapper) = self.mapper {
+            mapper.step(cycles);
+        }
+    }
+
+    // Loads the battery save file. A missing file is not an error, the game just starts with empty RAM.
+    // Anything past the RAM image belongs to the mapper (e.g. the MBC3 clock).
+    pub fn load_battery_file(&mut self, path: &Path) -> io::Result<()> {
+        if !self.battery() || !path.is_file() {
+            return Ok(());
+        }
+
+        let mut data = Vec::new();
+        File::open(path)?.read_to_end(&mut data)?;
+
+        let ram_len = self.ram().len();
+
+        if data.len() > ram_len {
+            let (ram, footer) = data.split_at(ram_len);
+            self.load_ram(ram);
+
+            if let Some(ref mut mapper) = self.mapper {
+                mapper.load_battery_footer(footer);
+            }
+        } else {
+            self.load_ram(&data);
+        }
+
+        Ok(())
+    }
+
+    // Writes the cartridge RAM to the battery save file
+    pub fn save_battery_file(&mut self, path: &Path) -> io::Result<()> {
+        let footer = match self.mapper {
+            Some(ref mapper) => mapper.battery_footer(),
+            None => Vec::new()
+        };
+
+        if !self.battery() || (self.ram().is_empty() && footer.is_empty()) {
+            return Ok(());
+        }
+
+        let mut file = File::create(path)?;
+        file.write_all(self.ram())?;
+        file.write_all(&footer)?;
+        self.ram_dirty = false;
+
+        Ok(())
+    }
+
+    // Global checksum stored in the header. Used to make sure save states belong to this ROM.
+    pub fn checksum(&self) -> u16 {
+        ((self.rom[0x014E] as u16) << 8) | (self.rom[0x014F] as u16)
+    }
+
+    // ROM bank currently mapped to 0x4000-0x7FFF. Cartridges without a mapper always have bank 1 there.
+    pub fn rom_bank(&self) -> usize {
+        self.mapper.as_ref().map_or(1, |mapper| mapper.rom_bank())
+    }
 }
 
 impl fmt::Display for Cartridge {
@@ -92,14 +195,15 @@ impl fmt::Debug for Cartridge {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let mapper = self.mapper_type();
 
-        write!(f, "Name: {}\nMapper: {} ({:#X})\nROM Banks: {} ({:#X})\nRAM Banks: {} ({:#X})\nGBC: {}\nSGB: {}",
+        write!(f, "Name: {}\nMapper: {} ({:#X})\nROM Banks: {} ({:#X})\nRAM Banks: {} ({:#X})\nBattery: {}\nGBC: {}\nSGB: {}",
             self.name(),
-            if mapper.is_some() { mapper.unwrap().to_string() } else { "None".to_string() },
+            mapper.map_or("None".to_string(), |mapper| mapper.to_string()),
             self.rom[0x147],
             self.total_rom_banks(),
             self.rom[0x148],
-            self.rom[0x149],
             self.total_ram_banks(),
+            self.rom[0x149],
+            if self.battery() { "Yes" } else { "No" },
             if self.gbc() { "Yes" } else { "No" },
             if self.sgb() { "Yes" } else { "No" })
     }
@@ -107,25 +211,73 @@ impl fmt::Debug for Cartridge {
 
 impl Addressable for Cartridge {
     fn read(&self, addr: u16) -> u8 {
-        if self.mapper.is_some() {
-            let mapper = self.mapper.as_ref().unwrap();
+        if let Some(ref mapper) = self.mapper {
             mapper.read(&self.rom, addr)
         } else {
-            self.rom[addr as usize]
+            // Without a mapper there's no cartridge RAM, reads past the ROM see open bus
+            self.rom.get(addr as usize).cloned().unwrap_or(0xFF)
         }
     }
 
     fn write(&mut self, addr: u16, val: u8) {
-        if self.mapper.is_some() {
-            let mapper = self.mapper.as_mut().unwrap();
-            mapper.write(addr, val)
+        if let Some(ref mut mapper) = self.mapper {
+            mapper.write(addr, val);
+
+            if addr >= 0xA000 {
+                self.ram_dirty = true;
+            }
+        }
+    }
+}
+
+// Only the mapper holds state. The ROM itself never changes.
+impl Savestate for Cartridge {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        match self.mapper {
+            Some(ref mapper) => mapper.save_state(out),
+            None => Ok(())
+        }
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        if let Some(ref mut mapper) = self.mapper {
+            mapper.load_state(input)?;
+        }
+        // The loaded RAM no longer matches the battery save
+        if self.battery() {
+            self.ram_dirty = true;
         }
+        Ok(())
     }
 }
 
-trait Mapper {
-    fn read(&self, rom: &Vec<u8>, addr: u16) -> u8;
+trait Mapper: Savestate {
+    fn read(&self, rom: &[u8], addr: u16) -> u8;
     fn write(&mut self, addr: u16, val: u8);
+
+    // Advances hardware on the cartridge, such as the MBC3 clock
+    fn step(&mut self, _cycles: usize) {
+    }
+
+    // Cartridge RAM image. This is what gets persisted for battery backed cartridges.
+    fn ram(&self) -> &[u8];
+    fn ram_mut(&mut self) -> &mut [u8];
+
+    // ROM bank currently mapped to 0x4000-0x7FFF
+    fn rom_bank(&self) -> usize;
+
+    // Extra data stored after the RAM image in battery saves
+    fn battery_footer(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn load_battery_footer(&mut self, _data: &[u8]) {
+    }
+}
+
+// Path of the battery save file for a ROM. It sits next to the ROM with a .sav extension.
+pub fn battery_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sav")
 }
 
 fn mapper_type(code: u8) -> Option<MapperType> {
@@ -154,6 +306,15 @@ fn mapper_type(code: u8) -> Option<MapperType> {
     }
 }
 
+fn has_battery(code: u8) -> bool {
+    matches!(code, 0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0xFF)
+}
+
+// Only MBC3+TIMER cartridges have a clock
+fn has_rtc(code: u8) -> bool {
+    matches!(code, 0x0F | 0x10)
+}
+
 fn total_rom_banks(code: u8) -> usize {
     match code {
         0x00 => 0,
@@ -184,9 +345,11 @@ fn total_ram_banks(code: u8) -> usize {
     }
 }
 
-fn create_mapper(mapper_type: MapperType, rom_banks: usize, ram_banks: usize) -> Box<dyn Mapper> {
+fn create_mapper(mapper_type: MapperType, rom_banks: usize, ram_banks: usize, rtc: bool) -> Box<dyn Mapper> {
     match mapper_type {
         MapperType::Mbc1 => Box::new(Mbc1::new(rom_banks, ram_banks)),
+        MapperType::Mbc2 => Box::new(Mbc2::new(rom_banks)),
+        MapperType::Mbc3 => Box::new(Mbc3::new(rom_banks, ram_banks, rtc)),
         MapperType::Mbc5 => Box::new(Mbc5::new(rom_banks, ram_banks)),
 
         _ => panic!("Mapper {} not implemented.", mapper_type)
@@ -0,0 +1,77 @@
+use bus::Addressable;
+use cartridge::Cartridge;
+use savestate::Savestate;
+use super::rom;
+
+#[test]
+fn battery_cartridge_types() {
+    assert!(Cartridge::from_vec(rom(0x03, 0, 2)).battery());
+    assert!(Cartridge::from_vec(rom(0x1B, 0, 2)).battery());
+    assert!(Cartridge::from_vec(rom(0x1E, 0, 2)).battery());
+    assert!(!Cartridge::from_vec(rom(0x01, 0, 0)).battery());
+    assert!(!Cartridge::from_vec(rom(0x1A, 0, 2)).battery());
+}
+
+#[test]
+fn ram_writes_mark_dirty() {
+    let mut cartridge = Cartridge::from_vec(rom(0x03, 0, 2));
+
+    assert!(!cartridge.ram_dirty());
+
+    // Enable RAM and write to it
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0xA010, 0x5A);
+
+    assert!(cartridge.ram_dirty());
+    assert_eq!(cartridge.ram().len(), 0x2000);
+    assert_eq!(cartridge.ram()[0x10], 0x5A);
+}
+
+#[test]
+fn load_ram_image() {
+    let mut cartridge = Cartridge::from_vec(rom(0x1B, 0, 3));
+    let mut image = vec![0; 0x8000];
+    image[0x1234] = 0x99;
+
+    cartridge.load_ram(&image);
+
+    assert!(!cartridge.ram_dirty());
+    assert_eq!(cartridge.ram()[0x1234], 0x99);
+
+    // Switch to RAM bank 0 and read it back through the bus
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0x4000, 0x00);
+
+    assert_eq!(cartridge.read(0xB234), 0x99);
+}
+
+#[test]
+fn load_short_ram_image() {
+    let mut cartridge = Cartridge::from_vec(rom(0x03, 0, 2));
+
+    cartridge.load_ram(&[1, 2, 3]);
+
+    assert_eq!(&cartridge.ram()[..4], &[1, 2, 3, 0]);
+}
+
+#[test]
+fn loading_state_marks_battery_ram_dirty() {
+    let mut cartridge = Cartridge::from_vec(rom(0x03, 0, 2));
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0xA010, 0x5A);
+
+    let mut state = Vec::new();
+    cartridge.save_state(&mut state).unwrap();
+
+    let mut loaded = Cartridge::from_vec(rom(0x03, 0, 2));
+    assert!(!loaded.ram_dirty());
+    loaded.load_state(&mut &state[..]).unwrap();
+    assert!(loaded.ram_dirty());
+    assert_eq!(loaded.ram()[0x10], 0x5A);
+
+    let mut cartridge = Cartridge::from_vec(rom(0x01, 0, 0));
+    let mut state = Vec::new();
+    cartridge.save_state(&mut state).unwrap();
+    cartridge.load_state(&mut &state[..]).unwrap();
+    assert!(!cartridge.ram_dirty());
+}
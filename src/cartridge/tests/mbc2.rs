@@ -0,0 +1,65 @@
+use bus::Addressable;
+use cartridge::Cartridge;
+use super::rom;
+
+#[test]
+fn rom_bank_switching() {
+    let mut data = rom(0x05, 3, 0);
+
+    // Mark the first byte of every bank with its number
+    for bank in 0..16 {
+        data[bank * 0x4000] = bank as u8;
+    }
+
+    let mut cartridge = Cartridge::from_vec(data);
+
+    assert_eq!(cartridge.read(0x4000), 1);
+
+    // A8 set selects the ROM bank register
+    cartridge.write(0x2100, 0x0F);
+    assert_eq!(cartridge.read(0x4000), 0x0F);
+
+    // Only 4 bits are used and bank 0 selects bank 1
+    cartridge.write(0x0100, 0x30);
+    assert_eq!(cartridge.read(0x4000), 1);
+
+    // A8 clear doesn't touch the ROM bank
+    cartridge.write(0x2000, 0x03);
+    assert_eq!(cartridge.read(0x4000), 1);
+}
+
+#[test]
+fn ram_is_half_bytes() {
+    let mut cartridge = Cartridge::from_vec(rom(0x06, 0, 0));
+
+    // RAM is disabled at startup
+    cartridge.write(0xA000, 0x05);
+    assert_eq!(cartridge.read(0xA000), 0xFF);
+
+    // A8 clear selects RAM enable
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0xA000, 0xAB);
+
+    assert_eq!(cartridge.read(0xA000), 0xFB);
+    assert_eq!(cartridge.ram().len(), 512);
+}
+
+#[test]
+fn ram_echoes() {
+    let mut cartridge = Cartridge::from_vec(rom(0x06, 0, 0));
+
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0xA1FF, 0x07);
+
+    assert_eq!(cartridge.read(0xA3FF), 0xF7);
+    assert_eq!(cartridge.read(0xBFFF), 0xF7);
+
+    cartridge.write(0xB000, 0x02);
+    assert_eq!(cartridge.read(0xA000), 0xF2);
+}
+
+#[test]
+fn battery() {
+    assert!(Cartridge::from_vec(rom(0x06, 0, 0)).battery());
+    assert!(!Cartridge::from_vec(rom(0x05, 0, 0)).battery());
+}
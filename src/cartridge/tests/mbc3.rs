@@ -0,0 +1,159 @@
+use bus::Addressable;
+use cartridge::Cartridge;
+use std::env;
+use std::fs;
+use super::rom;
+
+const CYCLES_PER_SECOND: usize = 4_194_304;
+
+fn latch(cartridge: &mut Cartridge) {
+    cartridge.write(0x6000, 0);
+    cartridge.write(0x6000, 1);
+}
+
+#[test]
+fn rom_bank_switching() {
+    let mut data = rom(0x13, 6, 3);
+
+    // Mark the first byte of every bank with its number
+    for bank in 0..128 {
+        data[bank * 0x4000] = bank as u8;
+    }
+
+    let mut cartridge = Cartridge::from_vec(data);
+
+    assert_eq!(cartridge.read(0x4000), 1);
+
+    cartridge.write(0x2000, 0x7F);
+    assert_eq!(cartridge.read(0x4000), 0x7F);
+
+    // Bank 0 selects bank 1
+    cartridge.write(0x2000, 0);
+    assert_eq!(cartridge.read(0x4000), 1);
+}
+
+#[test]
+fn ram_bank_switching() {
+    let mut cartridge = Cartridge::from_vec(rom(0x13, 0, 3));
+
+    cartridge.write(0x0000, 0x0A);
+
+    for bank in 0..4 {
+        cartridge.write(0x4000, bank);
+        cartridge.write(0xA000, bank + 0x10);
+    }
+
+    for bank in 0..4 {
+        cartridge.write(0x4000, bank);
+        assert_eq!(cartridge.read(0xA000), bank + 0x10);
+    }
+
+    // Disabled RAM reads as 0xFF
+    cartridge.write(0x0000, 0x00);
+    assert_eq!(cartridge.read(0xA000), 0xFF);
+}
+
+#[test]
+fn rtc_latches_time() {
+    let mut cartridge = Cartridge::from_vec(rom(0x10, 0, 3));
+
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0x4000, 0x08);
+
+    cartridge.step(CYCLES_PER_SECOND * 3);
+
+    // Nothing is visible until the clock is latched
+    assert_eq!(cartridge.read(0xA000), 0);
+
+    latch(&mut cartridge);
+    assert_eq!(cartridge.read(0xA000), 3);
+
+    // Latched value doesn't change while the clock runs
+    cartridge.step(CYCLES_PER_SECOND);
+    assert_eq!(cartridge.read(0xA000), 3);
+
+    latch(&mut cartridge);
+    assert_eq!(cartridge.read(0xA000), 4);
+}
+
+#[test]
+fn rtc_rolls_over() {
+    let mut cartridge = Cartridge::from_vec(rom(0x10, 0, 3));
+
+    cartridge.write(0x0000, 0x0A);
+
+    // Set the clock to day 511, 23:59:59
+    for &(register, val) in &[(0x08, 59), (0x09, 59), (0x0A, 23), (0x0B, 0xFF), (0x0C, 0x01)] {
+        cartridge.write(0x4000, register);
+        cartridge.write(0xA000, val);
+    }
+
+    cartridge.step(CYCLES_PER_SECOND);
+    latch(&mut cartridge);
+
+    for &(register, val) in &[(0x08, 0), (0x09, 0), (0x0A, 0), (0x0B, 0), (0x0C, 0x80)] {
+        cartridge.write(0x4000, register);
+        assert_eq!(cartridge.read(0xA000), val);
+    }
+}
+
+#[test]
+fn rtc_halt() {
+    let mut cartridge = Cartridge::from_vec(rom(0x10, 0, 3));
+
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0x4000, 0x0C);
+    cartridge.write(0xA000, 0x40);
+
+    cartridge.step(CYCLES_PER_SECOND * 10);
+    latch(&mut cartridge);
+
+    cartridge.write(0x4000, 0x08);
+    assert_eq!(cartridge.read(0xA000), 0);
+}
+
+#[test]
+fn battery_file_includes_clock() {
+    let path = env::temp_dir().join(format!("rustboy-mbc3-{}.sav", ::std::process::id()));
+
+    let mut cartridge = Cartridge::from_vec(rom(0x10, 0, 3));
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0x4000, 0x00);
+    cartridge.write(0xA000, 0x77);
+    cartridge.write(0x4000, 0x09);
+    cartridge.write(0xA000, 42);
+    cartridge.save_battery_file(&path).unwrap();
+
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0x8000 + 48);
+
+    let mut restored = Cartridge::from_vec(rom(0x10, 0, 3));
+    restored.load_battery_file(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    restored.write(0x0000, 0x0A);
+    latch(&mut restored);
+
+    restored.write(0x4000, 0x00);
+    assert_eq!(restored.read(0xA000), 0x77);
+    restored.write(0x4000, 0x09);
+    assert_eq!(restored.read(0xA000), 42);
+}
+
+#[test]
+fn battery_file_without_clock() {
+    let path = env::temp_dir().join(format!("rustboy-mbc3-ram-{}.sav", ::std::process::id()));
+
+    let mut cartridge = Cartridge::from_vec(rom(0x13, 0, 3));
+    cartridge.write(0x0000, 0x0A);
+    cartridge.write(0xA000, 0x77);
+    cartridge.save_battery_file(&path).unwrap();
+
+    assert_eq!(fs::metadata(&path).unwrap().len(), 0x8000);
+
+    let mut restored = Cartridge::from_vec(rom(0x13, 0, 3));
+    restored.load_battery_file(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    restored.write(0x0000, 0x0A);
+    assert_eq!(restored.read(0xA000), 0x77);
+}
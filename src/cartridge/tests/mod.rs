@@ -0,0 +1,14 @@
+mod battery;
+mod mbc2;
+mod mbc3;
+
+// Builds a blank ROM image with the given header cartridge type, ROM size and RAM size codes
+pub fn rom(cart_type: u8, rom_size: u8, ram_size: u8) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000 << rom_size];
+
+    rom[0x147] = cart_type;
+    rom[0x148] = rom_size;
+    rom[0x149] = ram_size;
+
+    rom
+}
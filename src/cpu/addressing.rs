@@ -1,22 +1,21 @@
-use super::Cpu;
-use bus::{Addressable, Bus};
+use super::{Cpu, Memory};
 use super::registers::Register;
 use std::fmt;
 
 pub trait AddressingMode<T> : fmt::Display {
-    fn read(&self, cpu: &Cpu, bus: &Bus) -> T;
+    fn read(&self, cpu: &Cpu, bus: &mut dyn Memory) -> T;
 
-    fn write(&self, cpu: &mut Cpu, bus: &mut Bus, val: T);
+    fn write(&self, cpu: &mut Cpu, bus: &mut dyn Memory, val: T);
 }
 
 pub struct ImmediateAddressing<T>(pub T);
 
 impl<T> AddressingMode<T> for ImmediateAddressing<T> where T : Copy + fmt::UpperHex {
-    fn read(&self, _: &Cpu, _: &Bus) -> T {
+    fn read(&self, _: &Cpu, _: &mut dyn Memory) -> T {
         self.0
     }
 
-    fn write(&self, _: &mut Cpu, _: &mut Bus, _: T) {
+    fn write(&self, _: &mut Cpu, _: &mut dyn Memory, _: T) {
         panic!("Write not supported for immediate addressing.");
     }
 }
@@ -30,39 +29,39 @@ impl<T> fmt::Display for ImmediateAddressing<T> where T : fmt::UpperHex {
 pub struct IndirectAddressing<T>(pub T);
 
 impl AddressingMode<u8> for IndirectAddressing<u8> {
-    fn read(&self, _: &Cpu, bus: &Bus) -> u8 {
-        bus.read(0xFF00 + self.0 as u16)
+    fn read(&self, _: &Cpu, bus: &mut dyn Memory) -> u8 {
+        bus.read_cycle(0xFF00 + self.0 as u16)
     }
 
-    fn write(&self, _: &mut Cpu, bus: &mut Bus, val: u8) {
-        bus.write(0xFF00 + self.0 as u16, val);
+    fn write(&self, _: &mut Cpu, bus: &mut dyn Memory, val: u8) {
+        bus.write_cycle(0xFF00 + self.0 as u16, val);
     }
 }
 
 impl AddressingMode<u8> for IndirectAddressing<u16> {
-    fn read(&self, _: &Cpu, bus: &Bus) -> u8 {
-        bus.read(self.0)
+    fn read(&self, _: &Cpu, bus: &mut dyn Memory) -> u8 {
+        bus.read_cycle(self.0)
     }
 
-    fn write(&self, _: &mut Cpu, bus: &mut Bus, val: u8) {
-        bus.write(self.0, val);
+    fn write(&self, _: &mut Cpu, bus: &mut dyn Memory, val: u8) {
+        bus.write_cycle(self.0, val);
     }
 }
 
 impl AddressingMode<u16> for IndirectAddressing<u16> {
-    fn read(&self, _: &Cpu, bus: &Bus) -> u16 {
-        let low = bus.read(self.0) as u16;
-        let high = bus.read(self.0 + 1) as u16;
+    fn read(&self, _: &Cpu, bus: &mut dyn Memory) -> u16 {
+        let low = bus.read_cycle(self.0) as u16;
+        let high = bus.read_cycle(self.0 + 1) as u16;
 
         (high << 8) | low
     }
 
-    fn write(&self, _: &mut Cpu, bus: &mut Bus, val: u16) {
+    fn write(&self, _: &mut Cpu, bus: &mut dyn Memory, val: u16) {
         let low = (val & 0xFF) as u8;
         let high = ((val & 0xFF00) >> 8) as u8;
 
-        bus.write(self.0, low);
-        bus.write(self.0 + 1, high);
+        bus.write_cycle(self.0, low);
+        bus.write_cycle(self.0 + 1, high);
     }
 }
 
@@ -75,7 +74,7 @@ impl<T> fmt::Display for IndirectAddressing<T> where T : fmt::UpperHex {
 pub struct RegisterAddressing(pub Register);
 
 impl AddressingMode<u8> for RegisterAddressing {
-    fn read(&self, cpu: &Cpu, _: &Bus) -> u8 {
+    fn read(&self, cpu: &Cpu, _: &mut dyn Memory) -> u8 {
         match self.0 {
             Register::A => cpu.regs.a(),
             Register::B => cpu.regs.b(),
@@ -94,7 +93,7 @@ impl AddressingMode<u8> for RegisterAddressing {
         }
     }
 
-    fn write(&self, cpu: &mut Cpu, _: &mut Bus, val: u8) {
+    fn write(&self, cpu: &mut Cpu, _: &mut dyn Memory, val: u8) {
         match self.0 {
             Register::A => cpu.regs.set_a(val),
             Register::B => cpu.regs.set_b(val),
@@ -115,7 +114,7 @@ impl AddressingMode<u8> for RegisterAddressing {
 }
 
 impl AddressingMode<u16> for RegisterAddressing {
-    fn read(&self, cpu: &Cpu, _: &Bus) -> u16 {
+    fn read(&self, cpu: &Cpu, _: &mut dyn Memory) -> u16 {
         match self.0 {
             Register::A
             | Register::B
@@ -134,7 +133,7 @@ impl AddressingMode<u16> for RegisterAddressing {
         }
     }
 
-    fn write(&self, cpu: &mut Cpu, _: &mut Bus, val: u16) {
+    fn write(&self, cpu: &mut Cpu, _: &mut dyn Memory, val: u16) {
         match self.0 {
             Register::A
             | Register::B
@@ -163,14 +162,14 @@ impl fmt::Display for RegisterAddressing {
 pub struct RegisterIndirectAddressing(pub Register);
 
 impl AddressingMode<u8> for RegisterIndirectAddressing {
-    fn read(&self, cpu: &Cpu, bus: &Bus) -> u8 {
+    fn read(&self, cpu: &Cpu, bus: &mut dyn Memory) -> u8 {
         let addr = register_indirect_addr(cpu, &self.0);
-        bus.read(addr)
+        bus.read_cycle(addr)
     }
 
-    fn write(&self, cpu: &mut Cpu, bus: &mut Bus, val: u8) {
+    fn write(&self, cpu: &mut Cpu, bus: &mut dyn Memory, val: u8) {
         let addr = register_indirect_addr(cpu, &self.0);
-        bus.write(addr, val)
+        bus.write_cycle(addr, val)
     }
 }
 
@@ -1,9 +1,8 @@
-use bus::Bus;
-use super::super::{AddressingMode, Condition, Cpu};
+use super::super::{AddressingMode, Condition, Cpu, Memory};
 
 // JR
 #[inline(always)]
-pub fn jr(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMode<u8>) -> bool {
+pub fn jr(cpu: &mut Cpu, bus: &mut dyn Memory, cond: Condition, src: &dyn AddressingMode<u8>) -> bool {
     if cpu.condition_met(cond) {
         let pc = cpu.regs.pc();
         let offset = src.read(cpu, bus) as i8;
@@ -11,7 +10,7 @@ pub fn jr(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMod
         if offset > 0 {
             cpu.regs.set_pc(pc.wrapping_add(offset as u16));
         } else {
-            cpu.regs.set_pc(pc.wrapping_sub(offset.abs() as u16));
+            cpu.regs.set_pc(pc.wrapping_sub(offset.unsigned_abs() as u16));
         }
         true
     } else {
@@ -21,7 +20,7 @@ pub fn jr(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMod
 
 // JP
 #[inline(always)]
-pub fn jp(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMode<u16>) -> bool {
+pub fn jp(cpu: &mut Cpu, bus: &mut dyn Memory, cond: Condition, src: &dyn AddressingMode<u16>) -> bool {
     if cpu.condition_met(cond) {
         let addr = src.read(cpu, bus);
         cpu.regs.set_pc(addr);
@@ -33,7 +32,7 @@ pub fn jp(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMod
 
 // CALL
 #[inline(always)]
-pub fn call(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingMode<u16>) -> bool {
+pub fn call(cpu: &mut Cpu, bus: &mut dyn Memory, cond: Condition, src: &dyn AddressingMode<u16>) -> bool {
     if cpu.condition_met(cond) {
         let addr = src.read(cpu, bus);
         let pc = cpu.regs.pc();
@@ -48,7 +47,12 @@ pub fn call(cpu: &mut Cpu, bus: &mut Bus, cond: Condition, src: &dyn AddressingM
 
 // RET
 #[inline(always)]
-pub fn ret(cpu: &mut Cpu, bus: &mut Bus, cond: Condition) -> bool {
+pub fn ret(cpu: &mut Cpu, bus: &mut dyn Memory, cond: Condition) -> bool {
+    // Conditional returns spend a cycle checking the flags before popping
+    if cond != Condition::None {
+        bus.idle_cycle();
+    }
+
     if cpu.condition_met(cond) {
         let addr = cpu.pop_stack(bus);
 
@@ -61,7 +65,7 @@ pub fn ret(cpu: &mut Cpu, bus: &mut Bus, cond: Condition) -> bool {
 
 // RETI
 #[inline(always)]
-pub fn reti(cpu: &mut Cpu, bus: &mut Bus) {
+pub fn reti(cpu: &mut Cpu, bus: &mut dyn Memory) {
     let addr = cpu.pop_stack(bus);
     cpu.regs.set_pc(addr);
     cpu.ime = true;
@@ -69,7 +73,7 @@ pub fn reti(cpu: &mut Cpu, bus: &mut Bus) {
 
 // RST
 #[inline(always)]
-pub fn rst(cpu: &mut Cpu, bus: &mut Bus, index: u8) {
+pub fn rst(cpu: &mut Cpu, bus: &mut dyn Memory, index: u8) {
     let pc = cpu.regs.pc();
     cpu.push_stack(bus, pc);
     cpu.regs.set_pc((index * 8) as u16);
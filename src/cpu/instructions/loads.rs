@@ -1,9 +1,8 @@
-use bus::Bus;
-use super::super::{AddressingMode, Cpu};
+use super::super::{AddressingMode, Cpu, Memory};
 
 // LD
 #[inline(always)]
-pub fn ld<T>(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<T>, src: &dyn AddressingMode<T>) {
+pub fn ld<T>(cpu: &mut Cpu, bus: &mut dyn Memory, dest: &dyn AddressingMode<T>, src: &dyn AddressingMode<T>) {
     let val = src.read(cpu, bus);
     dest.write(cpu, bus, val);
 }
@@ -11,15 +10,15 @@ pub fn ld<T>(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<T>, src: &d
 // LDHL SP, r8
 // Affects flags: Z, N, H, C
 #[inline(always)]
-pub fn ldhl(cpu: &mut Cpu, bus: &mut Bus, src: &dyn AddressingMode<u8>) {
+pub fn ldhl(cpu: &mut Cpu, bus: &mut dyn Memory, src: &dyn AddressingMode<u8>) {
     let sp = cpu.regs.sp();
     let unsigned = src.read(cpu, bus) as u16;
     let signed = src.read(cpu, bus) as i8;
 
     if signed < 0 {
-        cpu.regs.set_hl(sp.wrapping_sub(signed.abs() as u16));
+        cpu.regs.set_hl(sp.wrapping_sub(signed.unsigned_abs() as u16));
     } else {
-        cpu.regs.set_hl(sp.wrapping_add(signed.abs() as u16));
+        cpu.regs.set_hl(sp.wrapping_add(signed.unsigned_abs() as u16));
     }
 
     cpu.regs.set_carry(((sp & 0xFF) + (unsigned & 0xFF)) & 0x100 == 0x100);
@@ -30,7 +29,7 @@ pub fn ldhl(cpu: &mut Cpu, bus: &mut Bus, src: &dyn AddressingMode<u8>) {
 
 // LDD
 #[inline(always)]
-pub fn ldd(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<u8>, src: &dyn AddressingMode<u8>) {
+pub fn ldd(cpu: &mut Cpu, bus: &mut dyn Memory, dest: &dyn AddressingMode<u8>, src: &dyn AddressingMode<u8>) {
     let val = src.read(cpu, bus);
     let hl = cpu.regs.hl();
 
@@ -40,7 +39,7 @@ pub fn ldd(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<u8>, src: &dy
 
 // LDI
 #[inline(always)]
-pub fn ldi(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<u8>, src: &dyn AddressingMode<u8>) {
+pub fn ldi(cpu: &mut Cpu, bus: &mut dyn Memory, dest: &dyn AddressingMode<u8>, src: &dyn AddressingMode<u8>) {
     let val = src.read(cpu, bus);
     let hl = cpu.regs.hl();
 
@@ -50,14 +49,14 @@ pub fn ldi(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<u8>, src: &dy
 
 // PUSH
 #[inline(always)]
-pub fn push(cpu: &mut Cpu, bus: &mut Bus, src: &dyn AddressingMode<u16>) {
+pub fn push(cpu: &mut Cpu, bus: &mut dyn Memory, src: &dyn AddressingMode<u16>) {
     let val = src.read(cpu, bus);
     cpu.push_stack(bus, val);
 }
 
 // POP
 #[inline(always)]
-pub fn pop(cpu: &mut Cpu, bus: &mut Bus, dest: &dyn AddressingMode<u16>) {
+pub fn pop(cpu: &mut Cpu, bus: &mut dyn Memory, dest: &dyn AddressingMode<u16>) {
     let val = cpu.pop_stack(bus);
     dest.write(cpu, bus, val);
 }
\ No newline at end of file
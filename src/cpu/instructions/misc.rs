@@ -1,4 +1,4 @@
-use super::super::Cpu;
+use super::super::{Cpu, Lockup, Memory};
 
  // NOP
 #[inline(always)]
@@ -8,22 +8,39 @@ pub fn nop() {
 
 // STOP 0
 #[inline(always)]
-pub fn stop(cpu: &mut Cpu) {
-    cpu.halted = true;
+pub fn stop(cpu: &mut Cpu, bus: &mut dyn Memory) {
+    bus.stop();
+
+    // With a button already held down STOP doesn't wait
+    if !bus.button_pressed() {
+        cpu.stopped = true;
+    }
 }
 
 // HALT
+// With interrupts disabled and one already pending the CPU doesn't halt, and triggers the HALT bug instead
 #[inline(always)]
-pub fn halt(cpu: &mut Cpu) {
-    cpu.halted = true;
+pub fn halt(cpu: &mut Cpu, bus: &mut dyn Memory) {
+    if !cpu.ime && bus.pending_interrupts() != 0 {
+        cpu.halt_bug = true;
+    } else {
+        cpu.halted = true;
+    }
 }
 
 // DI
 pub fn di(cpu: &mut Cpu) {
     cpu.ime = false;
+    cpu.ime_pending = false;
 }
 
 // EI
 pub fn ei(cpu: &mut Cpu) {
-    cpu.ime = true;
-}
\ No newline at end of file
+    cpu.ime_pending = true;
+}
+
+// Illegal opcodes hang the CPU until the Gameboy is switched off
+pub fn illegal(cpu: &mut Cpu, opcode: u8) {
+    let addr = cpu.regs.pc().wrapping_sub(1);
+    cpu.lockup = Some(Lockup { addr, opcode });
+}
@@ -13,9 +13,8 @@ pub use self::loads::*;
 pub use self::math::*;
 pub use self::misc::*;
 
-use super::{Condition, Cpu};
+use super::{Condition, Cpu, Memory};
 use super::addressing::*;
-use bus::Bus;
 use std::fmt;
 use super::registers::Register;
 
@@ -71,7 +70,8 @@ pub enum Instruction {
     Srl(Box<dyn AddressingMode<u8>>),
     Bit(u8, Box<dyn AddressingMode<u8>>),
     Res(u8, Box<dyn AddressingMode<u8>>),
-    Set(u8, Box<dyn AddressingMode<u8>>)
+    Set(u8, Box<dyn AddressingMode<u8>>),
+    Illegal(u8)                 // Opcode the CPU doesn't implement. Executing it locks up the CPU.
 }
 
 impl fmt::Display for Instruction {
@@ -114,6 +114,7 @@ impl fmt::Display for Instruction {
             Or(ref reg) => write!(f, "OR {}", reg),
             Cp(ref reg) => write!(f, "CP {}", reg),
             Halt => write!(f, "HALT"),
+            Illegal(opcode) => write!(f, "ILLEGAL {:#04X}", opcode),
             Ret(ref cond) => {
                 if *cond == Condition::None {
                     write!(f, "RET")
@@ -213,7 +214,7 @@ fn reg_pair_table(idx: u8) -> Register {
     }
 }
 
-pub fn decode(cpu: &mut Cpu, bus: &Bus, opcode: u8, prefixed: bool) -> Instruction {
+pub fn decode(cpu: &mut Cpu, bus: &mut dyn Memory, opcode: u8, prefixed: bool) -> Instruction {
     use self::Register::*;
     use self::Instruction::*;
 
@@ -223,107 +224,105 @@ pub fn decode(cpu: &mut Cpu, bus: &Bus, opcode: u8, prefixed: bool) -> Instructi
     let p = y >> 1;
     let q = (0b0000_1000 & opcode) >> 3;
 
-    let instruction = 
-        if !prefixed {
-            match (x, y, z, q, p) {
-                // X=0, Z=0
-                (0, 0, 0, _, _) => Nop,
-                (0, 1, 0, _, _) => Ld16(ind_addr!(cpu.step_next_word(bus)), reg_addr!(SP)),
-                (0, 2, 0, _, _) => { cpu.step_next_word(bus); Stop },
-                (0, 3, 0, _, _) => Jr(Condition::None, imm_addr!(cpu.step_next_byte(bus))),
-                (0, 4..=7, 0, _, _) => Jr(cond_table(y-4), imm_addr!(cpu.step_next_byte(bus))),
-                // X=0, Z=1
-                (0, _, 1, 0, _) => Ld16(reg_addr!(reg_pair_table(p)), imm_addr!(cpu.step_next_word(bus))),
-                (0, _, 1, 1, _) => Add16(reg_addr!(HL), reg_addr!(reg_pair_table(p))),
-                // X=0, Z=2
-                (0, _, 2, 0, 0) => Ld8(regind_addr!(BC), reg_addr!(A)),
-                (0, _, 2, 0, 1) => Ld8(regind_addr!(DE), reg_addr!(A)),
-                (0, _, 2, 0, 2) => Ldi(regind_addr!(HL), reg_addr!(A)),
-                (0, _, 2, 0, 3) => Ldd(regind_addr!(HL), reg_addr!(A)),
-                (0, _, 2, 1, 0) => Ld8(reg_addr!(A), regind_addr!(BC)),
-                (0, _, 2, 1, 1) => Ld8(reg_addr!(A), regind_addr!(DE)),
-                (0, _, 2, 1, 2) => Ldi(reg_addr!(A), regind_addr!(HL)),
-                (0, _, 2, 1, 3) => Ldd(reg_addr!(A), regind_addr!(HL)),
-                // X=0, Z=3
-                (0, _, 3, 0, _) => Inc16(reg_addr!(reg_pair_table(p))),
-                (0, _, 3, 1, _) => Dec16(reg_addr!(reg_pair_table(p))),
-                // X=0, Z=4
-                (0, _, 4, _, _) => Inc8(reg_addr_table(y)),
-                // X=0, Z=5
-                (0, _, 5, _, _) => Dec8(reg_addr_table(y)),
-                // X=0, Z=6
-                (0, _, 6, _, _) => Ld8(reg_addr_table(y), imm_addr!(cpu.step_next_byte(bus))),
-                // X=0, Z=7
-                (0, 0, 7, _, _) => Rlca,
-                (0, 1, 7, _, _) => Rrca,
-                (0, 2, 7, _, _) => Rla,
-                (0, 3, 7, _, _) => Rra,
-                (0, 4, 7, _, _) => Daa,
-                (0, 5, 7, _, _) => Cpl,
-                (0, 6, 7, _, _) => Scf,
-                (0, 7, 7, _, _) => Ccf,
-                // X=1
-                (1, _, _, _, _) if !(z == 6 && y == 6) => Ld8(reg_addr_table(y), reg_addr_table(z)),
-                (1, _, _, _, _) if z == 6 && y == 6 => Halt,
-                // X=2
-                (2, _, _, _, _) => decode_alu(y, reg_addr_table(z)),
-                // X=3, Z=0
-                (3, 0..=3, 0, _, _) => Ret(cond_table(y)),
-                (3, 4, 0, _, _) => Ldh(ind_addr!(cpu.step_next_byte(bus)), reg_addr!(A)),
-                (3, 5, 0, _, _) => AddSp(imm_addr!(cpu.step_next_byte(bus))),
-                (3, 6, 0, _, _) => Ldh(reg_addr!(A), ind_addr!(cpu.step_next_byte(bus))),
-                (3, 7, 0, _, _) => Ldhl(imm_addr!(cpu.step_next_byte(bus))),
-                // X=3, Z=1
-                (3, _, 1, 0, _) => Pop(reg_addr!(reg_pair_table(p + 4))),
-                (3, _, 1, 1, 0) => Ret(Condition::None),
-                (3, _, 1, 1, 1) => Reti,
-                (3, _, 1, 1, 2) => Jp(Condition::None, reg_addr!(HL)),
-                (3, _, 1, 1, 3) => Ld16(reg_addr!(SP), reg_addr!(HL)),
-                // X=3, Z=2
-                (3, 0..=3, 2, _, _) => Jp(cond_table(y), imm_addr!(cpu.step_next_word(bus))),
-                (3, 4, 2, _, _) => Ld8(regind_addr!(C), reg_addr!(A)),
-                (3, 5, 2, _, _) => Ld8(ind_addr!(cpu.step_next_word(bus)), reg_addr!(A)),
-                (3, 6, 2, _, _) => Ld8(reg_addr!(A), regind_addr!(C)),
-                (3, 7, 2, _, _) => Ld8(reg_addr!(A), ind_addr!(cpu.step_next_word(bus))),
-                // X=3, Z=3
-                (3, 0, 3, _, _) => Jp(Condition::None, imm_addr!(cpu.step_next_word(bus))),
-                (3, 6, 3, _, _) => Di,
-                (3, 7, 3, _, _) => Ei,
-                // X=3, Z=4
-                (3, 0..=3, 4, _, _) => Call(cond_table(y), imm_addr!(cpu.step_next_word(bus))),
-                // X=3, Z=5
-                (3, _, 5, 0, _) => Push(reg_addr!(reg_pair_table(p + 4))),
-                (3, _, 5, 1, 0) => Call(Condition::None, imm_addr!(cpu.step_next_word(bus))),
-                // X=3, Z=6
-                (3, _, 6, _, _) => decode_alu(y, imm_addr!(cpu.step_next_byte(bus))),
-                // X=3, Z=7
-                (3, _, 7, _, _) => Rst(y),
-                _ => unreachable!()
-            }
-        } else {
-            let register = reg_addr_table(z);
-            match x {
-                0 => {
-                    match y {
-                        0 => Rlc(register),
-                        1 => Rrc(register),
-                        2 => Rl(register),
-                        3 => Rr(register),
-                        4 => Sla(register),
-                        5 => Sra(register),
-                        6 => Swap(register),
-                        7 => Srl(register),
-                        _ => unreachable!()
-                    }
-                },
-                1 => Bit(y, register),
-                2 => Res(y, register),
-                3 => Set(y, register),
-                _ => unreachable!()
-            }
-        };
-
-    instruction
+    if !prefixed {
+        match (x, y, z, q, p) {
+            // X=0, Z=0
+            (0, 0, 0, _, _) => Nop,
+            (0, 1, 0, _, _) => Ld16(ind_addr!(cpu.step_next_word(bus)), reg_addr!(SP)),
+            (0, 2, 0, _, _) => { cpu.step_next_byte(bus); Stop },
+            (0, 3, 0, _, _) => Jr(Condition::None, imm_addr!(cpu.step_next_byte(bus))),
+            (0, 4..=7, 0, _, _) => Jr(cond_table(y-4), imm_addr!(cpu.step_next_byte(bus))),
+            // X=0, Z=1
+            (0, _, 1, 0, _) => Ld16(reg_addr!(reg_pair_table(p)), imm_addr!(cpu.step_next_word(bus))),
+            (0, _, 1, 1, _) => Add16(reg_addr!(HL), reg_addr!(reg_pair_table(p))),
+            // X=0, Z=2
+            (0, _, 2, 0, 0) => Ld8(regind_addr!(BC), reg_addr!(A)),
+            (0, _, 2, 0, 1) => Ld8(regind_addr!(DE), reg_addr!(A)),
+            (0, _, 2, 0, 2) => Ldi(regind_addr!(HL), reg_addr!(A)),
+            (0, _, 2, 0, 3) => Ldd(regind_addr!(HL), reg_addr!(A)),
+            (0, _, 2, 1, 0) => Ld8(reg_addr!(A), regind_addr!(BC)),
+            (0, _, 2, 1, 1) => Ld8(reg_addr!(A), regind_addr!(DE)),
+            (0, _, 2, 1, 2) => Ldi(reg_addr!(A), regind_addr!(HL)),
+            (0, _, 2, 1, 3) => Ldd(reg_addr!(A), regind_addr!(HL)),
+            // X=0, Z=3
+            (0, _, 3, 0, _) => Inc16(reg_addr!(reg_pair_table(p))),
+            (0, _, 3, 1, _) => Dec16(reg_addr!(reg_pair_table(p))),
+            // X=0, Z=4
+            (0, _, 4, _, _) => Inc8(reg_addr_table(y)),
+            // X=0, Z=5
+            (0, _, 5, _, _) => Dec8(reg_addr_table(y)),
+            // X=0, Z=6
+            (0, _, 6, _, _) => Ld8(reg_addr_table(y), imm_addr!(cpu.step_next_byte(bus))),
+            // X=0, Z=7
+            (0, 0, 7, _, _) => Rlca,
+            (0, 1, 7, _, _) => Rrca,
+            (0, 2, 7, _, _) => Rla,
+            (0, 3, 7, _, _) => Rra,
+            (0, 4, 7, _, _) => Daa,
+            (0, 5, 7, _, _) => Cpl,
+            (0, 6, 7, _, _) => Scf,
+            (0, 7, 7, _, _) => Ccf,
+            // X=1
+            (1, _, _, _, _) if !(z == 6 && y == 6) => Ld8(reg_addr_table(y), reg_addr_table(z)),
+            (1, _, _, _, _) if z == 6 && y == 6 => Halt,
+            // X=2
+            (2, _, _, _, _) => decode_alu(y, reg_addr_table(z)),
+            // X=3, Z=0
+            (3, 0..=3, 0, _, _) => Ret(cond_table(y)),
+            (3, 4, 0, _, _) => Ldh(ind_addr!(cpu.step_next_byte(bus)), reg_addr!(A)),
+            (3, 5, 0, _, _) => AddSp(imm_addr!(cpu.step_next_byte(bus))),
+            (3, 6, 0, _, _) => Ldh(reg_addr!(A), ind_addr!(cpu.step_next_byte(bus))),
+            (3, 7, 0, _, _) => Ldhl(imm_addr!(cpu.step_next_byte(bus))),
+            // X=3, Z=1
+            (3, _, 1, 0, _) => Pop(reg_addr!(reg_pair_table(p + 4))),
+            (3, _, 1, 1, 0) => Ret(Condition::None),
+            (3, _, 1, 1, 1) => Reti,
+            (3, _, 1, 1, 2) => Jp(Condition::None, reg_addr!(HL)),
+            (3, _, 1, 1, 3) => Ld16(reg_addr!(SP), reg_addr!(HL)),
+            // X=3, Z=2
+            (3, 0..=3, 2, _, _) => Jp(cond_table(y), imm_addr!(cpu.step_next_word(bus))),
+            (3, 4, 2, _, _) => Ld8(regind_addr!(C), reg_addr!(A)),
+            (3, 5, 2, _, _) => Ld8(ind_addr!(cpu.step_next_word(bus)), reg_addr!(A)),
+            (3, 6, 2, _, _) => Ld8(reg_addr!(A), regind_addr!(C)),
+            (3, 7, 2, _, _) => Ld8(reg_addr!(A), ind_addr!(cpu.step_next_word(bus))),
+            // X=3, Z=3
+            (3, 0, 3, _, _) => Jp(Condition::None, imm_addr!(cpu.step_next_word(bus))),
+            (3, 6, 3, _, _) => Di,
+            (3, 7, 3, _, _) => Ei,
+            // X=3, Z=4
+            (3, 0..=3, 4, _, _) => Call(cond_table(y), imm_addr!(cpu.step_next_word(bus))),
+            // X=3, Z=5
+            (3, _, 5, 0, _) => Push(reg_addr!(reg_pair_table(p + 4))),
+            (3, _, 5, 1, 0) => Call(Condition::None, imm_addr!(cpu.step_next_word(bus))),
+            // X=3, Z=6
+            (3, _, 6, _, _) => decode_alu(y, imm_addr!(cpu.step_next_byte(bus))),
+            // X=3, Z=7
+            (3, _, 7, _, _) => Rst(y),
+            // 0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD
+            _ => Illegal(opcode)
+        }
+    } else {
+        let register = reg_addr_table(z);
+        match x {
+            0 => {
+                match y {
+                    0 => Rlc(register),
+                    1 => Rrc(register),
+                    2 => Rl(register),
+                    3 => Rr(register),
+                    4 => Sla(register),
+                    5 => Sra(register),
+                    6 => Swap(register),
+                    7 => Srl(register),
+                    _ => unreachable!()
+                }
+            },
+            1 => Bit(y, register),
+            2 => Res(y, register),
+            3 => Set(y, register),
+            _ => unreachable!()
+        }
+    }
 }
 
 fn decode_alu(y: u8, src: Box<dyn AddressingMode<u8>>) -> Instruction {
@@ -342,13 +341,13 @@ fn decode_alu(y: u8, src: Box<dyn AddressingMode<u8>>) -> Instruction {
     }
 }
 
-pub fn execute(cpu: &mut Cpu, bus: &mut Bus, instruction: &Instruction) -> bool {
+pub fn execute(cpu: &mut Cpu, bus: &mut dyn Memory, instruction: &Instruction) -> bool {
     use self::Instruction::*;
     match instruction {
         Nop                 => { nop(); true },
         Ld8(dest, src)      => { ld(cpu, bus, dest.as_ref(), src.as_ref()); true },
         Ld16(dest, src)     => { ld(cpu, bus, dest.as_ref(), src.as_ref()); true },
-        Stop                => { stop(cpu); true },
+        Stop                => { stop(cpu, bus); true },
         Jr(cond, addr)      => { jr(cpu, bus, *cond, addr.as_ref()) },
         Add16(dest, src)    => { add_16(cpu, bus, dest.as_ref(), src.as_ref()); true },
         Inc8(reg)           => { inc_8(cpu, bus, reg.as_ref()); true },
@@ -373,7 +372,7 @@ pub fn execute(cpu: &mut Cpu, bus: &mut Bus, instruction: &Instruction) -> bool
         Xor(reg)            => { xor(cpu, bus, reg.as_ref()); true },
         Or(reg)             => { or(cpu, bus, reg.as_ref()); true },
         Cp(reg)             => { cp(cpu, bus, reg.as_ref()); true },
-        Halt                => { halt(cpu); true },
+        Halt                => { halt(cpu, bus); true },
         Ret(cond)           => { ret(cpu, bus, *cond) },
         AddSp(reg)          => { add_sp(cpu, bus, reg.as_ref()); true },
         Ldh(dest, src)      => { ld(cpu, bus, dest.as_ref(), src.as_ref()); true },
@@ -396,6 +395,7 @@ pub fn execute(cpu: &mut Cpu, bus: &mut Bus, instruction: &Instruction) -> bool
         Srl(reg)            => { srl(cpu, bus, reg.as_ref()); true },
         Bit(b, reg)         => { bit(cpu, bus, *b, reg.as_ref()); true },
         Res(bit, reg)       => { res(cpu, bus, *bit, reg.as_ref()); true },
-        Set(bit, reg)       => { set(cpu, bus, *bit, reg.as_ref()); true }
+        Set(bit, reg)       => { set(cpu, bus, *bit, reg.as_ref()); true },
+        Illegal(opcode)     => { illegal(cpu, *opcode); true }
     }
 }
\ No newline at end of file
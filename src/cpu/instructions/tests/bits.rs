@@ -163,12 +163,12 @@ fn rlc_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b10000000);
 
-    rlc(&mut cpu, &mut bus, &mut val);
+    rlc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 1);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -179,11 +179,11 @@ fn rlc_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b00000001);
 
-    rlc(&mut cpu, &mut bus, &mut val);
+    rlc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b00000010);
     assert_eq!(cpu.regs.f(), 0);
@@ -194,11 +194,11 @@ fn rlc_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0);
 
-    rlc(&mut cpu, &mut bus, &mut val);
+    rlc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -211,12 +211,12 @@ fn rrc_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b00000001);
 
-    rrc(&mut cpu, &mut bus, &mut val);
+    rrc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b10000000);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -227,11 +227,11 @@ fn rrc_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b00000010);
 
-    rrc(&mut cpu, &mut bus, &mut val);
+    rrc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 1);
     assert_eq!(cpu.regs.f(), 0);
@@ -242,11 +242,11 @@ fn rrc_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0);
 
-    rrc(&mut cpu, &mut bus, &mut val);
+    rrc(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -259,12 +259,12 @@ fn rl_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b10000000);
 
-    rl(&mut cpu, &mut bus, &mut val);
+    rl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 1);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -275,11 +275,11 @@ fn rl_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b00000001);
 
-    rl(&mut cpu, &mut bus, &mut val);
+    rl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b00000011);
     assert_eq!(cpu.regs.f(), 0);
@@ -290,11 +290,11 @@ fn rl_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1110 << 4);
     cpu.regs.set_a(0);
 
-    rl(&mut cpu, &mut bus, &mut val);
+    rl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -307,12 +307,12 @@ fn rr_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(1);
 
-    rr(&mut cpu, &mut bus, &mut val);
+    rr(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b10000000);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -323,11 +323,11 @@ fn rr_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b00000010);
 
-    rr(&mut cpu, &mut bus, &mut val);
+    rr(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b10000001);
     assert_eq!(cpu.regs.f(), 0);
@@ -338,11 +338,11 @@ fn rr_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1110 << 4);
     cpu.regs.set_a(0);
 
-    rr(&mut cpu, &mut bus, &mut val);
+    rr(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -355,12 +355,12 @@ fn sla_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b11000000);
 
-    sla(&mut cpu, &mut bus, &mut val);
+    sla(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b10000000);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -371,11 +371,11 @@ fn sla_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1111 << 4);
     cpu.regs.set_a(0b01000000);
 
-    sla(&mut cpu, &mut bus, &mut val);
+    sla(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b10000000);
     assert_eq!(cpu.regs.f(), 0);
@@ -386,11 +386,11 @@ fn sla_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0b1110 << 4);
     cpu.regs.set_a(0b10000000);
 
-    sla(&mut cpu, &mut bus, &mut val);
+    sla(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1001 << 4);
@@ -403,12 +403,12 @@ fn sra_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0);
     cpu.regs.set_a(0b10000001);
 
-    sra(&mut cpu, &mut bus, &mut val);
+    sra(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b11000000);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -419,11 +419,11 @@ fn sra_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(0b10000000);
 
-    sra(&mut cpu, &mut bus, &mut val);
+    sra(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b11000000);
     assert_eq!(cpu.regs.f(), 0);
@@ -434,11 +434,11 @@ fn sra_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(0);
 
-    sra(&mut cpu, &mut bus, &mut val);
+    sra(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -451,11 +451,11 @@ fn swap_not_zero() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(0xF0);
 
-    swap(&mut cpu, &mut bus, &mut val);
+    swap(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0x0F);
     assert_eq!(cpu.regs.f(), 0);
@@ -466,11 +466,11 @@ fn swap_zero() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(0);
 
-    swap(&mut cpu, &mut bus, &mut val);
+    swap(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1000 << 4);
@@ -483,12 +483,12 @@ fn srl_does_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
 
     cpu.regs.set_f(0);
     cpu.regs.set_a(0b00000011);
 
-    srl(&mut cpu, &mut bus, &mut val);
+    srl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b00000001);
     assert_eq!(cpu.regs.f(), 0b0001 << 4);
@@ -499,11 +499,11 @@ fn srl_doesnt_carry() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(0b00000010);
 
-    srl(&mut cpu, &mut bus, &mut val);
+    srl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0b00000001);
     assert_eq!(cpu.regs.f(), 0);
@@ -514,11 +514,11 @@ fn srl_zero_result() {
     let mut cpu = Cpu::new();
     let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
     let mut bus = Bus::new(&mut cartridge);
-    let mut val = RegisterAddressing(Register::A);
+    let val = RegisterAddressing(Register::A);
     cpu.regs.set_f(0);
     cpu.regs.set_a(1);
 
-    srl(&mut cpu, &mut bus, &mut val);
+    srl(&mut cpu, &mut bus, &val);
 
     assert_eq!(cpu.regs.a(), 0);
     assert_eq!(cpu.regs.f(), 0b1001 << 4);
@@ -64,7 +64,7 @@ fn call_addr() {
     call(&mut cpu, &mut bus, Condition::None, &addr);
 
     assert_eq!(cpu.regs.pc(), 0xFFFF);
-    assert_eq!(cpu.pop_stack(&bus), 0xFF);
+    assert_eq!(cpu.pop_stack(&mut bus), 0xFF);
 }
 
 // RET tests
@@ -99,7 +99,7 @@ fn reti_after_call() {
     reti(&mut cpu, &mut bus);
 
     assert_eq!(cpu.regs.pc(), 0xFF);
-    assert_eq!(cpu.ime, true);
+    assert!(cpu.ime);
 }
 
 // RST tests
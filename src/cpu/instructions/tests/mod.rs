@@ -1,4 +1,5 @@
 mod bits;
 mod jumps;
 mod loads;
-mod math;
\ No newline at end of file
+mod math;
+mod timing;
\ No newline at end of file
@@ -0,0 +1,247 @@
+use cpu::{Cpu, Interrupt, Memory};
+
+// Flat 64KB of memory that logs the clock cycle every access finished on
+struct TimedMemory {
+    data: Vec<u8>,
+    cycles: usize,
+    accesses: Vec<(usize, u16, bool)>,     // Cycle, address and whether it was a write
+    interrupts: u8,                        // Requested and enabled
+    button: bool
+}
+
+impl TimedMemory {
+    fn new(code: &[u8]) -> Self {
+        let mut data = vec![0; 0x10000];
+        data[0x100..0x100 + code.len()].copy_from_slice(code);
+
+        Self { data, cycles: 0, accesses: Vec::new(), interrupts: 0, button: false }
+    }
+
+    fn writes(&self) -> Vec<(usize, u16)> {
+        self.accesses.iter().filter(|access| access.2).map(|&(cycle, addr, _)| (cycle, addr)).collect()
+    }
+}
+
+impl Memory for TimedMemory {
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        self.cycles += 4;
+        self.accesses.push((self.cycles, addr, false));
+        self.data[addr as usize]
+    }
+
+    fn write_cycle(&mut self, addr: u16, val: u8) {
+        // Writing IE can disable requested interrupts
+        if addr == 0xFFFF {
+            self.interrupts &= val;
+        }
+
+        self.cycles += 4;
+        self.accesses.push((self.cycles, addr, true));
+        self.data[addr as usize] = val;
+    }
+
+    fn idle_cycle(&mut self) {
+        self.cycles += 4;
+    }
+
+    fn stopped_cycle(&mut self) {
+        self.cycles += 4;
+    }
+
+    fn cycles(&self) -> usize {
+        self.cycles
+    }
+
+    fn pending_interrupts(&self) -> u8 {
+        self.interrupts
+    }
+
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt) {
+        self.interrupts &= !(interrupt as u8);
+    }
+
+    fn stop(&mut self) { }
+
+    fn button_pressed(&self) -> bool {
+        self.button
+    }
+}
+
+fn cpu() -> Cpu {
+    let mut cpu = Cpu::new();
+    cpu.reset();
+    cpu
+}
+
+#[test]
+fn write_happens_on_last_cycle() {
+    // LD (0xC000),A
+    let mut memory = TimedMemory::new(&[0xEA, 0x00, 0xC0]);
+
+    assert_eq!(cpu().step(&mut memory), 16);
+    assert_eq!(memory.accesses, [(4, 0x100, false), (8, 0x101, false), (12, 0x102, false), (16, 0xC000, true)]);
+}
+
+#[test]
+fn call_pushes_high_byte_first() {
+    // CALL 0x1234
+    let mut memory = TimedMemory::new(&[0xCD, 0x34, 0x12]);
+
+    assert_eq!(cpu().step(&mut memory), 24);
+    assert_eq!(memory.writes(), [(20, 0xFFFD), (24, 0xFFFC)]);
+    assert_eq!(&memory.data[0xFFFC..0xFFFE], [0x03, 0x01]);
+}
+
+#[test]
+fn internal_cycles_are_padded() {
+    // INC BC; RET NZ (taken); RET Z (not taken)
+    let mut memory = TimedMemory::new(&[0x03, 0xC0]);
+    let mut cpu = cpu();
+
+    assert_eq!(cpu.step(&mut memory), 8);
+
+    cpu.regs.set_f(0);
+    cpu.regs.set_sp(0xC000);
+    memory.data[0xC000..0xC002].copy_from_slice(&[0x03, 0x01]);
+    memory.data[0x103] = 0xC8;
+    memory.accesses.clear();
+
+    // The flags are checked on an internal cycle before SP is read
+    assert_eq!(cpu.step(&mut memory), 20);
+    assert_eq!(memory.accesses.iter().map(|access| access.0 - 8).collect::<Vec<_>>(), [4, 12, 16]);
+
+    cpu.regs.set_f(0);
+    assert_eq!(cpu.step(&mut memory), 8);
+}
+
+#[test]
+fn interrupt_dispatch_takes_five_cycles() {
+    let mut memory = TimedMemory::new(&[]);
+    let mut cpu = cpu();
+
+    cpu.ime = true;
+    memory.interrupts = Interrupt::Timer as u8;
+
+    assert_eq!(cpu.step(&mut memory), 20);
+    assert_eq!(memory.writes(), [(12, 0xFFFD), (16, 0xFFFC)]);
+    assert_eq!(memory.interrupts, 0);
+    assert_eq!(cpu.regs.pc(), 0x50);
+}
+
+#[test]
+fn ei_takes_effect_after_next_instruction() {
+    // EI; NOP; NOP
+    let mut memory = TimedMemory::new(&[0xFB, 0x00, 0x00]);
+    let mut cpu = cpu();
+
+    memory.interrupts = Interrupt::VBlank as u8;
+
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.regs.pc(), 0x102);
+
+    cpu.step(&mut memory);
+    assert_eq!(cpu.regs.pc(), 0x40);
+    assert_eq!(&memory.data[0xFFFC..0xFFFE], [0x02, 0x01]);
+}
+
+#[test]
+fn di_cancels_pending_ei() {
+    // EI; DI; NOP
+    let mut memory = TimedMemory::new(&[0xFB, 0xF3, 0x00]);
+    let mut cpu = cpu();
+
+    memory.interrupts = Interrupt::VBlank as u8;
+
+    for _ in 0..3 {
+        cpu.step(&mut memory);
+    }
+
+    assert_eq!(cpu.regs.pc(), 0x103);
+}
+
+#[test]
+fn halt_bug_reads_next_byte_twice() {
+    // HALT; INC A
+    let mut memory = TimedMemory::new(&[0x76, 0x3C]);
+    let mut cpu = cpu();
+
+    memory.interrupts = Interrupt::Timer as u8;
+    cpu.regs.set_a(0);
+
+    for _ in 0..3 {
+        cpu.step(&mut memory);
+    }
+
+    assert!(!cpu.halted());
+    assert_eq!(cpu.regs.a(), 2);
+    assert_eq!(cpu.regs.pc(), 0x102);
+}
+
+#[test]
+fn halt_without_interrupts_waits() {
+    // HALT; NOP
+    let mut memory = TimedMemory::new(&[0x76, 0x00]);
+    let mut cpu = cpu();
+
+    cpu.step(&mut memory);
+    assert_eq!(cpu.step(&mut memory), 4);
+    assert!(cpu.halted());
+
+    // With IME clear an interrupt wakes the CPU without being dispatched
+    memory.interrupts = Interrupt::Timer as u8;
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+
+    assert_eq!(cpu.regs.pc(), 0x102);
+    assert_eq!(memory.interrupts, Interrupt::Timer as u8);
+}
+
+#[test]
+fn dispatch_cancelled_when_push_overwrites_ie() {
+    let mut memory = TimedMemory::new(&[]);
+    let mut cpu = cpu();
+
+    cpu.ime = true;
+    cpu.regs.set_sp(0x0000);
+    cpu.regs.set_pc(0x0200);
+    memory.interrupts = Interrupt::Timer as u8;
+
+    assert_eq!(cpu.step(&mut memory), 20);
+    assert_eq!(cpu.regs.pc(), 0x0000);
+    assert_eq!(cpu.regs.sp(), 0xFFFE);
+}
+
+#[test]
+fn stop_waits_for_a_button() {
+    // STOP 0; NOP
+    let mut memory = TimedMemory::new(&[0x10, 0x00, 0x00]);
+    let mut cpu = cpu();
+
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.regs.pc(), 0x102);
+
+    memory.button = true;
+    cpu.step(&mut memory);
+    cpu.step(&mut memory);
+    assert_eq!(cpu.regs.pc(), 0x103);
+}
+
+#[test]
+fn illegal_opcode_locks_up() {
+    // NOP; 0xD3
+    let mut memory = TimedMemory::new(&[0x00, 0xD3, 0x00]);
+    let mut cpu = cpu();
+
+    cpu.ime = true;
+    cpu.step(&mut memory);
+    assert_eq!(cpu.step(&mut memory), 4);
+    assert_eq!(cpu.lockup().unwrap().to_string(), "CPU locked at $0101 on opcode $D3");
+
+    // Interrupts don't get it going again, but time keeps passing
+    memory.interrupts = Interrupt::VBlank as u8;
+    assert_eq!(cpu.step(&mut memory), 4);
+    assert_eq!(cpu.regs.pc(), 0x102);
+    assert_eq!(memory.interrupts, Interrupt::VBlank as u8);
+}
@@ -3,14 +3,16 @@ mod instructions;
 mod registers;
 
 use self::addressing::*;
-use bus::{Addressable, Bus, IO_IE_ADDR, IO_IF_ADDR};
-use byteorder::{ByteOrder, LittleEndian};
+use byteorder::{ByteOrder, LittleEndian, ReadBytesExt, WriteBytesExt};
 use enum_primitive::FromPrimitive;
 use self::instructions as inst;
 use self::registers::*;
+use savestate::{self, Savestate};
 use std::fmt;
+use std::io::{self, Read, Write};
 
 pub use self::instructions::{decode, Instruction};
+pub use self::registers::Registers;
 
 #[derive(Copy, Clone, PartialEq)]
 pub enum Condition {
@@ -50,6 +52,19 @@ enum_from_primitive! {
     }
 }
 
+// Where the CPU locked up after running into an illegal opcode
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Lockup {
+    pub addr: u16,
+    pub opcode: u8
+}
+
+impl fmt::Display for Lockup {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "CPU locked at ${:04X} on opcode ${:02X}", self.addr, self.opcode)
+    }
+}
+
 fn interrupt_start_address(interrupt: Interrupt) -> u16 {
     match interrupt {
         Interrupt::Joypad => 0x60,
@@ -62,7 +77,7 @@ fn interrupt_start_address(interrupt: Interrupt) -> u16 {
 
 // Number of clock cycles (not machine cycles) used by each instruction. 
 // First number is cycles used if instruction is conditional and condition is met. Secodn number if cycles used if condition is not met.
-static CYCLES: &'static [(usize, usize)] = &[
+static CYCLES: &[(usize, usize)] = &[
     (4, 4), (12, 12), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4), (20, 20), (8, 8), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4),
     (4, 4), (12, 12), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4), (12, 12), (8, 8), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4),
     (12, 8), (12, 12), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4), (12, 8), (8, 8), (8, 8), (8, 8), (4, 4), (4, 4), (8, 8), (4, 4),
@@ -81,7 +96,7 @@ static CYCLES: &'static [(usize, usize)] = &[
     (12, 12), (12, 12), (8, 8), (4, 4), (0, 0), (16, 16), (8, 8), (16, 16), (12, 12), (8, 8), (16, 16), (4, 4), (0, 0), (0, 0), (8, 8), (16, 16)
 ];
 
-static PREFIXED_CYCLES: &'static [(usize, usize)] = &[
+static PREFIXED_CYCLES: &[(usize, usize)] = &[
     (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8),
     (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8),
     (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8),
@@ -100,21 +115,56 @@ static PREFIXED_CYCLES: &'static [(usize, usize)] = &[
     (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (8, 8), (16, 16), (8, 8)
 ];
 
-const DISPATCH_CYCLES: usize = 20; 
-const CLEAR_HALT_CYCLES: usize = 4;
+// The CPU's view of memory. Every access takes one machine cycle (4 clock cycles) during which the rest of
+// the hardware keeps running, so timers and the LCD see reads and writes at the moment they happen.
+pub trait Memory {
+    fn read_cycle(&mut self, addr: u16) -> u8;
+    fn write_cycle(&mut self, addr: u16, val: u8);
+
+    // Machine cycle spent on internal work, without touching memory
+    fn idle_cycle(&mut self);
+
+    // Machine cycle spent in STOP. The main clock is stopped, so only the joypad is checked for a wake-up.
+    fn stopped_cycle(&mut self);
+
+    // Clock cycles that have passed so far
+    fn cycles(&self) -> usize;
+
+    // Interrupts that are both requested (IF) and enabled (IE)
+    fn pending_interrupts(&self) -> u8;
+    fn acknowledge_interrupt(&mut self, interrupt: Interrupt);
+
+    // STOP resets DIV, and the CPU stays stopped until a selected joypad line goes low
+    fn stop(&mut self);
+    fn button_pressed(&self) -> bool;
+}
 
 pub struct Cpu {
     pub regs: Registers,
     ime: bool,
-    halted: bool
+    ime_pending: bool,          // EI was just executed. IME is set once the next instruction starts.
+    halted: bool,
+    halt_bug: bool,             // HALT was skipped with an interrupt pending, so the next opcode is read twice
+    stopped: bool,
+    lockup: Option<Lockup>
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
     pub fn new() -> Self {
         Self {
             regs: Registers::default(),
-            ime: true,
-            halted: false
+            ime: false,
+            ime_pending: false,
+            halted: false,
+            halt_bug: false,
+            stopped: false,
+            lockup: None
         }
     }
 
@@ -125,34 +175,68 @@ impl Cpu {
         self.regs.set_hl(0x014D);
         self.regs.set_sp(0xFFFE);
         self.regs.set_pc(0x100);
-        self.ime = true;
+        self.ime = false;
+        self.ime_pending = false;
+        self.halted = false;
+        self.halt_bug = false;
+        self.stopped = false;
+        self.lockup = None;
+    }
+
+    // Waiting for an interrupt after HALT
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    // Set once the CPU has executed an illegal opcode
+    pub fn lockup(&self) -> Option<Lockup> {
+        self.lockup
     }
 
-    pub fn step(&mut self, bus: &mut Bus) -> usize {
-        let mut used_cycles = 0;
+    // The next step fetches and executes an instruction, rather than dispatching an interrupt or waiting
+    pub fn executes_next(&self, bus: &dyn Memory) -> bool {
+        let dispatches = self.ime && self.pending_interrupt(bus).is_some();
+
+        self.lockup.is_none() && !self.stopped && !self.halted && !dispatches
+    }
+
+    // Runs the next instruction, or an interrupt dispatch or a halted cycle, advancing the hardware as it goes.
+    // Returns the number of clock cycles used.
+    pub fn step(&mut self, bus: &mut dyn Memory) -> usize {
+        let start = bus.cycles();
         let interrupt = self.pending_interrupt(bus);
 
+        // A locked up CPU never fetches again, not even for interrupts. The rest of the hardware keeps running.
+        if self.lockup.is_some() {
+            bus.idle_cycle();
+        // STOP ends when a button is pressed. The timer, LCD and APU don't run until then.
+        } else if self.stopped {
+            bus.stopped_cycle();
+            self.stopped = !bus.button_pressed();
         // If an interrupt is pending and interrupts are enabled, jump to interrupt
-        if interrupt.is_some() && self.ime {
-            self.handle_interrupt(bus, interrupt.unwrap());
-            
-            // It takes 20 cycles to dispatch interrupt, 24 if HALTed.
-            used_cycles += DISPATCH_CYCLES;
-
+        } else if interrupt.is_some() && self.ime {
+            // Leaving HALT takes an extra cycle
             if self.halted {
-                // Clear HALT.
                 self.halted = false;
-                used_cycles += CLEAR_HALT_CYCLES;   
+                bus.idle_cycle();
             }
+
+            self.handle_interrupt(bus);
         // An an interrupt is pending, interrupts are disabled, and the CPU is halted, then unhalt the CPU
-        } else if interrupt.is_some() & !self.ime && self.halted {
+        } else if interrupt.is_some() && !self.ime && self.halted {
             self.halted = false;
-            used_cycles += CLEAR_HALT_CYCLES;
+            bus.idle_cycle();
         // CPU is halted and there's no interrupt
         } else if self.halted {
-            used_cycles += 4;
+            bus.idle_cycle();
         // Else execute the next instruction
         } else {
+            // EI takes effect after the instruction following it, so an interrupt can't be taken in between
+            if self.ime_pending {
+                self.ime_pending = false;
+                self.ime = true;
+            }
+
             let decoded = self.decode_next_instruction(bus);
             let condition_met = inst::execute(self, bus, &decoded.instruction);
 
@@ -163,18 +247,18 @@ impl Cpu {
                     CYCLES[decoded.opcode as usize]
                 };
 
-            used_cycles = 
-                if condition_met { 
-                    cond_met_cycles
-                } else { 
-                    cond_not_met_cycles
-                }
+            let expected_cycles = if condition_met { cond_met_cycles } else { cond_not_met_cycles };
+
+            // Memory accesses have already been timed. Whatever is left over is internal work done at the end.
+            while bus.cycles() - start < expected_cycles {
+                bus.idle_cycle();
+            }
         };
 
-        used_cycles
+        bus.cycles() - start
     }
 
-    fn decode_next_instruction(&mut self, bus: &Bus) -> DecodedInstruction {
+    fn decode_next_instruction(&mut self, bus: &mut dyn Memory) -> DecodedInstruction {
         let mut opcode = self.step_next_byte(bus);
         let mut prefixed = false;
 
@@ -184,48 +268,49 @@ impl Cpu {
         }
 
         DecodedInstruction {
-            opcode: opcode,
-            prefixed: prefixed,
+            opcode,
+            prefixed,
             instruction: inst::decode(self, bus, opcode, prefixed)
         }        
     }
 
-    pub fn step_next_byte(&mut self, bus: &Bus) -> u8 {
+    pub fn step_next_byte(&mut self, bus: &mut dyn Memory) -> u8 {
         let pc = self.regs.pc();
-        let byte = bus.read(pc);
-        self.regs.set_pc(pc.wrapping_add(1));
+        let byte = bus.read_cycle(pc);
+
+        // After the HALT bug PC fails to move past the next byte, which is then read again
+        if self.halt_bug {
+            self.halt_bug = false;
+        } else {
+            self.regs.set_pc(pc.wrapping_add(1));
+        }
 
         byte
     }
 
-    pub fn step_next_word(&mut self, bus: &Bus) -> u16 {
+    pub fn step_next_word(&mut self, bus: &mut dyn Memory) -> u16 {
         let lb = self.step_next_byte(bus);
         let hb = self.step_next_byte(bus);
 
         LittleEndian::read_u16(&[lb, hb])
     }
 
-    pub fn interrupt(&mut self, bus: &mut Bus, interrupt: Interrupt) {
-        let flags = bus.read(IO_IF_ADDR);
-        let interrupt_flag = interrupt as u8;
-
-        self.halted = false;
-        bus.write(IO_IF_ADDR, flags | interrupt_flag);
-    }
-
-    fn pop_stack(&mut self, bus: &Bus) -> u16 {
+    fn pop_stack(&mut self, bus: &mut dyn Memory) -> u16 {
         let addr = self.regs.sp();
-        let word = &[bus.read(addr), bus.read(addr.wrapping_add(1))];
+        let low = bus.read_cycle(addr);
+        let high = bus.read_cycle(addr.wrapping_add(1));
         self.regs.set_sp(addr.wrapping_add(2));
 
-        LittleEndian::read_u16(word)
+        LittleEndian::read_u16(&[low, high])
     }
 
-    fn push_stack(&mut self, bus: &mut Bus, val: u16) {
+    // Pushes take an internal cycle to decrement SP, then write the high byte first
+    fn push_stack(&mut self, bus: &mut dyn Memory, val: u16) {
         let addr = self.regs.sp();
-        
-        bus.write(addr.wrapping_sub(2), (val & 0x00FF) as u8);
-        bus.write(addr.wrapping_sub(1), ((val >> 8) & 0x00FF) as u8);
+
+        bus.idle_cycle();
+        bus.write_cycle(addr.wrapping_sub(1), ((val >> 8) & 0x00FF) as u8);
+        bus.write_cycle(addr.wrapping_sub(2), (val & 0x00FF) as u8);
 
         self.regs.set_sp(addr.wrapping_sub(2));
     }
@@ -240,10 +325,10 @@ impl Cpu {
         }
     }
 
-    fn pending_interrupt(&self, bus: &Bus) -> Option<Interrupt> {
+    fn pending_interrupt(&self, bus: &dyn Memory) -> Option<Interrupt> {
         let mut result = None;
 
-        let interrupts = bus.read(IO_IF_ADDR) & bus.read(IO_IE_ADDR);
+        let interrupts = bus.pending_interrupts();
         if interrupts > 0 {
             let mut flag = Interrupt::VBlank as u8;
             while flag > 0 {
@@ -253,27 +338,78 @@ impl Cpu {
                     break;
                 }
 
-                flag = flag << 1;
+                flag <<= 1;
             }
         }
 
         result
     }
 
-    fn handle_interrupt(&mut self, bus: &mut Bus, interrupt: Interrupt) {
-        let addr = interrupt_start_address(interrupt);
-
-        // Push current PC to stack and set PC to interrupt address.
+    // Dispatch takes 5 machine cycles: two internal, two pushing PC and one jumping to the handler.
+    // The interrupt to jump to is only picked after the high byte of PC is pushed. If that push overwrote IE and
+    // nothing is pending anymore, dispatch is cancelled and the CPU jumps to 0x0000 instead.
+    fn handle_interrupt(&mut self, bus: &mut dyn Memory) {
         let pc = self.regs.pc();
-        self.push_stack(bus, pc);
-        self.regs.set_pc(addr);
+        let sp = self.regs.sp();
+
+        bus.idle_cycle();
+        bus.idle_cycle();
+
+        bus.write_cycle(sp.wrapping_sub(1), (pc >> 8) as u8);
+        let interrupt = self.pending_interrupt(bus);
+        bus.write_cycle(sp.wrapping_sub(2), (pc & 0x00FF) as u8);
+
+        self.regs.set_sp(sp.wrapping_sub(2));
+
+        match interrupt {
+            Some(interrupt) => {
+                self.regs.set_pc(interrupt_start_address(interrupt));
+                bus.acknowledge_interrupt(interrupt);
+            },
+            None => self.regs.set_pc(0)
+        }
+
+        bus.idle_cycle();
 
         // Disable interrupts
         self.ime = false;
+    }
+}
+
+impl Savestate for Cpu {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.regs.save_state(out)?;
+        savestate::write_bool(out, self.ime)?;
+        savestate::write_bool(out, self.ime_pending)?;
+        savestate::write_bool(out, self.halted)?;
+        savestate::write_bool(out, self.halt_bug)?;
+        savestate::write_bool(out, self.stopped)?;
+
+        match self.lockup {
+            Some(lockup) => {
+                savestate::write_bool(out, true)?;
+                out.write_u16::<LittleEndian>(lockup.addr)?;
+                out.write_u8(lockup.opcode)
+            },
+            None => savestate::write_bool(out, false)
+        }
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.regs.load_state(input)?;
+        self.ime = savestate::read_bool(input)?;
+        self.ime_pending = savestate::read_bool(input)?;
+        self.halted = savestate::read_bool(input)?;
+        self.halt_bug = savestate::read_bool(input)?;
+        self.stopped = savestate::read_bool(input)?;
+
+        self.lockup = if savestate::read_bool(input)? {
+            Some(Lockup { addr: input.read_u16::<LittleEndian>()?, opcode: input.read_u8()? })
+        } else {
+            None
+        };
 
-        // Clear IF flag
-        let if_register = bus.read(IO_IF_ADDR);
-        bus.write(IO_IF_ADDR, if_register ^ (interrupt as u8));
+        Ok(())
     }
 }
 
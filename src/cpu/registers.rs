@@ -1,4 +1,7 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use savestate::Savestate;
 use std::fmt;
+use std::io::{self, Read, Write};
 
 #[derive(Copy, Clone)]
 #[allow(dead_code)]
@@ -234,4 +237,26 @@ impl fmt::Debug for Registers {
         writeln!(f, "H : {:#04X}\tL : {:#04X}", self.h(), self.l())?;
         writeln!(f, "SP: {:#06X}\tPC: {:#06X}", self.sp(), self.pc())
     }
-}
\ No newline at end of file
+}
+
+impl Savestate for Registers {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        for reg in &[self.af, self.bc, self.de, self.hl, self.sp, self.pc] {
+            out.write_u16::<LittleEndian>(*reg)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        let af = input.read_u16::<LittleEndian>()?;
+        self.set_af(af);
+        self.bc = input.read_u16::<LittleEndian>()?;
+        self.de = input.read_u16::<LittleEndian>()?;
+        self.hl = input.read_u16::<LittleEndian>()?;
+        self.sp = input.read_u16::<LittleEndian>()?;
+        self.pc = input.read_u16::<LittleEndian>()?;
+
+        Ok(())
+    }
+}
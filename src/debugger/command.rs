@@ -1,124 +1,207 @@
-/*
 use regex::Regex;
 use std::str::FromStr;
+use super::symbols::{is_banked, Symbols};
+use super::watchpoint::{WatchKind, Watchpoint};
 
 #[derive(Copy, Clone)]
 pub enum Command {
-    AddBreakPoint(u16),
+    AddBreakPoint(u16, Option<usize>),     // Address and the ROM bank that must be mapped, if any
+    AddWatchPoint(Watchpoint),
     Continue,
     Disassemble(u16, usize),
     Help,
     ListBreakPoints,
+    ListWatchPoints,
     Memory(u16, usize),
     Quit,
     RemoveBreakPoint(u16),
+    RemoveWatchPoint(usize),
     Registers,
     Step(usize)
 }
 
-fn parse_str(val: &str) -> usize {
+// Numbers are decimal unless prefixed with 0x (hex) or 0b (binary)
+fn parse_str(val: &str) -> Result<usize, String> {
     lazy_static! {
-        static ref RE: Regex = Regex::new(r"^0[xb]").unwrap();
+        static ref RE: Regex = Regex::new(r"^0[xXbB]").unwrap();
     }
 
     if RE.is_match(val) {
         match &val[..2] {
-            "0x" => usize::from_str_radix(&val[2..], 16),
-            "0b" => usize::from_str_radix(&val[2..], 2),
-            _ => usize::from_str(val)
+            "0x" | "0X" => usize::from_str_radix(&val[2..], 16),
+            _ => usize::from_str_radix(&val[2..], 2)
         }
     } else {
         usize::from_str(val)
-    }.unwrap()
+    }.map_err(|_| format!("Invalid number: {}", val))
+}
+
+// A number or a label from the symbol file
+fn parse_addr(val: &str, symbols: &Symbols) -> Result<u16, String> {
+    parse_location(val, symbols).map(|(addr, _)| addr)
+}
+
+// Like parse_addr, but also returns the ROM bank for labels and bank:addr pairs in switchable ROM
+fn parse_location(val: &str, symbols: &Symbols) -> Result<(u16, Option<usize>), String> {
+    lazy_static! {
+        static ref BANKED: Regex = Regex::new(r"^([0-9a-fA-F]+):([0-9a-fA-F]{1,4})$").unwrap();
+        static ref NUMBER: Regex = Regex::new(r"^[0-9]").unwrap();
+    }
+
+    if let Some(caps) = BANKED.captures(val) {
+        let bank = usize::from_str_radix(&caps[1], 16).map_err(|_| format!("Invalid bank: {}", val))?;
+        let addr = u16::from_str_radix(&caps[2], 16).map_err(|_| format!("Invalid address: {}", val))?;
+
+        return Ok((addr, if is_banked(addr) { Some(bank) } else { None }));
+    }
+
+    if !NUMBER.is_match(val) {
+        return match symbols.resolve(val) {
+            Some((bank, addr)) => Ok((addr, if is_banked(addr) { Some(bank) } else { None })),
+            None => Err(format!("Unknown label: {}", val))
+        };
+    }
+
+    let addr = parse_str(val)?;
+
+    if addr > 0xFFFF {
+        Err(format!("Address out of range: {}", val))
+    } else {
+        Ok((addr as u16, None))
+    }
+}
+
+// A single address or an inclusive start-end range
+fn parse_range(val: &str, symbols: &Symbols) -> Result<(u16, u16), String> {
+    let mut parts = val.splitn(2, '-');
+    let start = parse_addr(parts.next().unwrap_or(""), symbols)?;
+    let end = parts.next().map_or(Ok(start), |end| parse_addr(end, symbols))?;
+
+    if end < start {
+        Err(format!("Invalid range: {}", val))
+    } else {
+        Ok((start, end))
+    }
+}
+
+fn parse_watch(trimmed: &str, symbols: &Symbols) -> Result<Command, String> {
+    lazy_static! {
+        static ref RE: Regex = Regex::new(r"^w([rwcvd])? *(\S+)? *(\S+)?$").unwrap();
+    }
+
+    let caps = match RE.captures(trimmed) {
+        Some(caps) => caps,
+        None => return Err(String::from("Unknown command"))
+    };
+
+    let kind = caps.get(1).map(|m| m.as_str());
+    let arg = caps.get(2).map(|m| m.as_str());
+    let value = caps.get(3).map(|m| m.as_str());
+
+    let kind = match (kind, arg, value) {
+        (None, None, None) => return Ok(Command::ListWatchPoints),
+        (Some("d"), Some(index), None) => return Ok(Command::RemoveWatchPoint(parse_str(index)?)),
+        (Some("d"), _, _) => return Err(String::from("Usage: wd [index]")),
+        (Some("r"), Some(_), None) => WatchKind::Read,
+        (Some("w"), Some(_), None) => WatchKind::Write,
+        (Some("c"), Some(_), None) => WatchKind::Change,
+        (Some("v"), Some(_), Some(value)) => {
+            match parse_str(value)? {
+                value if value <= 0xFF => WatchKind::Value(value as u8),
+                _ => return Err(format!("Value out of range: {}", value))
+            }
+        },
+        (Some("v"), _, _) => return Err(String::from("Usage: wv [range] [val]")),
+        (Some(kind), _, _) => return Err(format!("Usage: w{} [range]", kind)),
+        _ => return Err(String::from("Unknown command"))
+    };
+
+    let (start, end) = parse_range(arg.unwrap(), symbols)?;
+
+    Ok(Command::AddWatchPoint(Watchpoint { start, end, kind }))
 }
 
 impl Command {
-    pub fn parse(line: &String, pc: u16) -> Result<Self, String> {
+    pub fn parse(line: &str, pc: u16, symbols: &Symbols) -> Result<Self, String> {
         let trimmed = line.trim();
 
-        match trimmed.chars().nth(0).unwrap() {
-            'b' => {
-                if trimmed.len() == 1 {
-                    Ok(Command::ListBreakPoints)
-                } else {
-                    match trimmed.chars().nth(1).unwrap() {
-                        'a' => {
-                            lazy_static! {
-                                static ref RE: Regex = Regex::new(r"ba ([0-9a-fA-Fx]+)").unwrap();
-                            }
-                            if RE.is_match(trimmed) {
-                                let caps = RE.captures(trimmed).unwrap();
-                                let addr = caps.get(1).map_or(0, |m| parse_str(m.as_str()) as u16);
-
-                                Ok(Command::AddBreakPoint(addr))
-                            } else {
-                                Err(String::from("Usage: ba [addr]"))
-                            }
-                        },
-                        'r' => {
-                            lazy_static! {
-                                static ref RE: Regex = Regex::new(r"br ([0-9a-fx]+)").unwrap();
-                            }
-                            if RE.is_match(trimmed) {
-                                let caps = RE.captures(trimmed).unwrap();
-                                let addr = caps.get(1).map_or(0, |m| parse_str(m.as_str()) as u16);
-
-                                Ok(Command::RemoveBreakPoint(addr))
-                            } else {
-                                Err(String::from("Usage: br [addr]"))
-                            }
-                        },
-                        _ => Err(String::from("Unknown command"))
-                    }
+        match trimmed.chars().next() {
+            Some('b') => {
+                lazy_static! {
+                    static ref RE: Regex = Regex::new(r"^b([ar])? *(\S+)?$").unwrap();
+                }
+
+                match RE.captures(trimmed) {
+                    Some(caps) => {
+                        match (caps.get(1).map(|m| m.as_str()), caps.get(2)) {
+                            (None, None) => Ok(Command::ListBreakPoints),
+                            (Some("a"), Some(addr)) => {
+                                let (addr, bank) = parse_location(addr.as_str(), symbols)?;
+                                Ok(Command::AddBreakPoint(addr, bank))
+                            },
+                            (Some("a"), None) => Err(String::from("Usage: ba [addr]")),
+                            (Some("r"), Some(addr)) => Ok(Command::RemoveBreakPoint(parse_addr(addr.as_str(), symbols)?)),
+                            (Some("r"), None) => Err(String::from("Usage: br [addr]")),
+                            _ => Err(String::from("Unknown command"))
+                        }
+                    },
+                    None => Err(String::from("Unknown command"))
                 }
             },
-            'c' => Ok(Command::Continue),
-            'd' => {
+            Some('c') => Ok(Command::Continue),
+            Some('d') => {
                 lazy_static! {
-                    static ref RE: Regex = Regex::new(r"d(?:is)? ?([0-9a-fx]+)? ?([0-9a-fx]+)?").unwrap();
+                    static ref RE: Regex = Regex::new(r"^d(?:is)? *(\S+)? *(\S+)?$").unwrap();
                 }
-                if RE.is_match(trimmed) {
-                    let caps = RE.captures(trimmed).unwrap();
-                    let addr = caps.get(1).map_or(pc, |m| parse_str(m.as_str()) as u16);
-                    let length = caps.get(2).map_or(10, |m| parse_str(m.as_str()));
-
-                    Ok(Command::Disassemble(addr, length))
-                } else {
-                    Err(String::from("Usage: d [addr] [length]"))
+
+                match RE.captures(trimmed) {
+                    Some(caps) => {
+                        let addr = caps.get(1).map_or(Ok(pc), |m| parse_addr(m.as_str(), symbols))?;
+                        let length = caps.get(2).map_or(Ok(10), |m| parse_str(m.as_str()))?;
+
+                        Ok(Command::Disassemble(addr, length))
+                    },
+                    None => Err(String::from("Usage: d [addr] [length]"))
                 }
             },
-            'h' => Ok(Command::Help),
-            'm' => {
+            Some('h') => Ok(Command::Help),
+            Some('m') => {
                 lazy_static! {
-                    static ref RE: Regex = Regex::new(r"m(?:em)? ([0-9a-fx]+) ?([0-9a-fx]+)?").unwrap();
+                    static ref RE: Regex = Regex::new(r"^m(?:em)? +(\S+) *(\S+)?$").unwrap();
                 }
-                if RE.is_match(trimmed) {
-                    let caps = RE.captures(trimmed).unwrap();
-                    let addr = caps.get(1).map_or(0, |m| parse_str(m.as_str()) as u16);
-                    let length = caps.get(2).map_or(1, |m| parse_str(m.as_str()));
-
-                    Ok(Command::Memory(addr, length))
-                } else {
-                    Err(String::from("Usage: m [addr] [length]"))
+
+                match RE.captures(trimmed) {
+                    Some(caps) => {
+                        let addr = parse_addr(&caps[1], symbols)?;
+                        let length = caps.get(2).map_or(Ok(1), |m| parse_str(m.as_str()))?;
+
+                        Ok(Command::Memory(addr, length))
+                    },
+                    None => Err(String::from("Usage: m [addr] [length]"))
                 }
-            }
-            'q' => Ok(Command::Quit),
-            'r' => Ok(Command::Registers),
-            's' => {
+            },
+            Some('q') => Ok(Command::Quit),
+            Some('r') => Ok(Command::Registers),
+            Some('s') => {
                 lazy_static! {
-                    static ref RE: Regex = Regex::new(r"s(?:tep)? ?([0-9a-fx]+)?").unwrap();
+                    static ref RE: Regex = Regex::new(r"^s(?:tep)? *(\S+)?$").unwrap();
                 }
-                if RE.is_match(trimmed) {
-                    let caps = RE.captures(trimmed).unwrap();
-                    let count = caps.get(1).map_or(1, |m| parse_str(m.as_str()));
 
-                    Ok(Command::Step(count))
-                } else {
-                    Err(String::from("Usage: s [count]"))
+                match RE.captures(trimmed) {
+                    Some(caps) => {
+                        let count = caps.get(1).map_or(Ok(1), |m| parse_str(m.as_str()))?;
+
+                        if count == 0 {
+                            Err(String::from("Step count must be at least 1"))
+                        } else {
+                            Ok(Command::Step(count))
+                        }
+                    },
+                    None => Err(String::from("Usage: s [count]"))
                 }
             },
+            Some('w') => parse_watch(trimmed, symbols),
             _ => Err(String::from("Unknown command"))
         }
     }
 }
-*/
\ No newline at end of file
@@ -0,0 +1,449 @@
+use cpu::Registers;
+use emulator::Emulator;
+use fnv::FnvHashSet;
+use log::{info, warn};
+use std::io::{self, BufRead, BufReader, ErrorKind, Write};
+use std::net::{TcpListener, TcpStream};
+use super::{Action, BreakHandler};
+use super::watchpoint::{WatchKind, Watchpoint};
+
+// How many instructions run between checks for an interrupt (Ctrl-C) from the client
+const POLL_INTERVAL: usize = 0x1000;
+
+const SIGINT: u8 = 2;
+const SIGILL: u8 = 4;
+const SIGTRAP: u8 = 5;
+
+// The Gameboy's registers, in the order they are sent to the client.
+// Each is 16 bits and sent little endian.
+const REGISTER_COUNT: usize = 6;
+
+fn read_register(regs: &Registers, index: usize) -> Option<u16> {
+    match index {
+        0 => Some(regs.af()),
+        1 => Some(regs.bc()),
+        2 => Some(regs.de()),
+        3 => Some(regs.hl()),
+        4 => Some(regs.sp()),
+        5 => Some(regs.pc()),
+        _ => None
+    }
+}
+
+fn write_register(regs: &mut Registers, index: usize, val: u16) -> bool {
+    match index {
+        0 => regs.set_af(val),
+        1 => regs.set_bc(val),
+        2 => regs.set_de(val),
+        3 => regs.set_hl(val),
+        4 => regs.set_sp(val),
+        5 => regs.set_pc(val),
+        _ => return false
+    };
+
+    true
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum State {
+    Running,
+    Stepping,
+    Stopped,
+    Detached
+}
+
+// What to do after a packet has been handled
+pub enum Response {
+    Reply(String),
+    Resume,
+    Detach,
+    Kill
+}
+
+// Protocol state that doesn't depend on the connection
+pub struct Session {
+    breakpoints: FnvHashSet<u16>,
+    state: State
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self {
+            breakpoints: FnvHashSet::default(),
+            state: State::Stopped
+        }
+    }
+
+    pub fn handle_packet(&mut self, packet: &str, emulator: &mut Emulator) -> Response {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+
+        let reply = match command {
+            "?" => stop_reply(SIGTRAP),
+            "g" => {
+                (0..REGISTER_COUNT)
+                    .filter_map(|index| read_register(&emulator.cpu().regs, index))
+                    .map(|val| format!("{:02x}{:02x}", val & 0xFF, val >> 8))
+                    .collect()
+            },
+            "G" => {
+                match decode_hex(args) {
+                    Some(ref bytes) if bytes.len() >= REGISTER_COUNT * 2 => {
+                        for index in 0..REGISTER_COUNT {
+                            let val = bytes[index * 2] as u16 | (bytes[index * 2 + 1] as u16) << 8;
+                            write_register(&mut emulator.cpu_mut().regs, index, val);
+                        }
+
+                        ok()
+                    },
+                    _ => error(1)
+                }
+            },
+            "p" => {
+                match usize::from_str_radix(args, 16).ok().and_then(|index| read_register(&emulator.cpu().regs, index)) {
+                    Some(val) => format!("{:02x}{:02x}", val & 0xFF, val >> 8),
+                    None => error(1)
+                }
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let index = parts.next().and_then(|index| usize::from_str_radix(index, 16).ok());
+                let bytes = parts.next().and_then(decode_hex);
+
+                match (index, bytes) {
+                    (Some(index), Some(ref bytes)) if bytes.len() == 2 => {
+                        let val = bytes[0] as u16 | (bytes[1] as u16) << 8;
+
+                        if write_register(&mut emulator.cpu_mut().regs, index, val) { ok() } else { error(1) }
+                    },
+                    _ => error(1)
+                }
+            },
+            "m" => {
+                match parse_addr_length(args) {
+                    Some((addr, length)) => {
+                        (0..length)
+                            .map(|offset| format!("{:02x}", emulator.bus().peek(addr.wrapping_add(offset as u16))))
+                            .collect()
+                    },
+                    None => error(1)
+                }
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                let range = parts.next().and_then(parse_addr_length);
+                let bytes = parts.next().and_then(decode_hex);
+
+                match (range, bytes) {
+                    (Some((addr, length)), Some(ref bytes)) if bytes.len() == length => {
+                        for (offset, byte) in bytes.iter().enumerate() {
+                            emulator.bus_mut().poke(addr.wrapping_add(offset as u16), *byte);
+                        }
+
+                        ok()
+                    },
+                    _ => error(1)
+                }
+            },
+            "Z" | "z" => self.handle_breakpoint(command == "Z", args, emulator),
+            "c" => {
+                self.state = State::Running;
+                return Response::Resume;
+            },
+            "s" => {
+                self.state = State::Stepping;
+                return Response::Resume;
+            },
+            "D" => {
+                self.state = State::Detached;
+                return Response::Detach;
+            },
+            "k" => return Response::Kill,
+            "H" => ok(),
+            "q" if args.starts_with("Supported") => String::from("PacketSize=1000"),
+            "q" if args == "Attached" => String::from("1"),
+            // Anything else is unsupported, which is reported with an empty reply
+            _ => String::new()
+        };
+
+        Response::Reply(reply)
+    }
+
+    // Z/z type,addr,kind. Types 0 and 1 are breakpoints, 2-4 are write, read and access watchpoints.
+    fn handle_breakpoint(&mut self, insert: bool, args: &str, emulator: &mut Emulator) -> String {
+        let parts: Vec<&str> = args.split(',').collect();
+
+        if parts.len() < 3 {
+            return error(1);
+        }
+
+        let addr = match u16::from_str_radix(parts[1], 16) {
+            Ok(addr) => addr,
+            Err(_) => return error(1)
+        };
+
+        let length = usize::from_str_radix(parts[2], 16).unwrap_or(1).max(1);
+        let end = addr.saturating_add((length - 1) as u16);
+
+        let kinds: &[WatchKind] = match parts[0] {
+            "0" | "1" => {
+                if insert {
+                    self.breakpoints.insert(addr);
+                } else {
+                    self.breakpoints.remove(&addr);
+                }
+
+                return ok();
+            },
+            "2" => &[WatchKind::Write],
+            "3" => &[WatchKind::Read],
+            "4" => &[WatchKind::Read, WatchKind::Write],
+            _ => return String::new()
+        };
+
+        let watchpoints = &mut emulator.bus_mut().watchpoints;
+
+        for kind in kinds {
+            let watchpoint = Watchpoint { start: addr, end, kind: *kind };
+
+            if insert {
+                watchpoints.add(watchpoint);
+            } else if let Some(index) = watchpoints.list().iter().position(|w| w.start == addr && w.end == end && w.kind == *kind) {
+                watchpoints.remove(index);
+            }
+        }
+
+        ok()
+    }
+
+    // Checked before every instruction. Returns the stop reply to send if execution should stop.
+    pub fn check_stop(&mut self, emulator: &Emulator) -> Option<String> {
+        if self.state == State::Detached {
+            return None;
+        }
+
+        let bus = emulator.bus();
+
+        if let Some(hit) = bus.watchpoints.take_hit() {
+            let kind = if hit.write { "watch" } else { "rwatch" };
+            return Some(format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.addr));
+        }
+
+        match self.state {
+            State::Stepping | State::Stopped => Some(stop_reply(SIGTRAP)),
+            State::Running if emulator.cpu().lockup().is_some() => Some(stop_reply(SIGILL)),
+            State::Running if self.breakpoints.contains(&emulator.cpu().regs.pc()) => Some(stop_reply(SIGTRAP)),
+            _ => None
+        }
+    }
+}
+
+fn ok() -> String {
+    String::from("OK")
+}
+
+fn error(code: u8) -> String {
+    format!("E{:02x}", code)
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return None;
+    }
+
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).ok()).collect()
+}
+
+// addr,length as used by the memory packets
+// Lengths past the end of the 64KB address space are rejected, so a reply can't grow without bound
+fn parse_addr_length(args: &str) -> Option<(u16, usize)> {
+    let mut parts = args.splitn(2, ',');
+    let addr = parts.next().and_then(|addr| u16::from_str_radix(addr, 16).ok())?;
+    let length = parts.next().and_then(|length| usize::from_str_radix(length, 16).ok())
+        .filter(|&length| length <= 0x10000)?;
+
+    Some((addr, length))
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte))
+}
+
+// GDB remote serial protocol server. Registers are exposed as AF, BC, DE, HL, SP, PC.
+// While the client has control the emulator is paused inside brk().
+pub struct GdbStub {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    session: Session,
+    poll_counter: usize,
+    stop_reply: Option<String>,
+    first_stop: bool
+}
+
+impl GdbStub {
+    // Waits for a client to connect on the given local port
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!("Waiting for GDB connection on port {}", port);
+
+        let (stream, addr) = listener.accept()?;
+        info!("GDB connected from {}", addr);
+
+        stream.set_nodelay(true)?;
+
+        Ok(Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            session: Session::new(),
+            poll_counter: 0,
+            stop_reply: None,
+            first_stop: true
+        })
+    }
+
+    // Reads the next packet, acknowledging it. Returns None for an interrupt request (Ctrl-C).
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            let mut byte = [0];
+            self.read_exact(&mut byte)?;
+
+            match byte[0] {
+                0x03 => return Ok(None),
+                b'$' => { },
+                // Acks from the client and anything outside a packet are skipped
+                _ => continue
+            }
+
+            let mut packet = Vec::new();
+            self.reader.read_until(b'#', &mut packet)?;
+
+            if packet.pop() != Some(b'#') {
+                return Err(io::Error::new(ErrorKind::UnexpectedEof, "Connection closed"));
+            }
+
+            let mut sum = [0; 2];
+            self.read_exact(&mut sum)?;
+
+            let expected = ::std::str::from_utf8(&sum).ok().and_then(|sum| u8::from_str_radix(sum, 16).ok());
+
+            if expected == Some(checksum(&packet)) {
+                self.writer.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&packet).into_owned()));
+            } else {
+                self.writer.write_all(b"-")?;
+            }
+        }
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8]) -> io::Result<()> {
+        io::Read::read_exact(&mut self.reader, buf)
+    }
+
+    fn send_packet(&mut self, data: &str) -> io::Result<()> {
+        write!(self.writer, "${}#{:02x}", data, checksum(data.as_bytes()))?;
+        self.writer.flush()
+    }
+
+    // Checks for a Ctrl-C from the client without blocking
+    fn poll_interrupt(&mut self) -> io::Result<bool> {
+        if self.reader.buffer().is_empty() {
+            self.reader.get_ref().set_nonblocking(true)?;
+            let result = self.reader.fill_buf().map(|buf| buf.is_empty());
+            self.reader.get_ref().set_nonblocking(false)?;
+
+            match result {
+                Ok(true) => return Err(io::Error::new(ErrorKind::UnexpectedEof, "Connection closed")),
+                Ok(false) => { },
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
+                Err(err) => return Err(err)
+            }
+        }
+
+        if self.reader.buffer()[0] == 0x03 {
+            self.reader.consume(1);
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    // Serves packets until the client resumes execution
+    fn serve(&mut self, emulator: &mut Emulator) -> io::Result<Action> {
+        if let Some(reply) = self.stop_reply.take() {
+            self.send_packet(&reply)?;
+        }
+
+        loop {
+            let packet = match self.read_packet()? {
+                Some(packet) => packet,
+                None => continue
+            };
+
+            match self.session.handle_packet(&packet, emulator) {
+                Response::Reply(reply) => self.send_packet(&reply)?,
+                Response::Resume => return Ok(Action::Resume),
+                Response::Detach => {
+                    self.send_packet("OK")?;
+                    info!("GDB detached");
+                    return Ok(Action::Resume);
+                },
+                Response::Kill => return Ok(Action::Quit)
+            }
+        }
+    }
+}
+
+impl BreakHandler for GdbStub {
+    fn should_break(&mut self, emulator: &Emulator) -> bool {
+        if self.session.state == State::Running {
+            self.poll_counter += 1;
+
+            if self.poll_counter >= POLL_INTERVAL {
+                self.poll_counter = 0;
+
+                match self.poll_interrupt() {
+                    Ok(true) => {
+                        self.session.state = State::Stopped;
+                        self.stop_reply = Some(stop_reply(SIGINT));
+                        return true;
+                    },
+                    Ok(false) => { },
+                    Err(err) => {
+                        warn!("GDB connection lost: {}", err);
+                        self.session.state = State::Detached;
+                    }
+                }
+            }
+        }
+
+        match self.session.check_stop(emulator) {
+            Some(reply) => {
+                self.stop_reply = Some(reply);
+                true
+            },
+            None => false
+        }
+    }
+
+    fn brk(&mut self, emulator: &mut Emulator) -> Action {
+        // The client asks why the target stopped when it connects, so the first stop isn't reported on its own
+        if self.first_stop {
+            self.first_stop = false;
+            self.stop_reply = None;
+        }
+
+        self.session.state = State::Stopped;
+
+        match self.serve(emulator) {
+            Ok(action) => action,
+            Err(err) => {
+                warn!("GDB connection lost: {}", err);
+                self.session.state = State::Detached;
+                Action::Resume
+            }
+        }
+    }
+}
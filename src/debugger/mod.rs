@@ -1,39 +1,203 @@
-/*
 mod command;
+mod gdb;
+mod symbols;
+mod watchpoint;
+
+#[cfg(test)]
+mod tests;
 
 use self::command::Command;
-use bus::{Addressable, Bus};
-use cpu::{Cpu, decode};
-use fnv::FnvHashSet;
+use bus::Bus;
+use cpu::{Cpu, decode, Instruction, Interrupt, Lockup, Memory};
+use emulator::Emulator;
+use fnv::FnvHashMap;
 use std::io::{stdin, stdout, Write};
-use std::iter;
-use std::process;
+
+pub use self::gdb::GdbStub;
+pub use self::symbols::{symbols_path, Symbols};
+pub use self::watchpoint::{WatchHit, WatchKind, Watchpoint, Watchpoints};
 
 enum State {
     Running,
     BreakAfter(usize)
 }
 
+// What the emulator should do once the debugger gives up control
+pub enum Action {
+    Resume,
+    Quit
+}
+
+// Gets a chance to pause the emulator before every instruction and take control of it
+pub trait BreakHandler {
+    fn should_break(&mut self, emulator: &Emulator) -> bool;
+
+    // Called when should_break returns true. Returns once execution should continue.
+    fn brk(&mut self, emulator: &mut Emulator) -> Action;
+}
+
+// Interactive command line debugger
 pub struct Debugger {
-    breakpoints: FnvHashSet<u16>,
+    breakpoints: FnvHashMap<u16, Option<usize>>,   // Address and the ROM bank that must be mapped, if any
     state: State,
-    previous_command: Command
+    previous_command: Command,
+    symbols: Symbols,
+    last_pc: u16,               // Address of the most recently executed instruction
+    watch_hit: Option<WatchHit>,
+    lockup: Option<Lockup>      // CPU lockup that was last reported, so it only breaks once
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Debugger {
+    // The debugger breaks before the first instruction is executed
     pub fn new() -> Self {
         Debugger {
-            breakpoints: FnvHashSet::default(),
-            state: State::Running,
-            previous_command: Command::Continue
+            breakpoints: FnvHashMap::default(),
+            state: State::BreakAfter(0),
+            previous_command: Command::Continue,
+            symbols: Symbols::default(),
+            last_pc: 0,
+            watch_hit: None,
+            lockup: None
+        }
+    }
+
+    // Labels shown in disassembly and accepted in place of addresses
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    fn prompt(&mut self, emulator: &mut Emulator) -> Action {
+        loop {
+            print_disassembly(emulator.bus(), &self.symbols, emulator.cpu().regs.pc(), 1);
+
+            print!("> ");
+            let _ = stdout().flush();
+
+            let mut input = String::new();
+
+            match stdin().read_line(&mut input) {
+                // Input was closed, there's nobody left to hand control back
+                Ok(0) | Err(_) => return Action::Quit,
+                Ok(_) => { }
+            }
+
+            let command = if input.trim().is_empty() {
+                Ok(self.previous_command)
+            } else {
+                Command::parse(&input, emulator.cpu().regs.pc(), &self.symbols)
+            };
+
+            match command {
+                Ok(command) => {
+                    self.previous_command = command;
+
+                    match command {
+                        Command::AddBreakPoint(addr, bank) => {
+                            self.breakpoints.insert(addr, bank);
+                        },
+                        Command::ListBreakPoints => {
+                            for (addr, bank) in &self.breakpoints {
+                                let location = match *bank {
+                                    Some(bank) => format!("{:02X}:{:04X}", bank, addr),
+                                    None => format!("{:#06X}", addr)
+                                };
+
+                                match self.symbols.lookup(emulator.bus(), *addr) {
+                                    Some(label) => println!("{}\t{}", location, label),
+                                    None => println!("{}", location)
+                                }
+                            }
+                        },
+                        Command::Continue => {
+                            self.state = State::Running;
+                            return Action::Resume;
+                        },
+                        Command::Disassemble(addr, count) => print_disassembly(emulator.bus(), &self.symbols, addr, count),
+                        Command::Help => {
+                            println!("Command\t\t\tDescription");
+                            println!("b\t\t\tList Break Points");
+                            println!("ba [addr]\t\tAdd Break Point (addr can be a label or bank:addr)");
+                            println!("br [addr]\t\tRemove Break Point");
+                            println!("c\t\t\tContinue Execution");
+                            println!("d [addr] [count]\tDisassemble");
+                            println!("h\t\t\tHelp");
+                            println!("m [addr] [count]\tInspect Memory");
+                            println!("q\t\t\tQuit");
+                            println!("r\t\t\tInspect Registers");
+                            println!("s [count]\t\tStep Instructions");
+                            println!("w\t\t\tList Watch Points");
+                            println!("wr [range]\t\tWatch Reads");
+                            println!("ww [range]\t\tWatch Writes");
+                            println!("wc [range]\t\tWatch Value Changes");
+                            println!("wv [range] [val]\tWatch Writes Of A Value");
+                            println!("wd [index]\t\tRemove Watch Point");
+                            println!("Ranges are a single address or start-end");
+                            println!("Addresses can be given as labels when a symbol file is loaded");
+                        }
+                        Command::Memory(addr, count) => print_memory(emulator.bus(), addr, count),
+                        Command::Quit => return Action::Quit,
+                        Command::RemoveBreakPoint(addr) => {
+                            self.breakpoints.remove(&addr);
+                        },
+                        Command::AddWatchPoint(watchpoint) => emulator.bus_mut().watchpoints.add(watchpoint),
+                        Command::ListWatchPoints => {
+                            for (index, watchpoint) in emulator.bus().watchpoints.list().iter().enumerate() {
+                                println!("{}\t{}", index, watchpoint);
+                            }
+                        },
+                        Command::RemoveWatchPoint(index) => {
+                            if emulator.bus_mut().watchpoints.remove(index).is_none() {
+                                println!("Error: No watchpoint {}", index);
+                            }
+                        },
+                        Command::Registers => print!("{:?}", emulator.cpu()),
+                        Command::Step(steps) => {
+                            self.state = State::BreakAfter(steps - 1);
+                            return Action::Resume;
+                        }
+                    };
+                },
+                Err(err) => println!("Error: {}", err)
+            }
         }
     }
+}
 
+impl BreakHandler for Debugger {
+    // Called before every instruction. Breaks on breakpoints, after stepping, or when
+    // the previous instruction triggered a watchpoint or locked up the CPU.
     #[inline(always)]
-    pub fn should_break(&mut self, cpu: &Cpu) -> bool {
+    fn should_break(&mut self, emulator: &Emulator) -> bool {
+        let pc = emulator.cpu().regs.pc();
+
+        if let Some(hit) = emulator.bus().watchpoints.take_hit() {
+            self.watch_hit = Some(hit);
+            return true;
+        }
+
+        if emulator.cpu().lockup() != self.lockup {
+            self.lockup = emulator.cpu().lockup();
+
+            if self.lockup.is_some() {
+                return true;
+            }
+        }
+
+        self.last_pc = pc;
+
         match self.state {
             State::Running => {
-                self.breakpoints.contains(&cpu.regs.pc())
+                match self.breakpoints.get(&pc) {
+                    Some(&Some(bank)) => emulator.bus().cartridge().rom_bank() == bank,
+                    Some(&None) => true,
+                    None => false
+                }
             },
             State::BreakAfter(steps) => {
                 if steps == 0 {
@@ -46,113 +210,136 @@ impl Debugger {
         }
     }
 
-    pub fn brk(&mut self, cpu: &mut Cpu, bus: &mut Bus) {
-        loop {
-            self.print_disassembly(&bus, cpu.regs.pc(), 1);
+    // Prompts for commands until execution is resumed
+    fn brk(&mut self, emulator: &mut Emulator) -> Action {
+        if let Some(hit) = self.watch_hit.take() {
+            println!("{}", hit);
+            print!("Accessed by ");
+            print_disassembly(emulator.bus(), &self.symbols, self.last_pc, 1);
+        }
 
-            print!("> ");
-            let _ = stdout().flush();
+        if let Some(lockup) = emulator.cpu().lockup() {
+            println!("{}", lockup);
+        }
 
-            let mut input = String::new();
+        self.prompt(emulator)
+    }
 
-            if let Ok(_) = stdin().read_line(&mut input) {
-                let command = if input.trim().len() == 0 {
-                    Ok(self.previous_command)
-                } else {
-                    Command::parse(&input, cpu.regs.pc())
-                };
-
-                match command {
-                    Ok(command) => {
-                        self.previous_command = command;
-
-                        match command {
-                            Command::AddBreakPoint(addr) => {
-                                self.breakpoints.insert(addr);
-                            },
-                            Command::ListBreakPoints => {
-                                for addr in &self.breakpoints {
-                                    println!("{:#X}", *addr);
-                                }
-                            },
-                            Command::Continue => {
-                                self.state = State::Running;
-                                break;
-                            },
-                            Command::Disassemble(addr, count) => self.print_disassembly(&bus, addr, count),
-                            Command::Help => {
-                                println!("Command\t\t\tDescription");
-                                println!("b\t\t\tList Break Points");
-                                println!("ba [addr]\t\tAdd Break Point");
-                                println!("br [addr]\t\tRemove Break Point");
-                                println!("c\t\t\tContinue Execution");
-                                println!("d [addr] [count]\tDisassemble");
-                                println!("h\t\t\tHelp");
-                                println!("m [addr] [count]\tInspect Memory");
-                                println!("q\t\t\tQuit");
-                                println!("r\t\t\tInspect Registers");
-                                println!("s [count]\t\tStep Instructions");
-                            }
-                            Command::Memory(addr, count) => {
-                                print!("{:#04X} = ", addr);
-                                for i in 0..count {
-                                    print!("{:02X} ", bus.read(addr.wrapping_add(i as u16)));
-                                }
-                                println!();
-                            },
-                            Command::Quit => process::exit(0),
-                            Command::RemoveBreakPoint(addr) => {
-                                self.breakpoints.remove(&addr);
-                            }
-                            Command::Registers => println!("{:?}", cpu.regs),
-                            Command::Step(steps) => {
-                                self.state = State::BreakAfter(steps - 1);
-                                break;
-                            }
-                        };
-                    },
-                    Err(err) => println!("Error: {}", err)
-                }
-            }
-        }
+}
+
+// Decodes the instruction at addr without touching the CPU. Returns the instruction and its length in bytes.
+// Lets the decoder read operands without stepping the hardware or triggering watchpoints
+struct Inspect<'b, 'a: 'b>(&'b Bus<'a>);
+
+impl<'b, 'a> Memory for Inspect<'b, 'a> {
+    fn read_cycle(&mut self, addr: u16) -> u8 {
+        self.0.peek(addr)
     }
 
-    fn print_disassembly(&self, bus: &Bus, addr: u16, count: usize) {
-        let mut instruction_addr = addr;
+    fn write_cycle(&mut self, _: u16, _: u8) { }
 
-        for _ in 0..count {
-            let mut opcode = bus.read(instruction_addr);
-            let mut prefixed = false;
+    fn idle_cycle(&mut self) { }
 
-            let (decoded_instruction, length) = {
-                let mut length = 1;
+    fn stopped_cycle(&mut self) { }
 
-                if opcode == 0xCB {
-                    opcode = bus.read(instruction_addr.wrapping_add(1));
-                    length = length + 1;
-                    prefixed = true;
-                }
+    fn cycles(&self) -> usize {
+        0
+    }
 
-                ({
-                    let mut next = || {
-                        let byte = bus.read(addr.wrapping_add(length));
-                        length = length + 1;
-                        byte
-                    };
+    fn pending_interrupts(&self) -> u8 {
+        0
+    }
 
-                    decode(opcode, prefixed, &mut next)
-                }, length)
-            };
+    fn acknowledge_interrupt(&mut self, _: Interrupt) { }
 
-            if let Some(instruction) = decoded_instruction {
-                let hex = "0x".to_string() + &(0..length).map(|offset| format!("{:02X}", bus.read(instruction_addr + offset))).collect::<String>();
-                let padding = iter::repeat(" ").take(10 - (length as usize * 2)).collect::<String>();
-                
-                println!("{:#06X}\t{}{}{}", instruction_addr, hex, padding, instruction);
-            }
+    fn stop(&mut self) { }
 
-            instruction_addr = instruction_addr + length;
+    fn button_pressed(&self) -> bool {
+        false
+    }
+}
+
+// Illegal opcodes come back as None with a length of 1.
+pub fn disassemble(bus: &Bus, addr: u16) -> (Option<Instruction>, u16) {
+    let mut memory = Inspect(bus);
+    let mut opcode = bus.peek(addr);
+    let mut prefixed = false;
+
+    // Decoding reads operands through the CPU's program counter, so use a scratch CPU pointed at the instruction
+    let mut cpu = Cpu::new();
+    cpu.regs.set_pc(addr.wrapping_add(1));
+
+    if opcode == 0xCB {
+        prefixed = true;
+        opcode = cpu.step_next_byte(&mut memory);
+    }
+
+    let instruction = decode(&mut cpu, &mut memory, opcode, prefixed);
+
+    if let Instruction::Illegal(_) = instruction {
+        return (None, 1);
+    }
+    let length = cpu.regs.pc().wrapping_sub(addr);
+
+    (Some(instruction), length)
+}
+
+// Address jumped or called to by a branch instruction, read straight from its encoding
+fn branch_target(bus: &Bus, addr: u16) -> Option<u16> {
+    let opcode = bus.peek(addr);
+    let word = || (bus.peek(addr.wrapping_add(1)) as u16) | ((bus.peek(addr.wrapping_add(2)) as u16) << 8);
+
+    match opcode {
+        // JR, JR cc
+        0x18 | 0x20 | 0x28 | 0x30 | 0x38 => {
+            let offset = bus.peek(addr.wrapping_add(1)) as i8;
+            Some(addr.wrapping_add(2).wrapping_add(offset as u16))
+        },
+        // JP, JP cc, CALL, CALL cc
+        0xC3 | 0xC2 | 0xCA | 0xD2 | 0xDA | 0xCD | 0xC4 | 0xCC | 0xD4 | 0xDC => Some(word()),
+        // RST
+        0xC7 | 0xCF | 0xD7 | 0xDF | 0xE7 | 0xEF | 0xF7 | 0xFF => Some((opcode & 0x38) as u16),
+        _ => None
+    }
+}
+
+fn print_disassembly(bus: &Bus, symbols: &Symbols, addr: u16, count: usize) {
+    let mut instruction_addr = addr;
+
+    for _ in 0..count {
+        let (instruction, length) = disassemble(bus, instruction_addr);
+
+        if let Some(label) = symbols.lookup(bus, instruction_addr) {
+            println!("{}:", label);
         }
+
+        let hex = (0..length).map(|offset| format!("{:02X}", bus.peek(instruction_addr.wrapping_add(offset)))).collect::<String>();
+        let mut text = match instruction {
+            Some(instruction) => instruction.to_string(),
+            None => format!("DB {:#04X}", bus.peek(instruction_addr))
+        };
+
+        if let Some(label) = branch_target(bus, instruction_addr).and_then(|target| symbols.lookup(bus, target)) {
+            text = format!("{}\t; {}", text, label);
+        }
+
+        println!("{:#06X}\t0x{:<8}{}", instruction_addr, hex, text);
+
+        instruction_addr = instruction_addr.wrapping_add(length);
+    }
+}
+
+fn print_memory(bus: &Bus, addr: u16, count: usize) {
+    for offset in (0..count).step_by(16) {
+        let row_addr = addr.wrapping_add(offset as u16);
+        let row_count = (count - offset).min(16);
+
+        print!("{:#06X} = ", row_addr);
+
+        for i in 0..row_count {
+            print!("{:02X} ", bus.peek(row_addr.wrapping_add(i as u16)));
+        }
+
+        println!();
     }
 }
-*/
\ No newline at end of file
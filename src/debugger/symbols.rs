@@ -0,0 +1,104 @@
+use bus::Bus;
+use fnv::FnvHashMap;
+use log::warn;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const ROMX_START: u16 = 0x4000;
+const ROMX_END: u16 = 0x7FFF;
+
+// Labels loaded from an RGBDS .sym file.
+// Each line holds a bank and address followed by the label, e.g. "01:4000 Main". Comments start with ';'.
+#[derive(Default)]
+pub struct Symbols {
+    labels: FnvHashMap<(usize, u16), String>,
+    addresses: FnvHashMap<String, (usize, u16)>
+}
+
+// Path of the symbol file RGBDS writes for a ROM. It sits next to the ROM with a .sym extension.
+pub fn symbols_path(rom_path: &Path) -> PathBuf {
+    rom_path.with_extension("sym")
+}
+
+impl Symbols {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(Self::parse(&fs::read_to_string(path)?))
+    }
+
+    pub fn parse(text: &str) -> Self {
+        let mut symbols = Self::default();
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            match parse_line(line) {
+                Some((bank, addr, label)) => symbols.insert(bank, addr, label),
+                None => warn!("Skipping invalid symbol on line {}: {}", number + 1, line)
+            }
+        }
+
+        symbols
+    }
+
+    fn insert(&mut self, bank: usize, addr: u16, label: &str) {
+        // Several labels can share an address. The first one is shown in disassembly.
+        self.labels.entry((bank, addr)).or_insert_with(|| label.to_string());
+        self.addresses.insert(label.to_string(), (bank, addr));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    // Bank and address of a label
+    pub fn resolve(&self, label: &str) -> Option<(usize, u16)> {
+        self.addresses.get(label).cloned()
+    }
+
+    // Label at an address as it is currently mapped.
+    // Switchable ROM is looked up in the bank the cartridge has selected, everything else in any bank.
+    pub fn lookup(&self, bus: &Bus, addr: u16) -> Option<&str> {
+        if self.labels.is_empty() {
+            return None;
+        }
+
+        let bank = match addr {
+            0x0000..=0x3FFF => 0,
+            ROMX_START..=ROMX_END => bus.cartridge().rom_bank(),
+            _ => {
+                return self.labels.iter()
+                    .filter(|&(&(_, label_addr), _)| label_addr == addr)
+                    .min_by_key(|&(&(bank, _), _)| bank)
+                    .map(|(_, label)| label.as_str());
+            }
+        };
+
+        self.labels.get(&(bank, addr)).map(|label| label.as_str())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(usize, u16, &str)> {
+    let mut parts = line.split_whitespace();
+    let location = parts.next()?;
+    let label = parts.next()?;
+
+    let mut location = location.splitn(2, ':');
+    let bank = usize::from_str_radix(location.next()?, 16).ok()?;
+    let addr = u16::from_str_radix(location.next()?, 16).ok()?;
+
+    Some((bank, addr, label))
+}
+
+// Whether a banked address only exists while its bank is mapped
+pub fn is_banked(addr: u16) -> bool {
+    (ROMX_START..=ROMX_END).contains(&addr)
+}
@@ -0,0 +1,255 @@
+use bus::{Addressable, Bus};
+use cartridge::Cartridge;
+use emulator::Emulator;
+use super::command::Command;
+use super::gdb::{Response, Session};
+use super::{disassemble, print_disassembly, print_memory, Symbols, WatchKind, Watchpoint};
+
+fn parse(line: &str, pc: u16) -> Result<Command, String> {
+    Command::parse(line, pc, &Symbols::default())
+}
+
+#[test]
+fn parse_commands() {
+    match parse("ba 0x150", 0) {
+        Ok(Command::AddBreakPoint(0x150, None)) => { },
+        _ => panic!("Expected breakpoint at 0x150")
+    }
+
+    match parse("d", 0x200) {
+        Ok(Command::Disassemble(0x200, 10)) => { },
+        _ => panic!("Expected disassembly at PC")
+    }
+
+    match parse("m 0xC000 0b100", 0) {
+        Ok(Command::Memory(0xC000, 4)) => { },
+        _ => panic!("Expected memory dump")
+    }
+
+    match parse("s 5", 0) {
+        Ok(Command::Step(5)) => { },
+        _ => panic!("Expected 5 steps")
+    }
+
+    match parse("b", 0) {
+        Ok(Command::ListBreakPoints) => { },
+        _ => panic!("Expected breakpoint list")
+    }
+}
+
+#[test]
+fn parse_errors_instead_of_panicking() {
+    assert!(parse("ba", 0).is_err());
+    assert!(parse("ba zzz", 0).is_err());
+    assert!(parse("ba 0x10000", 0).is_err());
+    assert!(parse("m", 0).is_err());
+    assert!(parse("s 0", 0).is_err());
+    assert!(parse("x", 0).is_err());
+}
+
+#[test]
+fn disassemble_instructions() {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x107].copy_from_slice(&[0xC3, 0x50, 0x01, 0xCB, 0x7C, 0xDD, 0x00]);
+
+    let mut cartridge = Cartridge::from_vec(rom);
+    let bus = Bus::new(&mut cartridge);
+
+    let (instruction, length) = disassemble(&bus, 0x100);
+    assert_eq!(instruction.unwrap().to_string(), "JP 0x150");
+    assert_eq!(length, 3);
+
+    let (instruction, length) = disassemble(&bus, 0x103);
+    assert_eq!(instruction.unwrap().to_string(), "BIT 7, H");
+    assert_eq!(length, 2);
+
+    // Illegal opcodes are skipped over as data
+    let (instruction, length) = disassemble(&bus, 0x105);
+    assert!(instruction.is_none());
+    assert_eq!(length, 1);
+}
+
+#[test]
+fn parse_watchpoints() {
+    match parse("ww 0xC000-0xC0FF", 0) {
+        Ok(Command::AddWatchPoint(Watchpoint { start: 0xC000, end: 0xC0FF, kind: WatchKind::Write })) => { },
+        _ => panic!("Expected write watchpoint over a range")
+    }
+
+    match parse("wv 0xC010 0x42", 0) {
+        Ok(Command::AddWatchPoint(Watchpoint { start: 0xC010, end: 0xC010, kind: WatchKind::Value(0x42) })) => { },
+        _ => panic!("Expected value watchpoint")
+    }
+
+    assert!(parse("ww", 0).is_err());
+    assert!(parse("wr 0xC100-0xC000", 0).is_err());
+    assert!(parse("wv 0xC000 0x100", 0).is_err());
+}
+
+#[test]
+fn watchpoints_trigger_on_access() {
+    let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+    let mut bus = Bus::new(&mut cartridge);
+
+    bus.watchpoints.add(Watchpoint { start: 0xC000, end: 0xC00F, kind: WatchKind::Read });
+    bus.watchpoints.add(Watchpoint { start: 0xD000, end: 0xD000, kind: WatchKind::Change });
+    bus.watchpoints.add(Watchpoint { start: 0xD001, end: 0xD001, kind: WatchKind::Value(0x42) });
+
+    bus.read(0xC010);
+    assert!(bus.watchpoints.take_hit().is_none());
+
+    bus.read(0xC00F);
+    let hit = bus.watchpoints.take_hit().unwrap();
+    assert_eq!((hit.index, hit.addr, hit.write), (0, 0xC00F, false));
+
+    // Writing the value already stored isn't a change
+    bus.write(0xD000, 0);
+    assert!(bus.watchpoints.take_hit().is_none());
+
+    bus.write(0xD000, 7);
+    let hit = bus.watchpoints.take_hit().unwrap();
+    assert_eq!((hit.index, hit.old, hit.val, hit.write), (1, 0, 7, true));
+
+    bus.write(0xD001, 0x41);
+    assert!(bus.watchpoints.take_hit().is_none());
+
+    bus.write(0xD001, 0x42);
+    assert_eq!(bus.watchpoints.take_hit().unwrap().index, 2);
+}
+
+#[test]
+fn symbols_resolve_and_lookup() {
+    let symbols = Symbols::parse("; File generated by rgblink\n00:0150 Start\n01:4000 Main\n02:4000 Level\nzz:4000 Broken\nC000 NoBank\n00:C000 wBuffer\n");
+
+    assert_eq!(symbols.len(), 4);
+    assert_eq!(symbols.resolve("Main"), Some((1, 0x4000)));
+    assert_eq!(symbols.resolve("Broken"), None);
+    assert_eq!(symbols.resolve("NoBank"), None);
+
+    // Labels in switchable ROM keep their bank, others are matched at any bank
+    match Command::parse("ba Level", 0, &symbols) {
+        Ok(Command::AddBreakPoint(0x4000, Some(2))) => { },
+        _ => panic!("Expected banked breakpoint")
+    }
+
+    match Command::parse("ba Start", 0, &symbols) {
+        Ok(Command::AddBreakPoint(0x150, None)) => { },
+        _ => panic!("Expected unbanked breakpoint")
+    }
+
+    match Command::parse("ww wBuffer-0xC0FF", 0, &symbols) {
+        Ok(Command::AddWatchPoint(Watchpoint { start: 0xC000, end: 0xC0FF, .. })) => { },
+        _ => panic!("Expected watchpoint from label")
+    }
+
+    assert!(Command::parse("ba Missing", 0, &symbols).is_err());
+
+    // MBC1 ROM with 4 banks, bank 1 selected after reset
+    let mut rom = vec![0; 0x10000];
+    rom[0x147] = 0x01;
+    rom[0x148] = 0x01;
+
+    let mut cartridge = Cartridge::from_vec(rom);
+    let mut bus = Bus::new(&mut cartridge);
+
+    assert_eq!(symbols.lookup(&bus, 0x150), Some("Start"));
+    assert_eq!(symbols.lookup(&bus, 0x4000), Some("Main"));
+    assert_eq!(symbols.lookup(&bus, 0xC000), Some("wBuffer"));
+
+    bus.write(0x2000, 2);
+    assert_eq!(symbols.lookup(&bus, 0x4000), Some("Level"));
+}
+
+fn reply(session: &mut Session, packet: &str, emulator: &mut Emulator) -> String {
+    match session.handle_packet(packet, emulator) {
+        Response::Reply(reply) => reply,
+        _ => panic!("Expected a reply to {}", packet)
+    }
+}
+
+#[test]
+fn gdb_registers_and_memory() {
+    let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+    let mut emulator = Emulator::new(&mut cartridge);
+    let mut session = Session::new();
+
+    // AF, BC, DE, HL, SP, PC after reset, little endian
+    assert_eq!(reply(&mut session, "g", &mut emulator), "b0011300d8004d01feff0001");
+
+    assert_eq!(reply(&mut session, "P5=5001", &mut emulator), "OK");
+    assert_eq!(emulator.cpu().regs.pc(), 0x150);
+    assert_eq!(reply(&mut session, "p5", &mut emulator), "5001");
+
+    assert_eq!(reply(&mut session, "MC000,3:0a0b0c", &mut emulator), "OK");
+    assert_eq!(reply(&mut session, "mc000,4", &mut emulator), "0a0b0c00");
+
+    assert_eq!(reply(&mut session, "m0", &mut emulator), "E01");
+    assert_eq!(reply(&mut session, "m0,FFFFFFFF", &mut emulator), "E01");
+    assert_eq!(reply(&mut session, "M0,10001:00", &mut emulator), "E01");
+    assert_eq!(reply(&mut session, "m0,10000", &mut emulator).len(), 0x20000);
+    assert_eq!(reply(&mut session, "vMustReplyEmpty", &mut emulator), "");
+}
+
+#[test]
+fn inspecting_memory_skips_watchpoints() {
+    let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+    let mut emulator = Emulator::new(&mut cartridge);
+
+    emulator.bus_mut().watchpoints.add(Watchpoint { start: 0xC000, end: 0xC00F, kind: WatchKind::Read });
+    emulator.bus_mut().watchpoints.add(Watchpoint { start: 0xC000, end: 0xC00F, kind: WatchKind::Change });
+
+    print_memory(emulator.bus(), 0xC000, 16);
+    print_disassembly(emulator.bus(), &Symbols::default(), 0xC000, 4);
+
+    assert!(emulator.bus().watchpoints.take_hit().is_none());
+}
+
+#[test]
+fn gdb_memory_access_skips_watchpoints() {
+    let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+    let mut emulator = Emulator::new(&mut cartridge);
+    let mut session = Session::new();
+
+    emulator.bus_mut().watchpoints.add(Watchpoint { start: 0xC000, end: 0xC00F, kind: WatchKind::Read });
+    emulator.bus_mut().watchpoints.add(Watchpoint { start: 0xC000, end: 0xC00F, kind: WatchKind::Change });
+
+    assert_eq!(reply(&mut session, "MC000,1:0a", &mut emulator), "OK");
+    assert_eq!(reply(&mut session, "mc000,1", &mut emulator), "0a");
+
+    assert!(emulator.bus().watchpoints.take_hit().is_none());
+}
+
+#[test]
+fn gdb_breakpoints_and_stepping() {
+    let mut cartridge = Cartridge::from_vec(vec![0; 0x8000]);
+    let mut emulator = Emulator::new(&mut cartridge);
+    let mut session = Session::new();
+
+    assert_eq!(reply(&mut session, "Z0,102,1", &mut emulator), "OK");
+    assert_eq!(reply(&mut session, "Z2,c000,2", &mut emulator), "OK");
+    assert_eq!(emulator.bus().watchpoints.list().len(), 1);
+
+    match session.handle_packet("c", &mut emulator) {
+        Response::Resume => { },
+        _ => panic!("Expected continue to resume")
+    }
+
+    // The ROM is all NOPs, so execution runs to the breakpoint
+    let mut stops = 0;
+
+    while session.check_stop(&emulator).is_none() {
+        emulator.step_instruction();
+        stops += 1;
+    }
+
+    assert_eq!(stops, 2);
+    assert_eq!(emulator.cpu().regs.pc(), 0x102);
+
+    // A single step stops before the next instruction
+    session.handle_packet("s", &mut emulator);
+    emulator.step_instruction();
+    assert_eq!(session.check_stop(&emulator), Some(String::from("S05")));
+
+    assert_eq!(reply(&mut session, "z2,c000,2", &mut emulator), "OK");
+    assert!(emulator.bus().watchpoints.list().is_empty());
+}
@@ -0,0 +1,129 @@
+use std::cell::Cell;
+use std::fmt;
+
+#[derive(Copy, Clone, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    Change,                     // Write that changes the stored value
+    Value(u8)                   // Write of a specific value
+}
+
+#[derive(Copy, Clone)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16,               // Inclusive
+    pub kind: WatchKind
+}
+
+impl Watchpoint {
+    fn contains(&self, addr: u16) -> bool {
+        addr >= self.start && addr <= self.end
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            WatchKind::Read => write!(f, "read")?,
+            WatchKind::Write => write!(f, "write")?,
+            WatchKind::Change => write!(f, "change")?,
+            WatchKind::Value(val) => write!(f, "write {:#04X}", val)?
+        };
+
+        if self.start == self.end {
+            write!(f, " {:#06X}", self.start)
+        } else {
+            write!(f, " {:#06X}-{:#06X}", self.start, self.end)
+        }
+    }
+}
+
+// A memory access that triggered a watchpoint
+#[derive(Copy, Clone)]
+pub struct WatchHit {
+    pub index: usize,           // Index of the watchpoint that triggered
+    pub addr: u16,
+    pub old: u8,                // Value before the access. Same as val for reads.
+    pub val: u8,
+    pub write: bool
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.write {
+            write!(f, "Watchpoint {}: wrote {:#04X} to {:#06X} (was {:#04X})", self.index, self.val, self.addr, self.old)
+        } else {
+            write!(f, "Watchpoint {}: read {:#04X} from {:#06X}", self.index, self.val, self.addr)
+        }
+    }
+}
+
+// Watchpoints checked by the bus on every read and write.
+// Reads go through a shared reference, so the hit is recorded in a Cell for the debugger to pick up.
+#[derive(Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    hit: Cell<Option<WatchHit>>
+}
+
+impl Watchpoints {
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    #[inline(always)]
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn check_read(&self, addr: u16, val: u8) {
+        self.check(addr, val, val, false);
+    }
+
+    pub fn check_write(&self, addr: u16, old: u8, val: u8) {
+        self.check(addr, old, val, true);
+    }
+
+    // Returns the first access that triggered a watchpoint since the last call
+    pub fn take_hit(&self) -> Option<WatchHit> {
+        self.hit.take()
+    }
+
+    fn check(&self, addr: u16, old: u8, val: u8, write: bool) {
+        // Keep the first hit. Later accesses by the same instruction shouldn't hide it.
+        if self.hit.get().is_some() {
+            return;
+        }
+
+        for (index, watchpoint) in self.list.iter().enumerate() {
+            if !watchpoint.contains(addr) {
+                continue;
+            }
+
+            let triggered = match watchpoint.kind {
+                WatchKind::Read => !write,
+                WatchKind::Write => write,
+                WatchKind::Change => write && old != val,
+                WatchKind::Value(expected) => write && val == expected
+            };
+
+            if triggered {
+                self.hit.set(Some(WatchHit { index, addr, old, val, write }));
+                return;
+            }
+        }
+    }
+}
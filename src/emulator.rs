@@ -0,0 +1,384 @@
+use bus::{Addressable, Bus};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use cartridge::Cartridge;
+use cpu::Cpu;
+use joypad::Button;
+use savestate::{self, Savestate};
+use sound::{AudioSink, Resampler};
+use std::io::{self, Read, Write};
+use trace::{TraceFilter, Tracer};
+use video::Recorder;
+
+// Number of clock cycles the LCD takes to draw all 154 lines of a frame
+pub const CYCLES_PER_FRAME: usize = 70224;
+
+#[derive(Default)]
+pub struct StepResult {
+    pub cycles: usize,          // Clock cycles used by the executed instruction
+    pub vblank: bool,           // LCD entered VBlank, the frame buffer holds a complete frame
+    pub serial: Option<u8>      // Byte sent out the serial port
+}
+
+// Headless Gameboy. Owns the CPU and the bus with everything attached to it.
+// Frontends feed it button presses and read back the frame buffer.
+pub struct Emulator<'a> {
+    bus: Bus<'a>,
+    cpu: Cpu,
+    audio_sinks: Vec<(Resampler, Box<dyn AudioSink + 'a>)>,
+    tracer: Option<Tracer<'a>>,
+    recorder: Option<Recorder<'a>>
+}
+
+impl<'a> Emulator<'a> {
+    pub fn new(cartridge: &'a mut Cartridge) -> Self {
+        let mut emulator = Self {
+            bus: Bus::new(cartridge),
+            cpu: Cpu::new(),
+            audio_sinks: Vec::new(),
+            tracer: None,
+            recorder: None
+        };
+
+        emulator.reset();
+        emulator
+    }
+
+    // Set the CPU to initial values and clear the screen
+    pub fn reset(&mut self) {
+        self.bus.clear_framebuffer();
+        self.cpu.reset();
+        self.bus.sound.reset();
+    }
+
+    // Starts from power on and runs the boot ROM, instead of starting in the state the boot ROM leaves behind
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        self.bus.map_boot_rom(boot_rom)?;
+        self.cpu = Cpu::new();
+
+        // The boot ROM switches the LCD and APU on and sets the palette itself
+        self.bus.lcd.write(0xFF40, 0);
+        self.bus.lcd.write(0xFF47, 0);
+        self.bus.sound.write(0xFF26, 0);
+
+        Ok(())
+    }
+
+    // Executes the next CPU instruction. The rest of the hardware is stepped by the bus as the CPU accesses memory.
+    pub fn step_instruction(&mut self) -> StepResult {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.trace(&self.cpu, &self.bus);
+        }
+
+        // Execute the next CPU instruction. The number of cycles used is returned.
+        let cycles = self.cpu.step(&mut self.bus);
+        let events = self.bus.take_events();
+
+        if !self.audio_sinks.is_empty() {
+            let output = self.bus.sound.output();
+
+            for (resampler, sink) in self.audio_sinks.iter_mut() {
+                resampler.step(cycles, output, sink.as_mut());
+            }
+        }
+
+        // Record the finished frame and the sound that played with it
+        if let Some(recorder) = self.recorder.as_mut() {
+            recorder.step(cycles, self.bus.sound.output(), events.vblank, self.bus.framebuffer());
+        }
+
+        StepResult {
+            cycles,
+            vblank: events.vblank,
+            serial: events.serial
+        }
+    }
+
+    // Runs until the LCD enters VBlank. Returns the number of cycles used.
+    // If the LCD is switched off VBlank never comes, so give up after a frame's worth of cycles.
+    pub fn run_frame(&mut self) -> usize {
+        self.run_frame_with(|_| true)
+    }
+
+    // Same as run_frame, but calls hook before every instruction.
+    // If the hook returns false the frame ends early without executing the instruction.
+    pub fn run_frame_with<F>(&mut self, mut hook: F) -> usize where F: FnMut(&mut Self) -> bool {
+        let mut cycles = 0;
+
+        while cycles < CYCLES_PER_FRAME {
+            if !hook(self) {
+                break;
+            }
+
+            let result = self.step_instruction();
+            cycles += result.cycles;
+
+            if result.vblank {
+                break;
+            }
+        }
+
+        cycles
+    }
+
+    // 160x144 RGB pixels of the most recently drawn frame
+    pub fn framebuffer(&self) -> &[u32] {
+        self.bus.framebuffer()
+    }
+
+    // Buttons currently held down. These are picked up by the joypad on the next step.
+    pub fn set_buttons(&mut self, buttons: Button) {
+        self.bus.set_buttons(buttons);
+    }
+
+    // Sends the APU output to a sink, resampled to the sink's sample rate
+    pub fn add_audio_sink(&mut self, sink: Box<dyn AudioSink + 'a>) {
+        let resampler = Resampler::new(sink.sample_rate());
+        self.audio_sinks.push((resampler, sink));
+    }
+
+    // Lets every audio sink wrap up. Returns the first error any of them reports.
+    pub fn finish_audio(&mut self) -> io::Result<()> {
+        let mut result = Ok(());
+
+        for (_, sink) in self.audio_sinks.iter_mut() {
+            let sink_result = sink.finish();
+
+            if result.is_ok() {
+                result = sink_result;
+            }
+        }
+
+        result
+    }
+
+    // Logs the CPU state before every instruction that passes the filter
+    pub fn set_trace(&mut self, out: Box<dyn Write + 'a>, filter: TraceFilter) {
+        self.tracer = Some(Tracer::new(out, filter));
+    }
+
+    // Flushes the trace log. Returns the first error hit while writing it.
+    pub fn finish_trace(&mut self) -> io::Result<()> {
+        match self.tracer.as_mut() {
+            Some(tracer) => tracer.finish(),
+            None => Ok(())
+        }
+    }
+
+    // Starts recording video, replacing any recording in progress without finishing it
+    pub fn start_recording(&mut self, recorder: Recorder<'a>) {
+        self.recorder = Some(recorder);
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recorder.is_some()
+    }
+
+    // Ends the recording. Returns the first error hit while writing it.
+    pub fn stop_recording(&mut self) -> io::Result<()> {
+        match self.recorder.take() {
+            Some(mut recorder) => recorder.finish(),
+            None => Ok(())
+        }
+    }
+
+    // Writes a snapshot of the whole machine
+    pub fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_header(out)?;
+        out.write_u16::<LittleEndian>(self.bus.cartridge().checksum())?;
+
+        self.cpu.save_state(out)?;
+        self.bus.save_state(out)
+    }
+
+    // Restores a snapshot written by save_state.
+    // If the snapshot can't be read the machine is left as it was.
+    pub fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        let mut backup = Vec::new();
+        self.save_state(&mut backup)?;
+
+        let result = self.read_state(input);
+
+        if result.is_err() {
+            self.read_state(&mut backup.as_slice()).expect("Unable to restore machine state");
+        }
+
+        result
+    }
+
+    fn read_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        savestate::read_header(input)?;
+
+        if input.read_u16::<LittleEndian>()? != self.bus.cartridge().checksum() {
+            return Err(savestate::invalid_data("Save state belongs to a different ROM"));
+        }
+
+        self.cpu.load_state(input)?;
+        self.bus.load_state(input)
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    pub fn bus(&self) -> &Bus<'a> {
+        &self.bus
+    }
+
+    pub fn bus_mut(&mut self) -> &mut Bus<'a> {
+        &mut self.bus
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn run_frame_stops_at_vblank() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        let cycles = emulator.run_frame();
+
+        assert!(cycles < CYCLES_PER_FRAME);
+        // STAT reports mode 1 (VBlank)
+        assert_eq!(emulator.bus().lcd.read(0xFF41) & 0b11, 1);
+    }
+
+    #[test]
+    fn run_frame_without_lcd() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        emulator.bus_mut().lcd.write(0xFF40, 0);
+
+        assert!(emulator.run_frame() >= CYCLES_PER_FRAME);
+    }
+
+    #[test]
+    fn stop_halts_the_hardware() {
+        // STOP 0; NOP
+        let mut rom = vec![0; 65536];
+        rom[0x100] = 0x10;
+
+        let mut cartridge = Cartridge::from_vec(rom);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        // Select the d-pad
+        emulator.bus_mut().write(0xFF00, 0x20);
+        emulator.step_instruction();
+
+        let ly = emulator.bus().read(0xFF44);
+        emulator.run_frame();
+
+        assert_eq!(emulator.bus().read(0xFF04), 0);
+        assert_eq!(emulator.bus().read(0xFF44), ly);
+        assert_eq!(emulator.cpu().regs.pc(), 0x102);
+
+        // Pressing a button wakes it up again
+        emulator.set_buttons(Button::RIGHT);
+        emulator.step_instruction();
+        emulator.step_instruction();
+
+        assert_eq!(emulator.cpu().regs.pc(), 0x103);
+    }
+
+    #[test]
+    fn boot_rom_unmaps_itself() {
+        let mut rom = vec![0; 65536];
+        rom[0] = 0xAA;
+
+        let mut cartridge = Cartridge::from_vec(rom);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        assert_eq!(emulator.bus().read(0xFF26), 0xF1);
+        assert!(emulator.load_boot_rom(vec![0; 0x900]).is_err());
+
+        // LD A,1; LDH (0x50),A
+        let mut boot_rom = vec![0; 0x100];
+        boot_rom[..4].copy_from_slice(&[0x3E, 0x01, 0xE0, 0x50]);
+
+        emulator.load_boot_rom(boot_rom).unwrap();
+        assert_eq!(emulator.cpu().regs.pc(), 0);
+        assert_eq!(emulator.bus().read(0), 0x3E);
+        assert_eq!(emulator.bus().lcd.read(0xFF40), 0);
+        assert_eq!(emulator.bus().read(0xFF26), 0x70);
+
+        emulator.step_instruction();
+        emulator.step_instruction();
+
+        assert!(!emulator.bus().boot_rom_mapped());
+        assert_eq!(emulator.bus().read(0), 0xAA);
+        assert_eq!(emulator.cpu().regs.pc(), 4);
+    }
+
+    #[test]
+    fn load_state_restores_machine() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        emulator.bus_mut().write(0xC000, 0x42);
+        emulator.cpu_mut().regs.set_bc(0x1234);
+
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+
+        emulator.run_frame();
+        emulator.bus_mut().write(0xC000, 0);
+        emulator.cpu_mut().regs.set_bc(0);
+
+        emulator.load_state(&mut state.as_slice()).unwrap();
+
+        assert_eq!(emulator.bus().read(0xC000), 0x42);
+        assert_eq!(emulator.cpu().regs.bc(), 0x1234);
+        assert_eq!(emulator.cpu().regs.pc(), 0x100);
+    }
+
+    #[test]
+    fn load_state_rejects_bad_data() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+
+        emulator.cpu_mut().regs.set_bc(0x1234);
+
+        // Truncated file
+        assert!(emulator.load_state(&mut &state[..state.len() / 2]).is_err());
+        assert_eq!(emulator.cpu().regs.bc(), 0x1234);
+
+        // Wrong version
+        state[4] = 0xFF;
+        assert!(emulator.load_state(&mut state.as_slice()).is_err());
+    }
+
+    #[test]
+    fn load_state_rejects_corrupt_dma_source() {
+        let mut cartridge = Cartridge::from_vec(vec![0; 65536]);
+        let mut emulator = Emulator::new(&mut cartridge);
+
+        emulator.bus_mut().write(0xFF46, 0xC0);
+        emulator.step_instruction();
+
+        let mut state = Vec::new();
+        emulator.save_state(&mut state).unwrap();
+
+        // The DMA source follows the header, ROM checksum, CPU and IE, IF, boot ROM and DMA registers
+        let mut cpu_state = Vec::new();
+        emulator.cpu().save_state(&mut cpu_state).unwrap();
+        let offset = 8 + cpu_state.len() + 4;
+        state[offset] = 0xFF;
+        state[offset + 1] = 0xFF;
+
+        let err = emulator.load_state(&mut state.as_slice()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // The machine is left as it was and keeps running
+        emulator.run_frame();
+    }
+}
@@ -0,0 +1,330 @@
+use rustboy::{AudioSink, Button, Cartridge, Emulator, TraceFilter, WavWriter, SCREEN_WIDTH, SCREEN_HEIGHT};
+use rustboy::debugger::{Action, BreakHandler};
+use rustboy::cartridge::battery_path;
+use rustboy::screenshot::{self, Image};
+use rustboy::video::Recorder;
+use log::{error, info};
+use minifb::{Key, KeyRepeat, Scale, WindowOptions, Window};
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, Duration, SystemTime};
+use std::thread;
+
+const MS_PER_FRAME: u128 = 16;
+
+// F1-F4 save the state to slots 1-4. F5-F8 load them back.
+const SAVE_STATE_KEYS: [Key; 4] = [Key::F1, Key::F2, Key::F3, Key::F4];
+const LOAD_STATE_KEYS: [Key; 4] = [Key::F5, Key::F6, Key::F7, Key::F8];
+
+const SCREENSHOT_KEY: Key = Key::F12;
+const RECORD_KEY: Key = Key::F11;
+
+// Desktop frontend. Runs the emulator in a minifb window.
+pub struct Rustboy<'a> {
+    options: RustboyOptions,
+    emulator: Emulator<'a>,
+    debugger: Option<Box<dyn BreakHandler>>,
+    rom_path: PathBuf,
+    window: Window
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RustboyOptions {
+    pub scale: Scale,
+    pub unlock_fps: bool,
+    pub screenshot_scale: usize,    // Screenshots are enlarged by this factor
+    pub audio_rate: u32             // Sample rate of the sound in recordings
+}
+
+impl<'a> Rustboy<'a> {
+    pub fn new(cartridge: &'a mut Cartridge, rom_path: &Path, options: RustboyOptions) -> Self {
+        Self {
+            options,
+            emulator: Emulator::new(cartridge),
+            debugger: None,
+            rom_path: rom_path.to_path_buf(),
+            window: create_window(options.scale)
+        }
+    }
+
+    // Runs the boot ROM before the game, like switching on a real Gameboy
+    pub fn load_boot_rom(&mut self, boot_rom: Vec<u8>) -> Result<(), String> {
+        self.emulator.load_boot_rom(boot_rom)
+    }
+
+    // Hands control to the debugger whenever it asks for it. The emulation loop pauses while it has control.
+    pub fn set_debugger(&mut self, debugger: Box<dyn BreakHandler>) {
+        self.debugger = Some(debugger);
+    }
+
+    // Sends the emulator's sound output to a sink. Sinks are finished when the window closes.
+    pub fn add_audio_sink(&mut self, sink: Box<dyn AudioSink + 'a>) {
+        self.emulator.add_audio_sink(sink);
+    }
+
+    // Logs every executed instruction. The log is flushed when the window closes.
+    pub fn set_trace(&mut self, out: Box<dyn Write + 'a>, filter: TraceFilter) {
+        self.emulator.set_trace(out, filter);
+    }
+
+    // Records video to a .y4m file and the sound next to it in a .wav file of the same name
+    pub fn start_recording(&mut self, path: &Path) -> io::Result<()> {
+        let wav_path = path.with_extension("wav");
+
+        let video = BufWriter::new(File::create(path)?);
+        let audio = WavWriter::new(BufWriter::new(File::create(&wav_path)?), self.options.audio_rate)?;
+
+        self.emulator.start_recording(Recorder::new(Box::new(video), Some(Box::new(audio)))?);
+        info!("Recording to {} and {}", path.display(), wav_path.display());
+
+        Ok(())
+    }
+
+    pub fn run(&mut self) {
+        // Clear the window
+        self.window.update_with_buffer(self.emulator.framebuffer()).expect("Unable to render window");
+
+        // FPS counter variables
+        let mut fps_counter_time = Instant::now();
+        let mut fps_counter_frames = 0;
+
+        let mut time_since_last_frame = Instant::now();
+
+        // Games that run into an illegal opcode freeze, so say why once it happens
+        let mut reported_lockup = None;
+
+        while self.window.is_open() && !self.window.is_key_down(Key::Escape) {
+            // Run the emulator until the LCD reaches VBLANK.
+            // We'll use this time to update the framebuffer and FPS counter. Also we'll get the current pressed buttons
+            if !self.run_frame() {
+                break;
+            }
+
+            self.window.update_with_buffer(self.emulator.framebuffer()).unwrap();
+
+            let lockup = self.emulator.cpu().lockup();
+
+            if lockup != reported_lockup {
+                if let Some(lockup) = lockup {
+                    error!("{}", lockup);
+                }
+
+                reported_lockup = lockup;
+            }
+
+            let elapsed = time_since_last_frame.elapsed();
+
+            if !self.options.unlock_fps && elapsed.as_millis() < MS_PER_FRAME {
+                // Sleep for the remaining time
+                thread::sleep(Duration::from_millis((MS_PER_FRAME - elapsed.as_millis()) as u64))
+            }
+
+            time_since_last_frame = Instant::now();
+
+            fps_counter_frames += 1;
+
+            // If a second has passed, update the FPS counter
+            let elapsed = fps_counter_time.elapsed();
+
+            if elapsed.as_secs() > 0 {
+                match lockup {
+                    Some(lockup) => self.window.set_title(format!("Rustboy ({} FPS) - {}", fps_counter_frames, lockup).as_str()),
+                    None => self.window.set_title(format!("Rustboy ({} FPS)", fps_counter_frames).as_str())
+                }
+                fps_counter_time = Instant::now();
+                fps_counter_frames = 0;
+
+                // Also flush the battery save once a second so a crash doesn't lose progress
+                self.save_battery();
+            }
+
+            let buttons = self.button_presses();
+            self.emulator.set_buttons(buttons);
+
+            self.handle_save_state_keys();
+
+            if self.window.is_key_pressed(SCREENSHOT_KEY, KeyRepeat::No) {
+                self.save_screenshot();
+            }
+
+            if self.window.is_key_pressed(RECORD_KEY, KeyRepeat::No) {
+                self.toggle_recording();
+            }
+        }
+
+        self.save_battery();
+
+        if let Err(err) = self.emulator.finish_audio() {
+            error!("Unable to finish writing audio: {}", err);
+        }
+
+        self.stop_recording();
+
+        if let Err(err) = self.emulator.finish_trace() {
+            error!("Unable to write trace: {}", err);
+        }
+    }
+
+    // Runs the emulator to the next frame, handing control to the debugger when it asks for it.
+    // Returns false if the debugger quit.
+    fn run_frame(&mut self) -> bool {
+        match self.debugger {
+            Some(ref mut debugger) => {
+                let mut quit = false;
+
+                self.emulator.run_frame_with(|emulator| {
+                    if debugger.should_break(emulator) {
+                        if let Action::Quit = debugger.brk(emulator) {
+                            quit = true;
+                        }
+                    }
+
+                    !quit
+                });
+
+                !quit
+            },
+            None => {
+                self.emulator.run_frame();
+                true
+            }
+        }
+    }
+
+    // Writes the cartridge RAM to the .sav file if the game has changed it
+    fn save_battery(&mut self) {
+        let path = battery_path(&self.rom_path);
+        let cartridge = self.emulator.bus_mut().cartridge_mut();
+
+        if cartridge.battery() && cartridge.ram_dirty() {
+            if let Err(err) = cartridge.save_battery_file(&path) {
+                error!("Unable to write battery save {}: {}", path.display(), err);
+            }
+        }
+    }
+
+    fn handle_save_state_keys(&mut self) {
+        for slot in 0..SAVE_STATE_KEYS.len() {
+            if self.window.is_key_pressed(SAVE_STATE_KEYS[slot], KeyRepeat::No) {
+                self.save_state(slot + 1);
+            } else if self.window.is_key_pressed(LOAD_STATE_KEYS[slot], KeyRepeat::No) {
+                self.load_state(slot + 1);
+            }
+        }
+    }
+
+    // Save states are stored next to the ROM as <rom>.ss1, <rom>.ss2, etc
+    fn save_state_path(&self, slot: usize) -> PathBuf {
+        self.rom_path.with_extension(format!("ss{}", slot))
+    }
+
+    fn save_state(&self, slot: usize) {
+        let path = self.save_state_path(slot);
+
+        let result = File::create(&path).and_then(|file| {
+            let mut out = BufWriter::new(file);
+            self.emulator.save_state(&mut out)?;
+            out.flush()
+        });
+
+        match result {
+            Ok(_) => info!("Saved state to slot {} ({})", slot, path.display()),
+            Err(err) => error!("Unable to save state to {}: {}", path.display(), err)
+        }
+    }
+
+    fn load_state(&mut self, slot: usize) {
+        let path = self.save_state_path(slot);
+        let emulator = &mut self.emulator;
+
+        let result = File::open(&path).and_then(|file| {
+            emulator.load_state(&mut BufReader::new(file))
+        });
+
+        match result {
+            Ok(_) => info!("Loaded state from slot {} ({})", slot, path.display()),
+            Err(err) => error!("Unable to load state from {}: {}", path.display(), err)
+        }
+    }
+
+    fn save_screenshot(&self) {
+        let path = screenshot::timestamped_path(&self.rom_path, SystemTime::now(), "png");
+        let image = Image::from_frame(self.emulator.framebuffer()).scale(self.options.screenshot_scale);
+
+        match File::create(&path).and_then(|file| screenshot::write_png(BufWriter::new(file), &image)) {
+            Ok(_) => info!("Saved screenshot to {}", path.display()),
+            Err(err) => error!("Unable to save screenshot to {}: {}", path.display(), err)
+        }
+    }
+
+    // Recordings started with the hotkey are named after the ROM and the time they started
+    fn toggle_recording(&mut self) {
+        if self.emulator.is_recording() {
+            self.stop_recording();
+            return;
+        }
+
+        let path = screenshot::timestamped_path(&self.rom_path, SystemTime::now(), "y4m");
+
+        if let Err(err) = self.start_recording(&path) {
+            error!("Unable to start recording to {}: {}", path.display(), err);
+        }
+    }
+
+    fn stop_recording(&mut self) {
+        if !self.emulator.is_recording() {
+            return;
+        }
+
+        match self.emulator.stop_recording() {
+            Ok(_) => info!("Recording stopped"),
+            Err(err) => error!("Unable to finish recording: {}", err)
+        }
+    }
+
+    fn button_presses(&self) -> Button {
+        let mut buttons = Button::empty();
+
+        if self.window.is_key_down(Key::Enter) {
+            buttons |= Button::START;
+        }
+
+        if self.window.is_key_down(Key::RightShift) || self.window.is_key_down(Key::LeftShift) {
+            buttons |= Button::SELECT;
+        }
+
+        if self.window.is_key_down(Key::Up) {
+            buttons |= Button::UP;
+        } else if self.window.is_key_down(Key::Down) {
+            buttons |= Button::DOWN;
+        }
+
+        if self.window.is_key_down(Key::Right) {
+            buttons |= Button::RIGHT;
+        } else if self.window.is_key_down(Key::Left) {
+            buttons |= Button::LEFT;
+        }
+
+        if self.window.is_key_down(Key::Z) {
+            buttons |= Button::B;
+        }
+
+        if self.window.is_key_down(Key::X) {
+            buttons |= Button::A;
+        }
+
+        buttons
+    }
+}
+
+fn create_window(scale: Scale) -> Window {
+    Window::new(
+        "Rustboy",
+        SCREEN_WIDTH,
+        SCREEN_HEIGHT,
+        WindowOptions {
+            scale,
+            ..WindowOptions::default()
+        }).expect("Unable to create window")
+}
@@ -1,4 +1,7 @@
 use bus::Addressable;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use savestate::Savestate;
+use std::io::{self, Read, Write};
 
 bitflags! {
     struct Pin: u8 {
@@ -34,6 +37,12 @@ pub struct Joypad {
     pins: Pin
 }
 
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Joypad {
     pub fn new() -> Self {
         Self {
@@ -41,6 +50,11 @@ impl Joypad {
         }
     }
 
+    // A button in a selected row is held down
+    pub fn pressed(&self) -> bool {
+        self.pins.intersects(Pin::PIN_10 | Pin::PIN_11 | Pin::PIN_12 | Pin::PIN_13)
+    }
+
     pub fn step(&mut self, buttons: Button) -> StepResult {
         let mut result = StepResult::default();
         let previous = self.pins;
@@ -104,4 +118,16 @@ impl Addressable for Joypad {
         // Only allow bits 5 and 4 to be written to
         self.pins.bits = (!val & 0b11_0000) | (self.pins.bits & 0b00_1111)
     }
-}
\ No newline at end of file
+}
+
+impl Savestate for Joypad {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.pins.bits)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.pins = Pin::from_bits_truncate(input.read_u8()?);
+
+        Ok(())
+    }
+}
@@ -1,6 +1,10 @@
 use bus::{Addressable, OAM_START, OAM_END, VIDEO_RAM_START, VIDEO_RAM_END};
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use enum_primitive::FromPrimitive;
 use log::{error, warn};
+use savestate::{self, Savestate};
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
 
 pub const SCREEN_WIDTH: usize = 160;
 pub const SCREEN_HEIGHT: usize = 144;
@@ -58,10 +62,26 @@ enum_from_primitive! {
 }
 
 const CYCLES_PER_OAM_READ: usize = 80;
-const CYCLES_PER_TRANSFER: usize = 172;
-const CYCLES_PER_HBLANK: usize = 204;
 const CYCLES_PER_LINE: usize = 456;
 
+// Each step of the pixel fetcher takes 2 cycles. Fetching a row of a sprite takes 6.
+const CYCLES_PER_FETCH_STEP: usize = 2;
+const CYCLES_PER_SPRITE_FETCH: usize = 6;
+
+const MAX_SPRITES_PER_LINE: usize = 10;
+
+// The pixel fetcher reads a row of 8 pixels from VRAM and pushes it into the background FIFO.
+// Pushing waits until the FIFO is empty.
+enum_from_primitive! {
+    #[derive(Copy, Clone, PartialEq)]
+    enum FetchStep {
+        TileNumber = 0,
+        DataLow    = 1,
+        DataHigh   = 2,
+        Push       = 3
+    }
+}
+
 
 
 // DMG can only display four beautiful shades of color
@@ -96,15 +116,18 @@ impl Palette {
         let shade_index = (self.0 >> (idx * 2)) & 0b11;
 
         match Shade::from_u8(shade_index).unwrap() {
-            Shade::White        => 0x9CBD0F,
-            Shade::LightGray    => 0x8CAD0F,
-            Shade::DarkGray     => 0x306230,
-            Shade::Black        => 0x0F380F
+            Shade::White        => SHADES[0],
+            Shade::LightGray    => SHADES[1],
+            Shade::DarkGray     => SHADES[2],
+            Shade::Black        => SHADES[3]
         }
     }
 }
 
-const WHITE_RGB: u32 = 0x9CBD0F;
+// RGB values the four shades are drawn with, from white to black
+pub const SHADES: [u32; 4] = [0x9CBD0F, 0x8CAD0F, 0x306230, 0x0F380F];
+
+const WHITE_RGB: u32 = SHADES[0];
 
 // Represents an OAM (Sprite data)
 #[derive(Copy, Clone, Debug)]
@@ -137,6 +160,57 @@ bitflags! {
     }
 }
 
+// A sprite pixel waiting in the sprite FIFO
+#[derive(Copy, Clone)]
+struct SpritePixel {
+    color: ColorIndex,
+    obp1: bool,    // Uses OBP1 instead of OBP0
+    priority: bool // Hidden behind the background
+}
+
+impl SpritePixel {
+    fn to_byte(self) -> u8 {
+        self.color | ((self.obp1 as u8) << 2) | ((self.priority as u8) << 3)
+    }
+
+    fn from_byte(val: u8) -> Self {
+        Self {
+            color: val & 0b11,
+            obp1: val & 0b100 != 0,
+            priority: val & 0b1000 != 0
+        }
+    }
+}
+
+// State of the background/window pixel fetcher
+struct Fetcher {
+    step: FetchStep,
+    cycles: usize,  // Cycles spent on the current step
+    x: u8,          // Tile column being fetched, counted from the left of the line or window
+    tile: u8,       // Tile number read from the tile map
+    row: u8,        // Row within the tile
+    low: u8,        // Tile data
+    high: u8,
+    window: bool,   // Fetching window tiles instead of background
+    warmup: bool    // The first tile of a line is fetched twice and the first fetch is thrown away
+}
+
+impl Fetcher {
+    fn new() -> Self {
+        Self {
+            step: FetchStep::TileNumber,
+            cycles: 0,
+            x: 0,
+            tile: 0,
+            row: 0,
+            low: 0,
+            high: 0,
+            window: false,
+            warmup: true
+        }
+    }
+}
+
 #[derive(Default)]
 pub struct StepResult {
     pub int_vblank: bool,
@@ -158,8 +232,25 @@ pub struct Lcd {
     wy: u8,
     wx: u8,
     mode: Mode,
-    mode_cycles: usize,
-    draw_pending: bool
+    line_cycles: usize,          // Cycles into the current line
+    lx: u8,                      // Pixels drawn on the current line
+    discard: u8,                 // Background pixels still to throw away before drawing
+    bg_line: [ColorIndex; SCREEN_WIDTH], // Background and window color indexes drawn on the current line
+    bg_fifo: VecDeque<ColorIndex>,
+    sprite_fifo: VecDeque<SpritePixel>,
+    fetcher: Fetcher,
+    line_sprites: Vec<u8>,       // OAM indexes of the sprites found on the current line that haven't been fetched yet
+    sprite_fetch: Option<u8>,    // OAM index of the sprite being fetched
+    sprite_cycles: usize,
+    window_line: u8,             // Window row to draw next. Only counts lines the window was drawn on.
+    window_latched: bool,        // WY has matched LY this frame
+    window_wrap: bool            // WX=166 on the last line, so the window covers all of this one
+}
+
+impl Default for Lcd {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Lcd {
@@ -179,8 +270,36 @@ impl Lcd {
             wy: 0,
             wx: 0,
             mode: Mode::Oam,
-            mode_cycles: 0,
-            draw_pending: false
+            line_cycles: 0,
+            lx: 0,
+            discard: 0,
+            bg_line: [0; SCREEN_WIDTH],
+            bg_fifo: VecDeque::with_capacity(8),
+            sprite_fifo: VecDeque::with_capacity(8),
+            fetcher: Fetcher::new(),
+            line_sprites: Vec::with_capacity(MAX_SPRITES_PER_LINE),
+            sprite_fetch: None,
+            sprite_cycles: 0,
+            window_line: 0,
+            window_latched: false,
+            window_wrap: false
+        }
+    }
+
+    // OAM DMA writes straight to OAM, whatever mode the LCD is in
+    pub fn dma_write(&mut self, index: usize, val: u8) {
+        self.write_oam(index, val);
+    }
+
+    fn write_oam(&mut self, index: usize, val: u8) {
+        let entry = &mut self.oam[index / 4];
+
+        match index % 4 {
+            0 => { entry.y = val },
+            1 => { entry.x = val },
+            2 => { entry.tile = val },
+            3 => { entry.attrs = OamAttr::from_bits(val).unwrap() },
+            _ => unreachable!()
         }
     }
 
@@ -188,262 +307,373 @@ impl Lcd {
         let mut result = StepResult::default();
 
         if self.lcdc.contains(Lcdc::LCDC_ENABLED) {
-            let previous_mode = self.mode;
-            self.mode_cycles += cycles;
-
-            match self.mode {
-                Mode::Oam => {
-                    // Process starts with the LCD controller reading information from OAM.
-                    // This lasts 77-83 cycles
-                    if self.mode_cycles >= CYCLES_PER_OAM_READ {
-                        // Move onto transfer stage
-                        self.mode = Mode::Transfer;
-                        self.mode_cycles -= CYCLES_PER_OAM_READ;
-                        self.draw_pending = true;
-                    }
-                },
-                Mode::Transfer => {
-                    // Data is being actively read by the LCD driver from OAM & VRAM.
-                    // This lasts 169-175 cycles
-                    if self.mode_cycles >= CYCLES_PER_TRANSFER {
-                        // Once transfer is complete, enter HBLANK
-                        self.mode = Mode::HBlank;
-                        self.mode_cycles -= CYCLES_PER_TRANSFER;
-                    } else if self.draw_pending {
-                        self.draw_background_current_line(screen_buffer);
-                        self.draw_window_current_line(screen_buffer);
-                        self.draw_sprites_current_line(screen_buffer);
-
-                        self.draw_pending = false;
+            // The LCD moves on by one dot every cycle
+            for _ in 0..cycles {
+                self.step_dot(screen_buffer, &mut result);
+            }
+        }
+
+        result
+    }
+
+    fn step_dot(&mut self, screen_buffer: &mut [u32], result: &mut StepResult) {
+        let previous_mode = self.mode;
+        self.line_cycles += 1;
+
+        match self.mode {
+            Mode::Oam => {
+                // Process starts with the LCD controller searching OAM for the sprites on this line.
+                // An entry is checked every 2 cycles, so this lasts 80 cycles
+                if self.line_cycles == 1 && self.ly == self.wy {
+                    // Window can be shown for the rest of the frame once WY matches LY at the start of a line
+                    self.window_latched = true;
+                }
+
+                if self.line_cycles.is_multiple_of(2) {
+                    self.scan_oam_entry((self.line_cycles / 2 - 1) as u8);
+                }
+
+                if self.line_cycles == CYCLES_PER_OAM_READ {
+                    // Move onto transfer stage
+                    self.start_transfer();
+                    self.mode = Mode::Transfer;
+                }
+            },
+            Mode::Transfer => {
+                // Pixels are fetched from VRAM and shifted out to the screen one at a time.
+                // This lasts 172 cycles, plus the time taken by fine scrolling, the window and sprites
+                self.transfer_dot(screen_buffer);
+
+                if self.lx as usize == SCREEN_WIDTH {
+                    // Once the line is drawn, enter HBLANK
+                    self.mode = Mode::HBlank;
+
+                    if self.fetcher.window {
+                        self.window_line = self.window_line.wrapping_add(1);
                     }
-                },
-                Mode::HBlank => {
-                    // LCD has reached the end of a line.
-                    // HBlank phase lasts 201-207 cycles
-                    if self.mode_cycles >= CYCLES_PER_HBLANK {
-                        // LCD is ready to begin the next line
-                        self.ly += 1;
-                        self.mode_cycles -= CYCLES_PER_HBLANK;
-
-                        // If the LCD has line 144, then enter VBLANK. Else, enter OAM read.
-                        if self.ly == 144 { 
-                            self.mode = Mode::VBlank;
-                            result.int_vblank = true;
-                        } else { 
-                            self.mode = Mode::Oam;
-
-                            self.check_coincidence(&mut result);
-                        };
+
+                    self.window_wrap = self.window_enabled() && self.wx == 166;
+                }
+            },
+            Mode::HBlank => {
+                // LCD has reached the end of a line.
+                // HBlank lasts for the rest of the line
+                if self.line_cycles == CYCLES_PER_LINE {
+                    // LCD is ready to begin the next line
+                    self.ly += 1;
+                    self.line_cycles = 0;
+
+                    // If the LCD has line 144, then enter VBLANK. Else, enter OAM read.
+                    if self.ly == 144 {
+                        self.mode = Mode::VBlank;
+                        result.int_vblank = true;
+                    } else {
+                        self.mode = Mode::Oam;
+
+                        self.check_coincidence(result);
+                    };
+                }
+            },
+            Mode::VBlank => {
+                // LCD is writing to VBlank lines
+                if self.line_cycles == CYCLES_PER_LINE {
+                    self.line_cycles = 0;
+                    self.ly += 1;
+
+                    if self.ly > 153 {
+                        // Finished with VBlank. Enter OAM with the first line.
+                        self.ly = 0;
+                        self.mode = Mode::Oam;
+                        self.reset_window();
+
+                        self.check_coincidence(result);
                     }
-                },
-                Mode::VBlank => {
-                    // LCD is writing to VBlank lines
-                    if self.mode_cycles >= CYCLES_PER_LINE {
-                        self.mode_cycles -= CYCLES_PER_LINE;
-                        self.ly += 1;
-
-                        if self.ly > 153 {
-                            // Finished with VBlank. Enter OAM with the first line.
-                            self.ly = 0;
-                            self.mode = Mode::Oam;
-
-                            self.check_coincidence(&mut result);
-                        }
-                    } 
                 }
             }
+        }
 
-            // Check if a new mode has been entered during this step.
-            // If so, an interrupt will be raised if the respective flag is set in the STAT register
-            if self.mode != previous_mode {
-                let raise = 
-                    self.stat.contains(Stat::STAT_HBLANK_INT) && self.mode == Mode::HBlank
-                    || self.stat.contains(Stat::STAT_VBLANK_INT) && self.mode == Mode::VBlank
-                    || self.stat.contains(Stat::STAT_OAM_INT) && (self.mode == Mode::Transfer || self.mode == Mode::VBlank);
+        // Check if a new mode has been entered during this step.
+        // If so, an interrupt will be raised if the respective flag is set in the STAT register
+        if self.mode != previous_mode {
+            let raise =
+                self.stat.contains(Stat::STAT_HBLANK_INT) && self.mode == Mode::HBlank
+                || self.stat.contains(Stat::STAT_VBLANK_INT) && self.mode == Mode::VBlank
+                || self.stat.contains(Stat::STAT_OAM_INT) && (self.mode == Mode::Transfer || self.mode == Mode::VBlank);
 
-                if raise {
-                    result.int_stat = true;
-                }
+            if raise {
+                result.int_stat = true;
             }
         }
-
-        result
     }
 
-    // Draws the background for the current line specified in LY
-    fn draw_background_current_line(&self, screen_buffer: &mut [u32]) {
-        if self.lcdc.contains(Lcdc::LCDC_BG_DISPLAY) {
-            // Background can be scrolled via the SCX and SCY registers
-            let map_y = self.scy.wrapping_add(self.ly);
+    // Checks whether an OAM entry is on the current line.
+    // Only the first 10 sprites found in OAM order are drawn. X isn't considered, so sprites off the screen still count.
+    fn scan_oam_entry(&mut self, idx: u8) {
+        if idx == 0 {
+            self.line_sprites.clear();
+        }
 
-            for screen_x in 0..SCREEN_WIDTH as u8 {
-                let map_x = self.scx.wrapping_add(screen_x);
+        // Sprites can be 8x8 or 8x16
+        let sprite_height = if self.lcdc.contains(Lcdc::LCDC_8X16_SPRITE) { 16 } else { 8 };
 
-                self.draw_bg_tile_pixel(map_x, map_y, screen_x, self.ly, screen_buffer);
-            }
+        // Y value is offset by 16
+        let y = self.oam[idx as usize].y as u16;
+        let screen_y = self.ly as u16 + 16;
+
+        if self.line_sprites.len() < MAX_SPRITES_PER_LINE && screen_y >= y && screen_y < y + sprite_height {
+            self.line_sprites.push(idx);
         }
     }
 
-    // Draws the window for the current line specified in LY
-    fn draw_window_current_line(&self, screen_buffer: &mut [u32]) {
-        if self.lcdc.contains(Lcdc::LCDC_WIN_DISPLAY) {
-            // Window cannot scroll. The top-left is specified by the WY and WX registers. 
-            // They are in relation to the top-left of the physical screen.
-            // Only display if WX=0..166, WY=0..143
-            if self.wx < 166 && self.wy < 143 {
-                if self.ly >= self.wy {
-                    let map_y = self.ly - self.wy;
+    // Resets the pixel pipeline at the start of a line
+    fn start_transfer(&mut self) {
+        self.lx = 0;
 
-                    // X position is really WX - 7
-                    let window_x = (self.wx as isize) - 7;
+        // Background is scrolled within a tile by throwing away the first SCX % 8 pixels
+        self.discard = self.scx & 7;
 
-                    for screen_x in 0..SCREEN_WIDTH as u8 {
-                        let screen_x_i = screen_x as isize;
+        self.bg_fifo.clear();
+        self.sprite_fifo.clear();
+        self.fetcher = Fetcher::new();
+        self.sprite_fetch = None;
+        self.sprite_cycles = 0;
+    }
 
-                        if window_x <= screen_x_i {
-                            let map_x_i = screen_x_i - window_x;
+    // Runs the pixel pipeline for a single cycle
+    fn transfer_dot(&mut self, screen_buffer: &mut [u32]) {
+        if self.sprite_fetch.is_none() {
+            // Once the left edge of the window is reached, the background pixels are thrown away
+            // and the fetcher starts over on the first window tile
+            if !self.fetcher.window && self.window_starts() {
+                self.bg_fifo.clear();
+                self.fetcher = Fetcher { window: true, warmup: self.fetcher.warmup, ..Fetcher::new() };
+
+                // X position is really WX - 7, so with WX=0..6 part of the window is off the left of the screen
+                self.discard = if self.window_wrap { 0 } else { 7 - self.wx.min(7) };
+            }
 
-                            if map_x_i >= 0 && map_x_i < 256 {
-                                self.draw_win_tile_pixel(map_x_i as u8, map_y, screen_x, self.ly, screen_buffer);
-                            }
-                        }
-                    }
+            // Sprites starting at the next pixel have to be fetched before it is drawn.
+            // Sprites hanging off the left edge are all fetched before the first pixel.
+            // Sprites fetched first win when they overlap, so they are taken by X, then by OAM index.
+            if self.lcdc.contains(Lcdc::LCDC_SPRITE_DISPLAY) {
+                let oam = &self.oam;
+                let x = self.lx + 8;
+
+                let next = self.line_sprites.iter()
+                    .enumerate()
+                    .filter(|&(_, &idx)| oam[idx as usize].x <= x)
+                    .min_by_key(|&(_, &idx)| (oam[idx as usize].x, idx))
+                    .map(|(pos, _)| pos);
+
+                if let Some(pos) = next {
+                    self.sprite_fetch = Some(self.line_sprites.remove(pos));
+                    self.sprite_cycles = 0;
                 }
             }
         }
-    }
 
-    // Draws a background tile at the given coordinates.
-    // map_x and map_y are the coordinates in the tile map
-    // screen_x and screen_y are the coordinates of the physical screen
-    fn draw_bg_tile_pixel(&self, map_x: u8, map_y: u8, screen_x: u8, screen_y: u8, screen_buffer: &mut [u32]) {
-        let map_addr_base = if self.lcdc.contains(Lcdc::LCDC_BG_TILE_9C) {
-            0x9C00
-        } else {
-            0x9800
-        } as u16;
+        if let Some(idx) = self.sprite_fetch {
+            // The background fetcher gets to finish the tile it's working on first
+            if !self.fetcher_waiting() {
+                self.step_fetcher();
+            }
+
+            if self.fetcher_waiting() {
+                self.sprite_cycles += 1;
+
+                if self.sprite_cycles == CYCLES_PER_SPRITE_FETCH {
+                    self.fetch_sprite(idx);
+                    self.sprite_fetch = None;
+                }
+            }
+
+            return;
+        }
+
+        if let Some(color) = self.bg_fifo.pop_front() {
+            if self.discard > 0 {
+                self.discard -= 1;
+            } else {
+                self.draw_pixel(color, screen_buffer);
+                self.lx += 1;
+            }
+        }
 
-        self.draw_tile_pixel(map_addr_base, map_x, map_y, screen_x, screen_y, screen_buffer);
+        self.step_fetcher();
     }
 
-    // Draws a window tile at the given coordinates.
-    // map_x and map_y are the coordinates in the tile map
-    // screen_x and screen_y are the coordinates of the physical screen
-    fn draw_win_tile_pixel(&self, map_x: u8, map_y: u8, screen_x: u8, screen_y: u8, screen_buffer: &mut [u32]) {
-        let map_addr_base = if self.lcdc.contains(Lcdc::LCDC_WIN_TILE_9C) {
-            0x9C00
+    // Window is displayed once WY has matched LY this frame
+    fn window_enabled(&self) -> bool {
+        self.lcdc.contains(Lcdc::LCDC_WIN_DISPLAY) && self.window_latched
+    }
+
+    // Checks whether the window starts at the next pixel.
+    // It starts at WX - 7, or at the left edge for WX=0..6. WX=166 misses the line and covers the whole of the next one instead.
+    fn window_starts(&self) -> bool {
+        if !self.window_enabled() {
+            false
+        } else if self.window_wrap {
+            self.lx == 0
         } else {
-            0x9800
-        } as u16;
+            self.wx != 166 && self.lx + 7 == self.wx.max(7)
+        }
+    }
 
-        self.draw_tile_pixel(map_addr_base, map_x, map_y, screen_x, screen_y, screen_buffer);
+    // The window starts over from its top row every frame
+    fn reset_window(&mut self) {
+        self.window_line = 0;
+        self.window_latched = false;
+        self.window_wrap = false;
     }
 
-    // Draws a tile at the given coordinates.
-    // map_addr_base is either 0x9C00 or 0x9800
-    // map_x and map_y are the coordinates in the tile map
-    // screen_x and screen_y are the coordinates of the physical screen
-    fn draw_tile_pixel(&self, map_addr_base: u16, map_x: u8, map_y: u8, screen_x: u8, screen_y: u8, screen_buffer: &mut [u32]) {
-        // Each tile is 8x8 pixels.
-        // Get the X,Y positions of the tile boundaries
-        let map_tile_y = (map_y / 8) as u16;
-        let map_tile_x = (map_x / 8) as u16;
+    // The fetcher has a tile ready but the FIFO is still full
+    fn fetcher_waiting(&self) -> bool {
+        self.fetcher.step == FetchStep::Push && !self.bg_fifo.is_empty()
+    }
 
-        // Get the X,Y coordinates within the tile for the given map X,Y
-        let tile_row = map_y & 7;
-        let tile_column = map_x & 7;
+    // Advances the background fetcher by a cycle
+    fn step_fetcher(&mut self) {
+        if self.fetcher.step != FetchStep::Push {
+            self.fetcher.cycles += 1;
+
+            if self.fetcher.cycles == CYCLES_PER_FETCH_STEP {
+                self.fetcher.cycles = 0;
+
+                self.fetcher.step = match self.fetcher.step {
+                    FetchStep::TileNumber => {
+                        self.fetch_tile_number();
+                        FetchStep::DataLow
+                    },
+                    FetchStep::DataLow => {
+                        self.fetcher.low = self.fetch_tile_data(0);
+                        FetchStep::DataHigh
+                    },
+                    FetchStep::DataHigh => {
+                        self.fetcher.high = self.fetch_tile_data(1);
+                        FetchStep::Push
+                    },
+                    FetchStep::Push => unreachable!()
+                };
+            }
+        }
 
-        // Background Tile Table is a 32x32 byte array containing the the index of each tile
-        let tile_idx_addr = map_addr_base + (map_tile_y * 32) + map_tile_x;
-        let tile_idx = self.vram[(tile_idx_addr - VIDEO_RAM_START) as usize];
+        if self.fetcher.step == FetchStep::Push && self.bg_fifo.is_empty() {
+            if self.fetcher.warmup {
+                self.fetcher.warmup = false;
+            } else {
+                for shift in (0..8).rev() {
+                    let upper_bit = (self.fetcher.high >> shift) & 0b1;
+                    let lower_bit = (self.fetcher.low >> shift) & 0b1;
 
-        // The tile index is used to get the address in the Tile Pattern Table that contains the tile pixel data
-        let tile_addr = self.tile_address(tile_idx);
+                    self.bg_fifo.push_back((upper_bit << 1) | lower_bit);
+                }
 
-        // Read the Tile Pattern Table to get what color pixel to display at the given screen coordinates
-        let color = self.tile_pixel_color(tile_addr, tile_row, tile_column);
+                self.fetcher.x = self.fetcher.x.wrapping_add(1);
+            }
 
-        screen_buffer[(screen_y as usize * SCREEN_WIDTH) + screen_x as usize] = self.bgp.rgb(color);
+            self.fetcher.step = FetchStep::TileNumber;
+        }
     }
 
-    // Draws sprites on the current line
-    fn draw_sprites_current_line(&self, screen_buffer: &mut [u32]) {
-        if self.lcdc.contains(Lcdc::LCDC_SPRITE_DISPLAY) {
-            let screen_y = self.ly as isize;
+    // Reads the number of the next tile from the background or window tile map
+    fn fetch_tile_number(&mut self) {
+        let (map_addr_base, map_tile_x, map_y) = if self.fetcher.window {
+            // Window cannot scroll. The top-left is specified by the WY and WX registers.
+            let base = if self.lcdc.contains(Lcdc::LCDC_WIN_TILE_9C) { 0x9C00 } else { 0x9800 };
 
-            // Sprites can be 8x8 or 8x16
-            let sprite_height = if self.lcdc.contains(Lcdc::LCDC_8X16_SPRITE) { 16 } else { 8 };
+            (base, self.fetcher.x & 31, self.window_line)
+        } else {
+            // Background can be scrolled via the SCX and SCY registers
+            let base = if self.lcdc.contains(Lcdc::LCDC_BG_TILE_9C) { 0x9C00 } else { 0x9800 };
 
-            // Find all sprites that will be visible on the current line
-            let line_sprite_idxs = (0..40 as usize).filter(|&idx| {
-                let entry = &self.oam[idx as usize];
+            (base, ((self.scx / 8).wrapping_add(self.fetcher.x)) & 31, self.scy.wrapping_add(self.ly))
+        };
 
-                // X,Y values are offset by 8,16
-                let y = (entry.y as isize) - 16;
-                let x = (entry.x as isize) - 8;
+        // Background Tile Table is a 32x32 byte array containing the the index of each tile
+        let tile_idx_addr = map_addr_base + ((map_y / 8) as u16 * 32) + map_tile_x as u16;
 
-                let end_y = y + (sprite_height - 1);
+        self.fetcher.tile = self.vram[(tile_idx_addr - VIDEO_RAM_START) as usize];
+        self.fetcher.row = map_y & 7;
+    }
 
-               return x >= 0 && x < 160 && screen_y >= y && screen_y <= end_y;
-            }).collect::<Vec<_>>();
+    // Reads one of the two bytes making up the current row of the fetched tile
+    fn fetch_tile_data(&self, offset: u16) -> u8 {
+        let address = self.tile_address(self.fetcher.tile) + (self.fetcher.row as u16 * 2) + offset;
 
-            for screen_x in 0..SCREEN_WIDTH as isize { 
-                // Sprites are 40 blocks in OAM. Each block is 32 bits
-                for idx in &line_sprite_idxs {
-                    let entry = &self.oam[*idx];
+        self.vram[(address - VIDEO_RAM_START) as usize]
+    }
 
-                    // X,Y values are offset by 8,16
-                    let y = (entry.y as isize) - 16;
-                    let x = (entry.x as isize) - 8;
+    // Fetches the current line of a sprite and mixes it into the sprite FIFO
+    fn fetch_sprite(&mut self, idx: u8) {
+        let entry = self.oam[idx as usize];
 
-                    let end_x = x + 7;
+        // Sprites can be 8x8 or 8x16
+        let sprite_height = if self.lcdc.contains(Lcdc::LCDC_8X16_SPRITE) { 16 } else { 8 };
 
-                    if screen_x >= x && screen_x <= end_x {
-                        let tile_addr = self.sprite_tile_address(entry.tile);
-                
-                        // In DMG, the sprite can use one of two palletes
-                        let palette = if entry.attrs.contains(OamAttr::OAM_ATTR_PALETTE_DMG) { 
-                            self.obp1
-                        } else { 
-                            self.obp0
-                        };
+        // Y value is offset by 16
+        let mut row = self.ly.wrapping_add(16).wrapping_sub(entry.y) & (sprite_height - 1);
 
-                        let row = if entry.attrs.contains(OamAttr::OAM_ATTR_Y_FLIP) {
-                            (screen_y - y - sprite_height + 1).abs()
-                        } else {
-                            screen_y - y
-                        };
+        if entry.attrs.contains(OamAttr::OAM_ATTR_Y_FLIP) {
+            row = sprite_height - 1 - row;
+        }
 
-                        // Sprite can also be horizontally flipped
-                        let column = if entry.attrs.contains(OamAttr::OAM_ATTR_X_FLIP) {
-                            (screen_x - x - 7).abs()
-                        } else {
-                            screen_x - x
-                        };
+        // 8x16 sprites ignore the lowest bit of the tile number
+        let tile = if sprite_height == 16 { entry.tile & 0xFE } else { entry.tile };
+        let tile_addr = self.sprite_tile_address(tile);
 
-                        let color = self.tile_pixel_color(tile_addr, row as u8, column as u8);
+        // Columns of a sprite off the left of the screen are skipped
+        let first_column = (self.lx + 8).saturating_sub(entry.x).min(8);
 
-                        // Color 0 is hidden for sprites
-                        if color != 0 {
-                            let screen_buffer_idx = (screen_y as usize * SCREEN_WIDTH) + screen_x as usize;
+        for column in first_column..8 {
+            // Sprite can also be horizontally flipped
+            let tile_column = if entry.attrs.contains(OamAttr::OAM_ATTR_X_FLIP) { 7 - column } else { column };
 
-                            // Sprite can be hidden behind BG layer if PRIORITY flag is set and background color is anything but white
-                            if !entry.attrs.contains(OamAttr::OAM_ATTR_OBJ_PRIORITY) || screen_buffer[screen_buffer_idx] != WHITE_RGB {
-                                screen_buffer[screen_buffer_idx] = palette.rgb(color);
-                            }
-                        }
-                    }
-                }
+            let pixel = SpritePixel {
+                color: self.tile_pixel_color(tile_addr, row, tile_column),
+                obp1: entry.attrs.contains(OamAttr::OAM_ATTR_PALETTE_DMG),
+                priority: entry.attrs.contains(OamAttr::OAM_ATTR_OBJ_PRIORITY)
+            };
+
+            // Sprites fetched earlier win, so only their transparent pixels are replaced
+            match self.sprite_fifo.get_mut((column - first_column) as usize) {
+                Some(existing) => if existing.color == 0 { *existing = pixel },
+                None => self.sprite_fifo.push_back(pixel)
             }
         }
     }
 
+    // Mixes a background pixel with the sprite pixel at the same position and draws it on the screen.
+    // Both layers are kept as color indexes until the pixel is drawn, so palettes can be changed partway through a line.
+    fn draw_pixel(&mut self, bg_color: ColorIndex, screen_buffer: &mut [u32]) {
+        let lx = self.lx as usize;
+
+        // Background and window are blank while disabled, which sprites treat as color 0
+        let bg_enabled = self.lcdc.contains(Lcdc::LCDC_BG_DISPLAY);
+        self.bg_line[lx] = if bg_enabled { bg_color } else { 0 };
+
+        let bg_line = &self.bg_line;
+        let sprite = self.sprite_fifo.pop_front()
+            // Color 0 is hidden for sprites
+            .filter(|sprite| sprite.color != 0 && self.lcdc.contains(Lcdc::LCDC_SPRITE_DISPLAY))
+            // Sprite is hidden behind background colors 1-3 if its PRIORITY flag is set
+            .filter(|sprite| !sprite.priority || bg_line[lx] == 0);
+
+        let rgb = match sprite {
+            // In DMG, the sprite can use one of two palletes
+            Some(sprite) => if sprite.obp1 { self.obp1 } else { self.obp0 }.rgb(sprite.color),
+            None if bg_enabled => self.bgp.rgb(bg_line[lx]),
+            None => WHITE_RGB
+        };
+
+        screen_buffer[(self.ly as usize * SCREEN_WIDTH) + lx] = rgb;
+    }
+
     // Gets the color of a pixel within a tile
     fn tile_pixel_color(&self, address: u16, row: u8, column: u8) -> ColorIndex {
         let upper_byte = self.vram[((address + (row * 2) as u16) + 1 - VIDEO_RAM_START) as usize];
         let lower_byte = self.vram[((address + (row * 2) as u16) - VIDEO_RAM_START) as usize];
 
-        
+
         let shift = 7 - column;
         let upper_bit = (upper_byte >> shift) & 0b1;
         let lower_bit = (lower_byte >> shift) & 0b1;
@@ -468,7 +698,7 @@ impl Lcd {
     // Gets the memory address for the given tile
     fn tile_address(&self, tile_index: u8) -> u16 {
         // Tile data can be in two spots. Either 0x8000 where the patterns are indexed with unsigned numbers,
-        // or 0x9000 where the indexes are signed 
+        // or 0x9000 where the indexes are signed
         let tile_addr_base = if self.lcdc.contains(Lcdc::LCDC_UNSIGNED_TILE_DATA) {
             0x8000
         } else {
@@ -531,36 +761,30 @@ impl Addressable for Lcd {
                 if !self.lcdc.contains(Lcdc::LCDC_ENABLED) || self.mode != Mode::Transfer {
                     self.vram[(addr - VIDEO_RAM_START) as usize] = val;
                 } else {
-                    warn!("Attempted VRAM write during mode {}. Mode cycles: {}", self.mode as u8, self.mode_cycles);
+                    warn!("Attempted VRAM write during mode {}. Line cycles: {}", self.mode as u8, self.line_cycles);
                 }
             },
             OAM_START..=OAM_END => {
                 // Only allow write if LCD is disabled or in Mode 00 (HBlank) or 01 (VBLANK)
                 if !self.lcdc.contains(Lcdc::LCDC_ENABLED) || self.mode == Mode::HBlank || self.mode == Mode::VBlank {
-                    let start = (addr - OAM_START) as usize;
-                    let entry_idx = start / 4;
-                    let offset = start % 4;
-                    let mut entry = &mut self.oam[entry_idx];
-
-                    match offset {
-                        0 => { entry.y = val },
-                        1 => { entry.x = val },
-                        2 => { entry.tile = val },
-                        3 => { entry.attrs = OamAttr::from_bits(val).unwrap() },
-                        _ => unreachable!()
-                    }
+                    self.write_oam((addr - OAM_START) as usize, val);
                 }
             },
             ADDR_LCDC => {
+                let was_enabled = self.lcdc.contains(Lcdc::LCDC_ENABLED);
                 self.lcdc = Lcdc::from_bits(val).unwrap();
 
                 // Reset internal counters if display disabled
                 // LCDC mode goes back to 0 (HBlank)
                 if !self.lcdc.contains(Lcdc::LCDC_ENABLED) {
-                    self.mode_cycles = 0;
+                    self.line_cycles = 0;
                     self.ly = 0;
                     self.mode = Mode::HBlank;
-
+                    self.reset_window();
+                } else if !was_enabled {
+                    // Turning the display back on starts line 0 from the OAM search,
+                    // so it gets drawn and WY is checked against it
+                    self.mode = Mode::Oam;
                 }
             },
             ADDR_STAT => self.stat.bits = val & 0b1111_1100,
@@ -576,4 +800,480 @@ impl Addressable for Lcd {
             _ => warn!("LCD IO write unimplemented {:#X} -> {:#X}", val, addr)
         }
     }
-}
\ No newline at end of file
+}
+
+impl Savestate for Lcd {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&self.vram)?;
+
+        for entry in self.oam.iter() {
+            out.write_all(&[entry.y, entry.x, entry.tile, entry.attrs.bits()])?;
+        }
+
+        out.write_all(&[
+            self.lcdc.bits(),
+            self.stat.bits(),
+            self.scy,
+            self.scx,
+            self.ly,
+            self.lyc,
+            self.bgp.0,
+            self.obp0.0,
+            self.obp1.0,
+            self.wy,
+            self.wx,
+            self.mode as u8
+        ])?;
+
+        savestate::write_usize(out, self.line_cycles)?;
+        out.write_all(&[self.lx, self.discard])?;
+
+        out.write_u8(self.bg_fifo.len() as u8)?;
+        for &color in self.bg_fifo.iter() {
+            out.write_u8(color)?;
+        }
+
+        out.write_u8(self.sprite_fifo.len() as u8)?;
+        for pixel in self.sprite_fifo.iter() {
+            out.write_u8(pixel.to_byte())?;
+        }
+
+        out.write_all(&[
+            self.fetcher.step as u8,
+            self.fetcher.x,
+            self.fetcher.tile,
+            self.fetcher.row,
+            self.fetcher.low,
+            self.fetcher.high
+        ])?;
+        savestate::write_usize(out, self.fetcher.cycles)?;
+        savestate::write_bool(out, self.fetcher.window)?;
+        savestate::write_bool(out, self.fetcher.warmup)?;
+
+        out.write_u8(self.line_sprites.len() as u8)?;
+        out.write_all(&self.line_sprites)?;
+
+        savestate::write_bool(out, self.sprite_fetch.is_some())?;
+        out.write_u8(self.sprite_fetch.unwrap_or(0))?;
+        savestate::write_usize(out, self.sprite_cycles)?;
+
+        out.write_u8(self.window_line)?;
+        savestate::write_bool(out, self.window_latched)?;
+        savestate::write_bool(out, self.window_wrap)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        input.read_exact(&mut self.vram)?;
+
+        for entry in self.oam.iter_mut() {
+            entry.y = input.read_u8()?;
+            entry.x = input.read_u8()?;
+            entry.tile = input.read_u8()?;
+            entry.attrs = OamAttr::from_bits_truncate(input.read_u8()?);
+        }
+
+        self.lcdc = Lcdc::from_bits_truncate(input.read_u8()?);
+        self.stat = Stat::from_bits_truncate(input.read_u8()?);
+        self.scy = input.read_u8()?;
+        self.scx = input.read_u8()?;
+        self.ly = input.read_u8()?;
+        self.lyc = input.read_u8()?;
+        self.bgp = Palette(input.read_u8()?);
+        self.obp0 = Palette(input.read_u8()?);
+        self.obp1 = Palette(input.read_u8()?);
+        self.wy = input.read_u8()?;
+        self.wx = input.read_u8()?;
+        self.mode = Mode::from_u8(input.read_u8()?).ok_or_else(|| savestate::invalid_data("Invalid LCD mode"))?;
+        self.line_cycles = savestate::read_usize(input)?;
+        self.lx = input.read_u8()?.min(SCREEN_WIDTH as u8);
+        self.discard = input.read_u8()?;
+
+        let len = input.read_u8()?;
+        if len > 8 {
+            return Err(savestate::invalid_data("Invalid LCD background FIFO"));
+        }
+
+        self.bg_fifo.clear();
+        for _ in 0..len {
+            self.bg_fifo.push_back(input.read_u8()? & 0b11);
+        }
+
+        let len = input.read_u8()?;
+        if len > 8 {
+            return Err(savestate::invalid_data("Invalid LCD sprite FIFO"));
+        }
+
+        self.sprite_fifo.clear();
+        for _ in 0..len {
+            self.sprite_fifo.push_back(SpritePixel::from_byte(input.read_u8()?));
+        }
+
+        self.fetcher.step = FetchStep::from_u8(input.read_u8()?).ok_or_else(|| savestate::invalid_data("Invalid LCD fetcher step"))?;
+        self.fetcher.x = input.read_u8()?;
+        self.fetcher.tile = input.read_u8()?;
+        self.fetcher.row = input.read_u8()? & 7;
+        self.fetcher.low = input.read_u8()?;
+        self.fetcher.high = input.read_u8()?;
+        self.fetcher.cycles = savestate::read_usize(input)?;
+        self.fetcher.window = savestate::read_bool(input)?;
+        self.fetcher.warmup = savestate::read_bool(input)?;
+
+        let len = input.read_u8()? as usize;
+        self.line_sprites.resize(len, 0);
+        input.read_exact(&mut self.line_sprites)?;
+
+        let fetching = savestate::read_bool(input)?;
+        let idx = input.read_u8()?;
+        self.sprite_fetch = if fetching { Some(idx) } else { None };
+        self.sprite_cycles = savestate::read_usize(input)?;
+        self.window_line = input.read_u8()?;
+        self.window_latched = savestate::read_bool(input)?;
+        self.window_wrap = savestate::read_bool(input)?;
+
+        if self.line_sprites.len() > MAX_SPRITES_PER_LINE || self.line_sprites.iter().chain(self.sprite_fetch.iter()).any(|&idx| idx >= 40) {
+            return Err(savestate::invalid_data("Invalid LCD sprite index"));
+        }
+
+        // Lines and cycle counters have to be somewhere the mode could have reached, or stepping runs off the end of the screen
+        let (lines, line_cycles) = match self.mode {
+            Mode::Oam => (0..144, CYCLES_PER_OAM_READ),
+            Mode::VBlank => (144..154, CYCLES_PER_LINE),
+            _ => (0..144, CYCLES_PER_LINE)
+        };
+
+        if !lines.contains(&self.ly) || self.line_cycles >= line_cycles {
+            return Err(savestate::invalid_data("Invalid LCD position"));
+        }
+
+        if self.fetcher.cycles >= CYCLES_PER_FETCH_STEP || self.sprite_cycles >= CYCLES_PER_SPRITE_FETCH {
+            return Err(savestate::invalid_data("Invalid LCD fetcher timing"));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Steps through line 0 and returns how many cycles it spent in mode 3
+    fn transfer_cycles(lcd: &mut Lcd) -> usize {
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        let mut cycles = 0;
+
+        while lcd.mode != Mode::HBlank {
+            lcd.step(1, &mut screen_buffer);
+
+            if lcd.mode == Mode::Transfer {
+                cycles += 1;
+            }
+        }
+
+        cycles
+    }
+
+    // Draws line 0 with tile 1 filled with color 3
+    fn draw_line(lcd: &mut Lcd) -> Vec<u32> {
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        for addr in 0x8010..0x8020 {
+            lcd.write(addr, 0xFF);
+        }
+
+        while lcd.mode != Mode::HBlank {
+            lcd.step(1, &mut screen_buffer);
+        }
+
+        screen_buffer.truncate(SCREEN_WIDTH);
+        screen_buffer
+    }
+
+    fn sprite(lcd: &mut Lcd, idx: usize, x: u8, attrs: u8) {
+        for (offset, &val) in [16, x, 1, attrs].iter().enumerate() {
+            lcd.dma_write(idx * 4 + offset, val);
+        }
+    }
+
+    #[test]
+    fn transfer_length_depends_on_scroll() {
+        let mut lcd = Lcd::new();
+        assert_eq!(transfer_cycles(&mut lcd), 172);
+
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_SCX, 3);
+        assert_eq!(transfer_cycles(&mut lcd), 175);
+    }
+
+    #[test]
+    fn window_and_sprites_lengthen_transfer() {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0xB1);
+        lcd.write(ADDR_WX, 87);
+        assert_eq!(transfer_cycles(&mut lcd), 178);
+
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0x93);
+        lcd.dma_write(0, 16);
+        lcd.dma_write(1, 8);
+        assert_eq!(transfer_cycles(&mut lcd), 183);
+    }
+
+    #[test]
+    fn palette_changes_partway_through_a_line() {
+        let mut lcd = Lcd::new();
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        lcd.write(ADDR_BGP, 0);
+
+        while lcd.lx < 80 {
+            lcd.step(1, &mut screen_buffer);
+        }
+
+        lcd.write(ADDR_BGP, 0xFF);
+
+        while lcd.mode != Mode::HBlank {
+            lcd.step(1, &mut screen_buffer);
+        }
+
+        assert_eq!(screen_buffer[79], SHADES[0]);
+        assert_eq!(screen_buffer[80], SHADES[3]);
+    }
+
+    #[test]
+    fn scroll_changes_partway_through_a_line() {
+        let mut lcd = Lcd::new();
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        // Tile 1 is solid color 3 and sits in every odd column of the map
+        for addr in 0x8010..0x8020 {
+            lcd.write(addr, 0xFF);
+        }
+        for column in (1..32).step_by(2) {
+            lcd.write(0x9800 + column, 1);
+        }
+
+        while lcd.lx < 16 {
+            lcd.step(1, &mut screen_buffer);
+        }
+
+        // The tile already in the FIFO is unaffected. Fetches after it skip a column.
+        lcd.write(ADDR_SCX, 8);
+
+        while lcd.mode != Mode::HBlank {
+            lcd.step(1, &mut screen_buffer);
+        }
+
+        assert_eq!(screen_buffer[0], SHADES[0]);
+        assert_eq!(screen_buffer[8], SHADES[3]);
+        assert_eq!(screen_buffer[16], SHADES[0]);
+        assert_eq!(screen_buffer[24], SHADES[0]);
+        assert_eq!(screen_buffer[32], SHADES[3]);
+    }
+
+    #[test]
+    fn only_ten_sprites_per_line() {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0x93);
+        lcd.write(ADDR_OBP0, 0xFF);
+
+        // An off-screen sprite still takes one of the ten slots
+        sprite(&mut lcd, 0, 0, 0);
+        for idx in 1..12 {
+            sprite(&mut lcd, idx, (idx * 10) as u8, 0);
+        }
+
+        let line = draw_line(&mut lcd);
+
+        assert_eq!(line[82], SHADES[3]);
+        assert_eq!(line[92], SHADES[0]);
+        assert_eq!(line[102], SHADES[0]);
+    }
+
+    #[test]
+    fn sprites_hang_off_the_left_edge() {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0x93);
+        lcd.write(ADDR_OBP0, 0xFF);
+        sprite(&mut lcd, 0, 4, 0);
+
+        let line = draw_line(&mut lcd);
+
+        assert_eq!(line[3], SHADES[3]);
+        assert_eq!(line[4], SHADES[0]);
+    }
+
+    #[test]
+    fn overlapping_sprites_ordered_by_x_then_index() {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0x93);
+        lcd.write(ADDR_OBP0, 0xFF);
+        lcd.write(ADDR_OBP1, 0x40);
+
+        // Sprite 1 is further left so it covers sprite 0
+        sprite(&mut lcd, 0, 20, 0x10);
+        sprite(&mut lcd, 1, 16, 0);
+
+        // Sprites 2 and 3 share an X, so sprite 2 wins
+        sprite(&mut lcd, 2, 60, 0);
+        sprite(&mut lcd, 3, 60, 0x10);
+
+        let line = draw_line(&mut lcd);
+
+        assert_eq!(line[8], SHADES[3]);
+        assert_eq!(line[15], SHADES[3]);
+        assert_eq!(line[16], SHADES[1]);
+        assert_eq!(line[52], SHADES[3]);
+    }
+
+    #[test]
+    fn sprite_priority_uses_background_color_index() {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0x93);
+        lcd.write(ADDR_OBP0, 0x40);
+
+        // Color 0 is black and color 3 is white
+        lcd.write(ADDR_BGP, 0x1B);
+
+        // The first tile column is color 3, the rest color 0
+        lcd.write(0x9800, 1);
+
+        sprite(&mut lcd, 0, 8, 0x80);
+        sprite(&mut lcd, 1, 16, 0x80);
+
+        let line = draw_line(&mut lcd);
+
+        assert_eq!(line[0], SHADES[0]);
+        assert_eq!(line[8], SHADES[1]);
+        assert_eq!(line[16], SHADES[3]);
+    }
+
+    #[test]
+    fn load_state_rejects_impossible_positions() {
+        let mut lcd = Lcd::new();
+        let mut state = Vec::new();
+        lcd.save_state(&mut state).unwrap();
+        assert!(Lcd::new().load_state(&mut state.as_slice()).is_ok());
+
+        // Drawing a line below the screen
+        lcd.mode = Mode::Transfer;
+        lcd.ly = 144;
+        let mut state = Vec::new();
+        lcd.save_state(&mut state).unwrap();
+        assert_eq!(Lcd::new().load_state(&mut state.as_slice()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+
+        // Searching OAM past the end of the table
+        lcd.mode = Mode::Oam;
+        lcd.ly = 0;
+        lcd.line_cycles = 1000;
+        let mut state = Vec::new();
+        lcd.save_state(&mut state).unwrap();
+        assert_eq!(Lcd::new().load_state(&mut state.as_slice()).unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+
+    // Sets up a window using the 9C00 map, filled with tile 2, over a white background
+    fn window_lcd(tile: [u8; 8]) -> Lcd {
+        let mut lcd = Lcd::new();
+        lcd.write(ADDR_LCDC, 0xF1);
+        lcd.write(ADDR_WX, 7);
+
+        for (row, &val) in tile.iter().enumerate() {
+            lcd.write(0x8020 + row as u16 * 2, val);
+            lcd.write(0x8021 + row as u16 * 2, val);
+        }
+        for addr in 0x9C00..0x9C20 {
+            lcd.write(addr, 2);
+        }
+
+        lcd
+    }
+
+    fn run_until_line(lcd: &mut Lcd, screen_buffer: &mut [u32], ly: u8) {
+        while lcd.ly != ly {
+            lcd.step(1, screen_buffer);
+        }
+    }
+
+    #[test]
+    fn window_line_counter_skips_hidden_lines() {
+        // Odd rows of the window are black
+        let mut lcd = window_lcd([0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF, 0x00, 0xFF]);
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        run_until_line(&mut lcd, &mut screen_buffer, 1);
+        lcd.write(ADDR_LCDC, 0xD1);
+        run_until_line(&mut lcd, &mut screen_buffer, 2);
+        lcd.write(ADDR_LCDC, 0xF1);
+        run_until_line(&mut lcd, &mut screen_buffer, 3);
+
+        // Line 2 draws window row 1
+        assert_eq!(screen_buffer[0], SHADES[0]);
+        assert_eq!(screen_buffer[SCREEN_WIDTH], SHADES[0]);
+        assert_eq!(screen_buffer[2 * SCREEN_WIDTH], SHADES[3]);
+    }
+
+    #[test]
+    fn window_latches_on_wy() {
+        let mut lcd = window_lcd([0xFF; 8]);
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        lcd.write(ADDR_WY, 2);
+
+        run_until_line(&mut lcd, &mut screen_buffer, 3);
+
+        // Moving WY below the current line doesn't hide the window again
+        lcd.write(ADDR_WY, 100);
+        run_until_line(&mut lcd, &mut screen_buffer, 4);
+
+        assert_eq!(screen_buffer[SCREEN_WIDTH], SHADES[0]);
+        assert_eq!(screen_buffer[2 * SCREEN_WIDTH], SHADES[3]);
+        assert_eq!(screen_buffer[3 * SCREEN_WIDTH], SHADES[3]);
+
+        // The bottom line can still show the window
+        let mut lcd = window_lcd([0xFF; 8]);
+        lcd.write(ADDR_WY, 143);
+
+        run_until_line(&mut lcd, &mut screen_buffer, 144);
+
+        assert_eq!(screen_buffer[142 * SCREEN_WIDTH], SHADES[0]);
+        assert_eq!(screen_buffer[143 * SCREEN_WIDTH], SHADES[3]);
+    }
+
+    #[test]
+    fn window_latches_on_line_zero_after_lcd_enable() {
+        let mut lcd = window_lcd([0xFF; 8]);
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        run_until_line(&mut lcd, &mut screen_buffer, 10);
+        lcd.write(ADDR_LCDC, 0x71);
+        for pixel in screen_buffer.iter_mut() {
+            *pixel = 0;
+        }
+
+        lcd.write(ADDR_LCDC, 0xF1);
+        run_until_line(&mut lcd, &mut screen_buffer, 2);
+
+        assert_eq!(screen_buffer[0], SHADES[3]);
+        assert_eq!(screen_buffer[SCREEN_WIDTH], SHADES[3]);
+    }
+
+    #[test]
+    fn window_left_edge_cases() {
+        // Only column 4 of the window is black
+        let mut lcd = window_lcd([0x08; 8]);
+        let mut screen_buffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+        lcd.write(ADDR_WX, 3);
+
+        run_until_line(&mut lcd, &mut screen_buffer, 1);
+
+        assert_eq!(screen_buffer[0], SHADES[3]);
+        assert_eq!(screen_buffer[1], SHADES[0]);
+
+        // WX=166 leaves the line alone and covers the next one from the left edge
+        let mut lcd = window_lcd([0x08; 8]);
+        lcd.write(ADDR_WX, 166);
+
+        run_until_line(&mut lcd, &mut screen_buffer, 2);
+
+        assert!(screen_buffer[..SCREEN_WIDTH].iter().all(|&rgb| rgb == SHADES[0]));
+        assert_eq!(screen_buffer[SCREEN_WIDTH + 4], SHADES[3]);
+    }
+}
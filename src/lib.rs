@@ -0,0 +1,35 @@
+#[macro_use]
+extern crate bitflags;
+extern crate byteorder;
+#[macro_use]
+extern crate enum_primitive;
+extern crate fnv;
+#[macro_use]
+extern crate lazy_static;
+extern crate log;
+extern crate png;
+extern crate regex;
+
+pub mod bus;
+pub mod cartridge;
+pub mod cpu;
+pub mod debugger;
+mod emulator;
+pub mod joypad;
+pub mod lcd;
+pub mod savestate;
+pub mod screenshot;
+pub mod serial;
+pub mod sound;
+pub mod testrom;
+pub mod timer;
+pub mod trace;
+pub mod video;
+
+pub use cartridge::Cartridge;
+pub use debugger::Debugger;
+pub use emulator::{Emulator, StepResult};
+pub use joypad::Button;
+pub use lcd::{SCREEN_WIDTH, SCREEN_HEIGHT};
+pub use sound::{AudioSink, WavWriter};
+pub use trace::TraceFilter;
@@ -1,30 +1,26 @@
-#[macro_use]
-extern crate bitflags;
-extern crate byteorder;
 extern crate clap;
-#[macro_use]
-extern crate enum_primitive;
+#[cfg(feature = "host-audio")]
+extern crate cpal;
 extern crate log;
 extern crate minifb;
+extern crate rustboy;
 
-mod bus;
-mod cartridge;
-mod debugger;
-mod cpu;
-mod joypad;
-mod lcd;
+#[cfg(feature = "host-audio")]
+mod audio;
+mod frontend;
 mod logger;
-mod rustboy;
-mod serial;
-mod sound;
-mod timer;
-
-use rustboy::RustboyOptions;
-use cartridge::Cartridge;
-use clap::{Arg, App};
+
+use frontend::{Rustboy, RustboyOptions};
+use clap::{Arg, App, AppSettings, ArgMatches, SubCommand};
 use logger::{Logger};
 use log::{error, info, LevelFilter};
-use rustboy::Rustboy;
+use rustboy::{Cartridge, Debugger, Emulator, TraceFilter, WavWriter};
+use rustboy::debugger::{symbols_path, GdbStub, Symbols};
+use rustboy::cartridge::battery_path;
+use rustboy::screenshot::{self, DmgPalette};
+use rustboy::testrom::{self, Outcome};
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
 use std::path::Path;
 use std::process;
 
@@ -34,6 +30,7 @@ static LOGGER: Logger = Logger;
 
 fn main() {
     let matches = App::new("Rustboy")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("scale")
             .long("scale")
             .value_name("SCALE")
@@ -51,11 +48,139 @@ fn main() {
             .multiple(false)
             .help("Disable limiting to 60fps"))
 
+        .arg(Arg::with_name("screenshot-scale")
+            .long("screenshot-scale")
+            .value_name("SCALE")
+            .default_value("1")
+            .possible_values(&["1", "2", "3", "4", "5", "6", "7", "8"])
+            .help("Sets how much screenshots are enlarged. F12 saves a screenshot next to the ROM.")
+            .takes_value(true))
+
+        .arg(Arg::with_name("screenshot-after")
+            .long("screenshot-after")
+            .value_names(&["FRAMES", "FILE"])
+            .conflicts_with_all(&["debug", "gdb"])
+            .help("Runs without a window for the given number of frames, saves the screen to a PNG and exits")
+            .takes_value(true))
+
+        .arg(Arg::with_name("boot-rom")
+            .long("boot-rom")
+            .value_name("FILE")
+            .help("Runs a 256 byte DMG boot ROM before the game instead of skipping straight to it")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("debug")
+            .long("debug")
+            .multiple(false)
+            .help("Starts the interactive debugger, breaking before the first instruction"))
+
+        .arg(Arg::with_name("symbols")
+            .long("symbols")
+            .value_name("FILE")
+            .requires("debug")
+            .help("Loads debugger labels from an RGBDS .sym file. Defaults to the .sym file next to the ROM.")
+            .takes_value(true))
+
+        .arg(Arg::with_name("gdb")
+            .long("gdb")
+            .value_name("PORT")
+            .conflicts_with("debug")
+            .help("Waits for a GDB remote protocol client to connect on the local port before starting")
+            .takes_value(true))
+
+        .arg(Arg::with_name("record-audio")
+            .long("record-audio")
+            .value_name("FILE")
+            .help("Records the sound output to a WAV file")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("record")
+            .long("record")
+            .value_name("FILE")
+            .help("Records video to a Y4M file, with the sound in a WAV file next to it. F11 starts and stops recording.")
+            .takes_value(true))
+
+        .arg(Arg::with_name("audio-rate")
+            .long("audio-rate")
+            .value_name("RATE")
+            .default_value("44100")
+            .possible_values(&["44100", "48000"])
+            .help("Sets the sample rate of recorded WAV files")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("trace")
+            .long("trace")
+            .value_name("FILE")
+            .help("Logs the CPU state before every instruction in the gameboy-doctor format")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("trace-pc")
+            .long("trace-pc")
+            .value_name("START-END")
+            .help("Only traces instructions with PC in the inclusive range")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("trace-bank")
+            .long("trace-bank")
+            .value_name("BANK")
+            .help("Only traces instructions while the ROM bank is mapped to 0x4000-0x7FFF")
+            .global(true)
+            .takes_value(true))
+
+        .arg(Arg::with_name("trace-skip")
+            .long("trace-skip")
+            .value_name("COUNT")
+            .help("Starts tracing after the given number of instructions")
+            .global(true)
+            .takes_value(true))
+
         .arg(Arg::with_name("v")
             .short("v")
             .multiple(true)
             .help("Set verbosity level. (1 - 3)"))
 
+        .subcommand(SubCommand::with_name("test")
+            .about("Runs a Blargg or Mooneye test ROM without a window, or compares its screen against a reference image. \
+                    Exits with 0 if it passed, 1 if it failed and 2 if it timed out.")
+            .arg(Arg::with_name("ROM")
+                .help("Sets the test ROM filename to run")
+                .required(true)
+                .index(1))
+            .arg(Arg::with_name("cycles")
+                .long("cycles")
+                .value_name("CYCLES")
+                .help("Gives up after the given number of clock cycles. Defaults to two minutes of emulated time.")
+                .takes_value(true))
+            .arg(Arg::with_name("reference")
+                .long("reference")
+                .value_name("PNG")
+                .conflicts_with("cycles")
+                .help("Compares the screen against a reference image instead of waiting for the ROM to report a result")
+                .takes_value(true))
+            .arg(Arg::with_name("frames")
+                .long("frames")
+                .value_name("FRAMES")
+                .requires("reference")
+                .help("Number of frames to run before comparing the screen. Defaults to 120.")
+                .takes_value(true))
+            .arg(Arg::with_name("palette")
+                .long("palette")
+                .value_name("COLORS")
+                .requires("reference")
+                .help("Colors the reference uses for the four shades, from white to black. Defaults to FFFFFF,AAAAAA,555555,000000.")
+                .takes_value(true))
+            .arg(Arg::with_name("diff")
+                .long("diff")
+                .value_name("PNG")
+                .requires("reference")
+                .help("Where to write the diff image if the screen doesn't match. Defaults to the ROM name with .diff.png.")
+                .takes_value(true)))
+
         .get_matches();
 
     // Configure logging
@@ -68,6 +193,10 @@ fn main() {
 
     log::set_logger(&LOGGER).map(|()| log::set_max_level(log_level)).unwrap();
 
+    if let Some(test_matches) = matches.subcommand_matches("test") {
+        process::exit(run_test(test_matches));
+    }
+
     let rom_arg = matches.value_of("ROM").unwrap();
     let rom_path = Path::new(rom_arg);
 
@@ -82,6 +211,13 @@ fn main() {
     info!("Loaded {}", rom_path.file_name().unwrap().to_str().unwrap());
     info!("{:?}", cart);
 
+    // Restore the in-game save for battery backed cartridges
+    let sav_path = battery_path(rom_path);
+
+    if let Err(err) = cart.load_battery_file(&sav_path) {
+        error!("Unable to read battery save {}: {}", sav_path.display(), err);
+    }
+
     let scale = match matches.value_of("scale").unwrap() {
         "1" => Scale::X1,
         "2" => Scale::X2,
@@ -95,11 +231,337 @@ fn main() {
         }
     };
 
+    let screenshot_scale = matches.value_of("screenshot-scale").unwrap().parse().unwrap();
+
+    if let Some(mut values) = matches.values_of("screenshot-after") {
+        let frames = values.next().unwrap();
+        let path = values.next().unwrap();
+
+        let mut emulator = headless_emulator(&mut cart, &matches).unwrap_or_else(|err| {
+            error!("{}", err);
+            process::exit(1);
+        });
+
+        let status = headless_screenshot(&mut emulator, frames, Path::new(path), screenshot_scale);
+        process::exit(finish_headless(&mut emulator, status));
+    }
+
     let options = RustboyOptions {
-        scale: scale,
-        unlock_fps: matches.is_present("unlock-fps")
+        scale,
+        unlock_fps: matches.is_present("unlock-fps"),
+        screenshot_scale,
+        audio_rate: matches.value_of("audio-rate").unwrap().parse().unwrap()
     };
 
-    let mut rustboy = Rustboy::new(&mut cart, options);
+    // Connect the debugger before the window opens so emulation doesn't start without it
+    let gdb = matches.value_of("gdb").map(|port| {
+        let stub = port.parse().map_err(|_| format!("Invalid port {}", port))
+            .and_then(|port| GdbStub::listen(port).map_err(|err| err.to_string()));
+
+        stub.unwrap_or_else(|err| {
+            error!("Unable to start GDB server: {}", err);
+            process::exit(1);
+        })
+    });
+
+    let mut rustboy = Rustboy::new(&mut cart, rom_path, options);
+
+    if let Some(boot_rom_path) = matches.value_of("boot-rom") {
+        let loaded = fs::read(boot_rom_path).map_err(|err| err.to_string())
+            .and_then(|boot_rom| rustboy.load_boot_rom(boot_rom));
+
+        if let Err(err) = loaded {
+            error!("Unable to load boot ROM {}: {}", boot_rom_path, err);
+            process::exit(1);
+        }
+    }
+
+    if let Some(gdb) = gdb {
+        rustboy.set_debugger(Box::new(gdb));
+    } else if matches.is_present("debug") {
+        let mut debugger = Debugger::new();
+        let sym_path = matches.value_of("symbols").map_or_else(|| symbols_path(rom_path), |path| Path::new(path).to_path_buf());
+
+        // A missing default symbol file is fine, most ROMs don't ship one
+        if matches.is_present("symbols") || sym_path.is_file() {
+            match Symbols::load(&sym_path) {
+                Ok(symbols) => {
+                    info!("Loaded {} symbols from {}", symbols.len(), sym_path.display());
+                    debugger.set_symbols(symbols);
+                },
+                Err(err) => error!("Unable to read symbols {}: {}", sym_path.display(), err)
+            }
+        }
+
+        rustboy.set_debugger(Box::new(debugger));
+    }
+
+    // Play sound through the host's audio device. Without one, emulation continues silently.
+    #[cfg(feature = "host-audio")]
+    match audio::HostAudio::new() {
+        Ok(audio) => rustboy.add_audio_sink(Box::new(audio)),
+        Err(err) => error!("Unable to open audio device: {}", err)
+    }
+
+    if let Some(wav_path) = matches.value_of("record-audio") {
+        match File::create(wav_path).and_then(|file| WavWriter::new(BufWriter::new(file), options.audio_rate)) {
+            Ok(writer) => rustboy.add_audio_sink(Box::new(writer)),
+            Err(err) => {
+                error!("Unable to create {}: {}", wav_path, err);
+                process::exit(1);
+            }
+        }
+    }
+
+    if let Some(video_path) = matches.value_of("record") {
+        if let Err(err) = rustboy.start_recording(Path::new(video_path)) {
+            error!("Unable to start recording to {}: {}", video_path, err);
+            process::exit(1);
+        }
+    }
+
+    match open_trace(&matches) {
+        Ok(Some((out, filter))) => rustboy.set_trace(out, filter),
+        Ok(None) => (),
+        Err(err) => {
+            error!("{}", err);
+            process::exit(1);
+        }
+    }
+
     rustboy.run();
+}
+
+// Sets up an emulator for running without a window, with the options shared with the windowed frontend
+fn headless_emulator<'a>(cart: &'a mut Cartridge, matches: &ArgMatches) -> Result<Emulator<'a>, String> {
+    let mut emulator = Emulator::new(cart);
+
+    if let Some(boot_rom_path) = matches.value_of("boot-rom") {
+        fs::read(boot_rom_path).map_err(|err| err.to_string())
+            .and_then(|boot_rom| emulator.load_boot_rom(boot_rom))
+            .map_err(|err| format!("Unable to load boot ROM {}: {}", boot_rom_path, err))?;
+    }
+
+    if let Some(wav_path) = matches.value_of("record-audio") {
+        let audio_rate = matches.value_of("audio-rate").unwrap().parse().unwrap();
+        let writer = File::create(wav_path).and_then(|file| WavWriter::new(BufWriter::new(file), audio_rate))
+            .map_err(|err| format!("Unable to create {}: {}", wav_path, err))?;
+
+        emulator.add_audio_sink(Box::new(writer));
+    }
+
+    if let Some((out, filter)) = open_trace(matches)? {
+        emulator.set_trace(out, filter);
+    }
+
+    Ok(emulator)
+}
+
+// Finishes the WAV recording and trace log of a headless run. The exit status becomes 1 if either couldn't be written.
+fn finish_headless(emulator: &mut Emulator, status: i32) -> i32 {
+    let mut status = status;
+
+    if let Err(err) = emulator.finish_audio() {
+        error!("Unable to finish audio recording: {}", err);
+        status = 1;
+    }
+
+    if let Err(err) = emulator.finish_trace() {
+        error!("Unable to write trace: {}", err);
+        status = 1;
+    }
+
+    status
+}
+
+// Runs the ROM without a window and saves the last frame. Returns the exit status.
+fn headless_screenshot(emulator: &mut Emulator, frames: &str, path: &Path, scale: usize) -> i32 {
+    let frames = match parse_number(frames) {
+        Ok(frames) => frames as usize,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    let image = testrom::run_frames(emulator, frames).scale(scale);
+
+    match File::create(path).and_then(|file| screenshot::write_png(BufWriter::new(file), &image)) {
+        Ok(_) => {
+            info!("Saved screenshot to {}", path.display());
+            0
+        },
+        Err(err) => {
+            error!("Unable to save screenshot to {}: {}", path.display(), err);
+            1
+        }
+    }
+}
+
+// Runs a test ROM headless and prints what it sent out the serial port. Returns the exit status.
+fn run_test(matches: &ArgMatches) -> i32 {
+    let rom_arg = matches.value_of("ROM").unwrap();
+
+    if !Path::new(rom_arg).is_file() {
+        error!("File {} does not exist.", rom_arg);
+        return 1;
+    }
+
+    let max_cycles = match matches.value_of("cycles").map_or(Ok(testrom::DEFAULT_MAX_CYCLES), parse_number) {
+        Ok(max_cycles) => max_cycles,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    let mut cart = Cartridge::new(rom_arg);
+
+    let mut emulator = match headless_emulator(&mut cart, matches) {
+        Ok(emulator) => emulator,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    let status = match matches.value_of("reference") {
+        Some(reference) => run_screenshot_test(matches, &mut emulator, Path::new(reference)),
+        None => run_test_rom(rom_arg, &mut emulator, max_cycles)
+    };
+
+    finish_headless(&mut emulator, status)
+}
+
+// Runs a test ROM until it reports a result and prints it
+fn run_test_rom(rom_arg: &str, emulator: &mut Emulator, max_cycles: u64) -> i32 {
+    let report = testrom::run(emulator, max_cycles);
+
+    if !report.serial.is_empty() {
+        println!("{}", report.serial.trim_end());
+    }
+
+    if let Some(lockup) = report.lockup {
+        println!("{}", lockup);
+    }
+
+    println!("{}: {} after {} cycles", rom_arg, report.outcome, report.cycles);
+
+    match report.outcome {
+        Outcome::Passed => 0,
+        Outcome::Failed => 1,
+        Outcome::Timeout => 2
+    }
+}
+
+// Runs a ROM for a number of frames and compares the screen against a reference image.
+// A diff image is written if they don't match.
+fn run_screenshot_test(matches: &ArgMatches, emulator: &mut Emulator, reference_path: &Path) -> i32 {
+    let options = matches.value_of("frames").map_or(Ok(testrom::DEFAULT_FRAMES as u64), parse_number)
+        .and_then(|frames| matches.value_of("palette").map_or(Ok(DmgPalette::default()), str::parse).map(|palette| (frames, palette)));
+
+    let (frames, palette) = match options {
+        Ok(options) => options,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    let reference = match File::open(reference_path).and_then(screenshot::read_png) {
+        Ok(reference) => reference,
+        Err(err) => {
+            error!("Unable to read {}: {}", reference_path.display(), err);
+            return 1;
+        }
+    };
+
+    let rom_arg = matches.value_of("ROM").unwrap();
+    let frame = testrom::run_frames(emulator, frames as usize);
+
+    let comparison = match screenshot::compare(&frame, &reference, &palette) {
+        Ok(comparison) => comparison,
+        Err(err) => {
+            error!("{}", err);
+            return 1;
+        }
+    };
+
+    if comparison.mismatched == 0 {
+        println!("{}: Passed after {} frames", rom_arg, frames);
+        return 0;
+    }
+
+    let diff_path = matches.value_of("diff").map_or_else(|| Path::new(rom_arg).with_extension("diff.png"), |path| Path::new(path).to_path_buf());
+
+    if let Err(err) = File::create(&diff_path).and_then(|file| screenshot::write_png(BufWriter::new(file), &comparison.diff)) {
+        error!("Unable to write {}: {}", diff_path.display(), err);
+    }
+
+    println!("{}: Failed after {} frames, {} pixels differ (see {})", rom_arg, frames, comparison.mismatched, diff_path.display());
+    1
+}
+
+// Numbers are decimal unless prefixed with 0x
+fn parse_number(val: &str) -> Result<u64, String> {
+    let result = if val.starts_with("0x") || val.starts_with("0X") {
+        u64::from_str_radix(&val[2..], 16)
+    } else {
+        val.parse()
+    };
+
+    result.map_err(|_| format!("Invalid number: {}", val))
+}
+
+fn parse_addr(val: &str) -> Result<u16, String> {
+    match parse_number(val)? {
+        addr if addr <= 0xFFFF => Ok(addr as u16),
+        _ => Err(format!("Address out of range: {}", val))
+    }
+}
+
+// Where the trace log goes, and which instructions it includes
+type TraceLog = (Box<dyn Write>, TraceFilter);
+
+// Creates the trace log file and its filter, if --trace was given
+fn open_trace(matches: &ArgMatches) -> Result<Option<TraceLog>, String> {
+    // Checked here rather than by clap, which validates before global arguments are passed down to subcommands
+    let trace_path = match matches.value_of("trace") {
+        Some(trace_path) => trace_path,
+        None if ["trace-pc", "trace-bank", "trace-skip"].iter().any(|arg| matches.is_present(arg)) => {
+            return Err("--trace-pc, --trace-bank and --trace-skip need --trace".to_string());
+        },
+        None => return Ok(None)
+    };
+
+    let filter = trace_filter(matches)?;
+    let file = File::create(trace_path).map_err(|err| format!("Unable to create {}: {}", trace_path, err))?;
+
+    Ok(Some((Box::new(BufWriter::new(file)), filter)))
+}
+
+fn trace_filter(matches: &ArgMatches) -> Result<TraceFilter, String> {
+    let mut filter = TraceFilter::default();
+
+    if let Some(range) = matches.value_of("trace-pc") {
+        let mut parts = range.splitn(2, '-');
+        let start = parse_addr(parts.next().unwrap_or(""))?;
+        let end = parts.next().map_or(Ok(start), parse_addr)?;
+
+        if end < start {
+            return Err(format!("Invalid trace range: {}", range));
+        }
+
+        filter.pc_range = Some((start, end));
+    }
+
+    if let Some(bank) = matches.value_of("trace-bank") {
+        filter.bank = Some(parse_number(bank)? as usize);
+    }
+
+    if let Some(skip) = matches.value_of("trace-skip") {
+        filter.skip = parse_number(skip)?;
+    }
+
+    Ok(filter)
 }
\ No newline at end of file
@@ -0,0 +1,73 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+// Save state files start with a magic number and a format version.
+// The version must be bumped whenever any component changes what it writes.
+pub const MAGIC: &[u8; 4] = b"RBSS";
+pub const VERSION: u16 = 8;
+
+// Implemented by every component that holds state that must survive a save/load.
+// Components write their fields in a fixed order and read them back in that same order.
+pub trait Savestate {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()>;
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()>;
+}
+
+pub fn write_header(out: &mut dyn Write) -> io::Result<()> {
+    out.write_all(MAGIC)?;
+    out.write_u16::<LittleEndian>(VERSION)
+}
+
+pub fn read_header(input: &mut dyn Read) -> io::Result<()> {
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+
+    if &magic != MAGIC {
+        return Err(invalid_data("Not a Rustboy save state"));
+    }
+
+    let version = input.read_u16::<LittleEndian>()?;
+
+    if version != VERSION {
+        return Err(invalid_data(&format!("Unsupported save state version {} (expected {})", version, VERSION)));
+    }
+
+    Ok(())
+}
+
+pub fn write_bool(out: &mut dyn Write, val: bool) -> io::Result<()> {
+    out.write_u8(val as u8)
+}
+
+pub fn read_bool(input: &mut dyn Read) -> io::Result<bool> {
+    Ok(input.read_u8()? != 0)
+}
+
+// usize fields (cycle counters and the like) are always stored as 64 bits so states are portable
+pub fn write_usize(out: &mut dyn Write, val: usize) -> io::Result<()> {
+    out.write_u64::<LittleEndian>(val as u64)
+}
+
+pub fn read_usize(input: &mut dyn Read) -> io::Result<usize> {
+    Ok(input.read_u64::<LittleEndian>()? as usize)
+}
+
+// Variable length data is prefixed with its length. Loading fails if the length doesn't match the destination.
+pub fn write_bytes(out: &mut dyn Write, data: &[u8]) -> io::Result<()> {
+    write_usize(out, data.len())?;
+    out.write_all(data)
+}
+
+pub fn read_bytes(input: &mut dyn Read, data: &mut [u8]) -> io::Result<()> {
+    let len = read_usize(input)?;
+
+    if len != data.len() {
+        return Err(invalid_data(&format!("Save state has {} bytes where {} were expected", len, data.len())));
+    }
+
+    input.read_exact(data)
+}
+
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
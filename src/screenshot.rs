@@ -0,0 +1,255 @@
+use lcd::{SCREEN_WIDTH, SCREEN_HEIGHT, SHADES};
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Matching pixels are faded towards white in the diff image, mismatches are drawn in red
+const DIFF_COLOR: u32 = 0xFF0000;
+
+// RGB pixels, one u32 per pixel like the frame buffer
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u32>
+}
+
+impl Image {
+    // Copies a frame from the emulator's frame buffer
+    pub fn from_frame(framebuffer: &[u32]) -> Self {
+        Self {
+            width: SCREEN_WIDTH,
+            height: SCREEN_HEIGHT,
+            pixels: framebuffer.to_vec()
+        }
+    }
+
+    // Enlarges the image by repeating every pixel factor times in each direction
+    pub fn scale(&self, factor: usize) -> Self {
+        let width = self.width * factor;
+        let height = self.height * factor;
+        let pixels = (0..width * height).map(|i| self.pixels[(i / width / factor) * self.width + (i % width) / factor]).collect();
+
+        Self { width, height, pixels }
+    }
+}
+
+// Screenshots and recordings are stored next to the ROM as <rom>-YYYYMMDD-HHMMSS.<extension>, in UTC
+pub fn timestamped_path(rom_path: &Path, time: SystemTime, extension: &str) -> PathBuf {
+    let secs = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_date(secs / 86400);
+    let stem = rom_path.file_stem().map_or_else(|| String::from("screenshot"), |stem| stem.to_string_lossy().into_owned());
+
+    rom_path.with_file_name(format!("{}-{:04}{:02}{:02}-{:02}{:02}{:02}.{}", stem, year, month, day, secs / 3600 % 24, secs / 60 % 60, secs % 60, extension))
+}
+
+// Converts days since 1970-01-01 to a year, month and day in the Gregorian calendar
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    // Count from 0000-03-01 so leap days fall at the end of each year
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days % 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = era * 400 + year_of_era + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+// Colors the four DMG shades are replaced with before comparing against a reference.
+// Reference images from test suites are usually in grayscale rather than the emulator's greens.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DmgPalette(pub [u32; 4]);
+
+impl Default for DmgPalette {
+    fn default() -> Self {
+        DmgPalette([0xFFFFFF, 0xAAAAAA, 0x555555, 0x000000])
+    }
+}
+
+// Four comma separated hex colors from white to black, e.g. FFFFFF,AAAAAA,555555,000000
+impl FromStr for DmgPalette {
+    type Err = String;
+
+    fn from_str(val: &str) -> Result<Self, String> {
+        let colors = val.split(',')
+            .map(|color| u32::from_str_radix(color.trim().trim_start_matches('#'), 16).ok().filter(|&rgb| rgb <= 0xFFFFFF))
+            .collect::<Option<Vec<u32>>>();
+
+        match colors {
+            Some(ref colors) if colors.len() == 4 => Ok(DmgPalette([colors[0], colors[1], colors[2], colors[3]])),
+            _ => Err(format!("Invalid palette: {}", val))
+        }
+    }
+}
+
+impl fmt::Display for DmgPalette {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:06X},{:06X},{:06X},{:06X}", self.0[0], self.0[1], self.0[2], self.0[3])
+    }
+}
+
+impl DmgPalette {
+    // Maps a pixel drawn by the LCD to this palette. Colors that aren't DMG shades are kept.
+    pub fn map(&self, rgb: u32) -> u32 {
+        match SHADES.iter().position(|&shade| shade == rgb) {
+            Some(index) => self.0[index],
+            None => rgb
+        }
+    }
+}
+
+pub struct Comparison {
+    pub mismatched: usize,      // Number of pixels that differ from the reference
+    pub diff: Image
+}
+
+// Compares a frame against a reference image after mapping its shades through the palette
+pub fn compare(frame: &Image, reference: &Image, palette: &DmgPalette) -> Result<Comparison, String> {
+    if frame.width != reference.width || frame.height != reference.height {
+        return Err(format!("Reference is {}x{} but the frame is {}x{}", reference.width, reference.height, frame.width, frame.height));
+    }
+
+    let mut mismatched = 0;
+
+    let pixels = frame.pixels.iter().zip(reference.pixels.iter()).map(|(&pixel, &expected)| {
+        if palette.map(pixel) == expected {
+            fade(expected)
+        } else {
+            mismatched += 1;
+            DIFF_COLOR
+        }
+    }).collect();
+
+    Ok(Comparison {
+        mismatched,
+        diff: Image { width: frame.width, height: frame.height, pixels }
+    })
+}
+
+// Blends a color 3/4 of the way to white
+fn fade(rgb: u32) -> u32 {
+    [16, 8, 0].iter().fold(0, |faded, &shift| {
+        let channel = (rgb >> shift) & 0xFF;
+        faded | ((0xFF - (0xFF - channel) / 4) << shift)
+    })
+}
+
+pub fn write_png<W: Write>(out: W, image: &Image) -> io::Result<()> {
+    let mut encoder = Encoder::new(out, image.width as u32, image.height as u32);
+    encoder.set_color(ColorType::Rgb);
+    encoder.set_depth(BitDepth::Eight);
+
+    let data = image.pixels.iter()
+        .flat_map(|&rgb| vec![(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+        .collect::<Vec<u8>>();
+
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(&data).map_err(png_error)?;
+    writer.finish().map_err(png_error)
+}
+
+// Reads any 8 or 16 bit PNG. Transparency is ignored.
+pub fn read_png<R: Read>(input: R) -> io::Result<Image> {
+    let mut decoder = Decoder::new(input);
+    decoder.set_transformations(Transformations::EXPAND | Transformations::STRIP_16);
+
+    let mut reader = decoder.read_info().map_err(png_error)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(png_error)?;
+
+    let channels = match info.color_type {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb => 3,
+        ColorType::Rgba => 4,
+        ColorType::Indexed => return Err(io::Error::new(io::ErrorKind::InvalidData, "Indexed PNG wasn't expanded"))
+    };
+
+    let pixels = data[..info.buffer_size()].chunks(channels).map(|pixel| {
+        if channels < 3 {
+            let gray = pixel[0] as u32;
+            (gray << 16) | (gray << 8) | gray
+        } else {
+            ((pixel[0] as u32) << 16) | ((pixel[1] as u32) << 8) | (pixel[2] as u32)
+        }
+    }).collect();
+
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels
+    })
+}
+
+fn png_error<E: fmt::Display>(err: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn image(pixels: &[u32]) -> Image {
+        Image { width: 2, height: pixels.len() / 2, pixels: pixels.to_vec() }
+    }
+
+    #[test]
+    fn parse_palette() {
+        assert_eq!("ffffff,#AAAAAA, 555555,000000".parse(), Ok(DmgPalette::default()));
+        assert!("FFFFFF,AAAAAA,555555".parse::<DmgPalette>().is_err());
+        assert!("FFFFFF,AAAAAA,555555,1000000".parse::<DmgPalette>().is_err());
+        assert_eq!(DmgPalette::default().to_string(), "FFFFFF,AAAAAA,555555,000000");
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let original = image(&[0x123456, 0xFFFFFF, 0x000000, 0xABCDEF]);
+
+        let mut data = Vec::new();
+        write_png(&mut data, &original).unwrap();
+
+        let decoded = read_png(data.as_slice()).unwrap();
+        assert_eq!((decoded.width, decoded.height), (2, 2));
+        assert_eq!(decoded.pixels, original.pixels);
+
+        assert!(read_png(&b"not a png"[..]).is_err());
+    }
+
+    #[test]
+    fn scale_repeats_pixels() {
+        let scaled = image(&[1, 2, 3, 4]).scale(2);
+
+        assert_eq!((scaled.width, scaled.height), (4, 4));
+        assert_eq!(scaled.pixels, [1, 1, 2, 2, 1, 1, 2, 2, 3, 3, 4, 4, 3, 3, 4, 4]);
+    }
+
+    #[test]
+    fn screenshot_paths_are_timestamped() {
+        use std::time::Duration;
+
+        // 2024-02-29 13:05:09 UTC
+        let time = UNIX_EPOCH + Duration::from_secs(1_709_211_909);
+        assert_eq!(timestamped_path(Path::new("roms/tetris.gb"), time, "png"), Path::new("roms/tetris-20240229-130509.png"));
+
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(11_016), (2000, 2, 29));
+    }
+
+    #[test]
+    fn compare_maps_shades() {
+        let frame = image(&[SHADES[0], SHADES[1], SHADES[2], SHADES[3]]);
+        let reference = image(&[0xFFFFFF, 0xAAAAAA, 0x555555, 0x555555]);
+
+        let comparison = compare(&frame, &reference, &DmgPalette::default()).unwrap();
+        assert_eq!(comparison.mismatched, 1);
+        assert_eq!(comparison.diff.pixels, [0xFFFFFF, 0xEAEAEA, 0xD5D5D5, DIFF_COLOR]);
+
+        assert!(compare(&frame, &image(&[0; 2]), &DmgPalette::default()).is_err());
+    }
+}
@@ -1,4 +1,7 @@
 use bus::Addressable;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
 
 const ADDR_SB: u16 = 0xFF01;
 const ADDR_SC: u16 = 0xFF02;
@@ -16,14 +19,22 @@ bitflags! {
 
 #[derive(Default)]
 pub struct SerialResult {
-    pub interrupt: bool         // Interrupt is raised after a byte is transferred
+    pub interrupt: bool,        // Interrupt is raised after a byte is transferred
+    pub sent: Option<u8>        // Byte that started being sent out since the last step
 }
 
 pub struct Serial {
     sb: u8,                     // SB stores the byte of data to be transferred out the serial port. It is replaced with a byte coming from the other side.
     sc: Sc,                     // SC controls the serial interface
     transfer_bit: usize,        // The current bit being transferred out of the serial connection
-    transfer_bit_cycles: usize  // Running tally of clock cycles for the current bit transfer
+    transfer_bit_cycles: usize, // Running tally of clock cycles for the current bit transfer
+    sent: Option<u8>            // Set when a transfer starts. Always picked up by the following step, so it isn't saved.
+}
+
+impl Default for Serial {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Serial {
@@ -32,7 +43,8 @@ impl Serial {
             sb: 0,
             sc: Sc::empty(),
             transfer_bit: 0,
-            transfer_bit_cycles: 0
+            transfer_bit_cycles: 0,
+            sent: None
         }
     }
 
@@ -47,7 +59,7 @@ impl Serial {
             // On every tick of the serial clock, shift a bit off of SB
             // In a real GB, the bit from the other GB would be shifted in
             if self.transfer_bit_cycles >= INT_CLOCK_CYCLES {
-                self.sb = self.sb << 1;
+                self.sb <<= 1;
 
                 // When all 8 bits are transferred, raise an interrupt and update SC to disable transfer
                 if self.transfer_bit == 7 {
@@ -62,14 +74,15 @@ impl Serial {
         }
 
         SerialResult {
-            interrupt: interrupt
+            interrupt,
+            sent: self.sent.take()
         }
     }
 }
 
 impl Addressable for Serial {
     fn read(&self, addr: u16) -> u8 {
-        return match addr {
+        match addr {
             ADDR_SB => self.sb,
             ADDR_SC => self.sc.bits(),
             _ => unreachable!()
@@ -79,8 +92,33 @@ impl Addressable for Serial {
     fn write(&mut self, addr: u16, byte: u8) {
         match addr {
             ADDR_SB => self.sb = byte,
-            ADDR_SC => self.sc = Sc::from_bits(byte & 0b1000_0001).unwrap(),
+            ADDR_SC => {
+                self.sc = Sc::from_bits(byte & 0b1000_0001).unwrap();
+
+                // Test ROMs print their results by sending text out the serial port
+                if self.sc.contains(Sc::SC_CLOCK | Sc::SC_START_TRANSFER) {
+                    self.sent = Some(self.sb);
+                }
+            },
             _ => unreachable!()
         };
     }
-}
\ No newline at end of file
+}
+
+impl Savestate for Serial {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.sb)?;
+        out.write_u8(self.sc.bits())?;
+        savestate::write_usize(out, self.transfer_bit)?;
+        savestate::write_usize(out, self.transfer_bit_cycles)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.sb = input.read_u8()?;
+        self.sc = Sc::from_bits_truncate(input.read_u8()?);
+        self.transfer_bit = savestate::read_usize(input)?;
+        self.transfer_bit_cycles = savestate::read_usize(input)?;
+
+        Ok(())
+    }
+}
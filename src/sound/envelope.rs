@@ -0,0 +1,84 @@
+use byteorder::ReadBytesExt;
+use savestate::Savestate;
+use std::io::{self, Read, Write};
+
+// Volume envelope used by the square and noise channels.
+// NRx2 register layout: VVVV APPP
+// VVVV is the starting volume, A is the direction (1 = increase), PPP is the period in 64Hz ticks
+pub struct Envelope {
+    register: u8,
+    pub volume: u8,
+    timer: u8
+}
+
+impl Envelope {
+    pub fn new() -> Self {
+        Self {
+            register: 0,
+            volume: 0,
+            timer: 0
+        }
+    }
+
+    pub fn read(&self) -> u8 {
+        self.register
+    }
+
+    pub fn write(&mut self, val: u8) {
+        self.register = val;
+    }
+
+    // The channel's DAC is powered as long as either the starting volume or direction bits are set
+    pub fn dac_enabled(&self) -> bool {
+        self.register & 0xF8 != 0
+    }
+
+    pub fn trigger(&mut self) {
+        self.volume = self.register >> 4;
+        self.timer = self.period();
+    }
+
+    pub fn clock(&mut self) {
+        if self.register & 0b111 == 0 {
+            return;
+        }
+
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+
+        if self.timer == 0 {
+            self.timer = self.period();
+
+            if self.register & 0b1000 != 0 {
+                if self.volume < 15 {
+                    self.volume += 1;
+                }
+            } else if self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    // A period of 0 is treated as 8
+    fn period(&self) -> u8 {
+        match self.register & 0b111 {
+            0 => 8,
+            period => period
+        }
+    }
+}
+
+impl Savestate for Envelope {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_all(&[self.register, self.volume, self.timer])
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.register = input.read_u8()?;
+        self.volume = input.read_u8()? & 0x0F;
+        self.timer = input.read_u8()?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,57 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+
+// Length counter shared by all channels.
+// When enabled, it counts down at 256Hz and turns the channel off when it reaches 0.
+pub struct LengthCounter {
+    max: u16,                   // 64 for the square and noise channels, 256 for the wave channel
+    counter: u16,
+    pub enabled: bool
+}
+
+impl LengthCounter {
+    pub fn new(max: u16) -> Self {
+        Self {
+            max,
+            counter: 0,
+            enabled: false
+        }
+    }
+
+    // Length registers hold the number of steps already taken, not the number remaining
+    pub fn load(&mut self, val: u8) {
+        self.counter = self.max - (val as u16 & (self.max - 1));
+    }
+
+    // Triggering a channel with an expired counter restarts it at the full length
+    pub fn trigger(&mut self) {
+        if self.counter == 0 {
+            self.counter = self.max;
+        }
+    }
+
+    // Returns true when the counter expires and the channel should be disabled
+    pub fn clock(&mut self) -> bool {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            self.counter == 0
+        } else {
+            false
+        }
+    }
+}
+
+impl Savestate for LengthCounter {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u16::<LittleEndian>(self.counter)?;
+        savestate::write_bool(out, self.enabled)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.counter = input.read_u16::<LittleEndian>()?.min(self.max);
+        self.enabled = savestate::read_bool(input)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+mod envelope;
+mod length;
+mod noise;
+mod sink;
+mod square;
+mod wav;
+mod wave;
+
+#[cfg(test)]
+mod tests;
+
+use bus::Addressable;
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use self::noise::Noise;
+use self::square::Square;
+use self::wave::Wave;
+
+pub use self::sink::{AudioSink, Resampler};
+pub use self::wav::WavWriter;
+
+const ADDR_NR50: u16 = 0xFF24;
+const ADDR_NR51: u16 = 0xFF25;
+const ADDR_NR52: u16 = 0xFF26;
+
+const WAVE_RAM_START: u16 = 0xFF30;
+const WAVE_RAM_END: u16 = 0xFF3F;
+
+// Bits that always read back as 1 for each register in 0xFF10 - 0xFF2F.
+// Write-only bits and unused registers are not readable.
+const READ_MASKS: [u8; 32] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF,   // NR10 - NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF,   // NR20 - NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF,   // NR30 - NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF,   // NR40 - NR44
+    0x00, 0x00, 0x70,               // NR50 - NR52
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF
+];
+
+// The frame sequencer steps at 512Hz, on the falling edge of bit 4 of DIV
+const DIV_FRAME_SEQUENCER_BIT: u8 = 0b0001_0000;
+
+pub struct Sound {
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+
+    nr50: u8,                   // Master volume. Bits 6-4 are the left volume, bits 2-0 the right.
+    nr51: u8,                   // Panning. Upper nibble enables channels 4-1 on the left, lower on the right.
+    power: bool,
+
+    frame_step: u8,             // Frame sequencer step (0-7)
+    div_bit: bool               // Last seen state of the DIV bit that clocks the frame sequencer
+}
+
+impl Default for Sound {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Sound {
+    pub fn new() -> Self {
+        Self {
+            square1: Square::new(true),
+            square2: Square::new(false),
+            wave: Wave::new(),
+            noise: Noise::new(),
+            nr50: 0,
+            nr51: 0,
+            power: false,
+            frame_step: 0,
+            div_bit: false
+        }
+    }
+
+    // Puts the APU in the state the boot ROM leaves it in. The startup chime on channel 1 has faded out,
+    // but the channel is still on, and every channel is sent to both speakers at full volume.
+    pub fn reset(&mut self) {
+        self.write_nr52(0x80);
+
+        self.write(0xFF11, 0x80);
+        self.write(0xFF12, 0xF3);
+        self.write(0xFF13, 0xC1);
+        self.write(0xFF14, 0x87);
+
+        // The envelope drops a step every 3 ticks
+        for _ in 0..15 * 3 {
+            self.square1.clock_envelope();
+        }
+
+        self.nr50 = 0x77;
+        self.nr51 = 0xF3;
+    }
+
+    // Steps all channels. div is the current value of the timer's DIV register.
+    pub fn step(&mut self, cycles: usize, div: u8) {
+        let div_bit = div & DIV_FRAME_SEQUENCER_BIT != 0;
+        let falling_edge = self.div_bit && !div_bit;
+        self.div_bit = div_bit;
+
+        if !self.power {
+            return;
+        }
+
+        self.square1.step(cycles);
+        self.square2.step(cycles);
+        self.wave.step(cycles);
+        self.noise.step(cycles);
+
+        if falling_edge {
+            self.step_frame_sequencer();
+        }
+    }
+
+    // Step   Length Ctr  Vol Env     Sweep
+    // ---------------------------------------
+    // 0      Clock       -           -
+    // 1      -           -           -
+    // 2      Clock       -           Clock
+    // 3      -           -           -
+    // 4      Clock       -           -
+    // 5      -           -           -
+    // 6      Clock       -           Clock
+    // 7      -           Clock       -
+    fn step_frame_sequencer(&mut self) {
+        if self.frame_step & 1 == 0 {
+            self.square1.clock_length();
+            self.square2.clock_length();
+            self.wave.clock_length();
+            self.noise.clock_length();
+        }
+
+        if self.frame_step == 2 || self.frame_step == 6 {
+            self.square1.clock_sweep();
+        }
+
+        if self.frame_step == 7 {
+            self.square1.clock_envelope();
+            self.square2.clock_envelope();
+            self.noise.clock_envelope();
+        }
+
+        self.frame_step = (self.frame_step + 1) & 0b111;
+    }
+
+    // Mixes the channels into a (left, right) sample pair, each in the range -1.0 to 1.0
+    pub fn output(&self) -> (f32, f32) {
+        if !self.power {
+            return (0.0, 0.0);
+        }
+
+        // Each channel's DAC maps its 0-15 digital output to an analog -1.0 to 1.0.
+        // A DAC that is off outputs nothing.
+        let dac = |enabled: bool, val: u8| if enabled { val as f32 / 7.5 - 1.0 } else { 0.0 };
+
+        let channels = [
+            dac(self.square1.dac_enabled(), self.square1.output()),
+            dac(self.square2.dac_enabled(), self.square2.output()),
+            dac(self.wave.dac_enabled(), self.wave.output()),
+            dac(self.noise.dac_enabled(), self.noise.output())
+        ];
+
+        let mut left = 0.0;
+        let mut right = 0.0;
+
+        for (i, sample) in channels.iter().enumerate() {
+            if self.nr51 & (0x10 << i) != 0 {
+                left += sample;
+            }
+
+            if self.nr51 & (0x01 << i) != 0 {
+                right += sample;
+            }
+        }
+
+        let left_volume = (((self.nr50 >> 4) & 0b111) + 1) as f32 / 8.0;
+        let right_volume = ((self.nr50 & 0b111) + 1) as f32 / 8.0;
+
+        (left / 4.0 * left_volume, right / 4.0 * right_volume)
+    }
+
+    fn read_nr52(&self) -> u8 {
+        ((self.power as u8) << 7) |
+            ((self.noise.enabled as u8) << 3) |
+            ((self.wave.enabled as u8) << 2) |
+            ((self.square2.enabled as u8) << 1) |
+            (self.square1.enabled as u8)
+    }
+
+    fn write_nr52(&mut self, val: u8) {
+        let power = val & 0b1000_0000 != 0;
+
+        if self.power && !power {
+            // Powering off clears every register except wave RAM
+            self.square1.reset();
+            self.square2.reset();
+            self.wave.reset();
+            self.noise.reset();
+            self.nr50 = 0;
+            self.nr51 = 0;
+        } else if !self.power && power {
+            self.frame_step = 0;
+        }
+
+        self.power = power;
+    }
+}
+
+impl Addressable for Sound {
+    fn read(&self, addr: u16) -> u8 {
+        let val = match addr {
+            0xFF10..=0xFF14 => self.square1.read(addr - 0xFF10),
+            0xFF15..=0xFF19 => self.square2.read(addr - 0xFF15),
+            0xFF1A..=0xFF1E => self.wave.read(addr - 0xFF1A),
+            0xFF1F..=0xFF23 => self.noise.read(addr - 0xFF1F),
+            ADDR_NR50 => self.nr50,
+            ADDR_NR51 => self.nr51,
+            ADDR_NR52 => self.read_nr52(),
+            WAVE_RAM_START..=WAVE_RAM_END => return self.wave.ram[(addr - WAVE_RAM_START) as usize],
+            _ => 0
+        };
+
+        val | READ_MASKS[(addr - 0xFF10) as usize]
+    }
+
+    fn write(&mut self, addr: u16, val: u8) {
+        match addr {
+            ADDR_NR52 => return self.write_nr52(val),
+            WAVE_RAM_START..=WAVE_RAM_END => return self.wave.ram[(addr - WAVE_RAM_START) as usize] = val,
+            _ => { }
+        }
+
+        // While powered off, registers ignore writes except for the length counters
+        if !self.power {
+            match addr {
+                0xFF11 => self.square1.write_length(val),
+                0xFF16 => self.square2.write_length(val),
+                0xFF1B => self.wave.write(1, val),
+                0xFF20 => self.noise.write_length(val),
+                _ => { }
+            }
+
+            return;
+        }
+
+        match addr {
+            0xFF10..=0xFF14 => self.square1.write(addr - 0xFF10, val),
+            0xFF15..=0xFF19 => self.square2.write(addr - 0xFF15, val),
+            0xFF1A..=0xFF1E => self.wave.write(addr - 0xFF1A, val),
+            0xFF1F..=0xFF23 => self.noise.write(addr - 0xFF1F, val),
+            ADDR_NR50 => self.nr50 = val,
+            ADDR_NR51 => self.nr51 = val,
+            _ => { }
+        }
+    }
+}
+
+impl Savestate for Sound {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        self.square1.save_state(out)?;
+        self.square2.save_state(out)?;
+        self.wave.save_state(out)?;
+        self.noise.save_state(out)?;
+        out.write_u8(self.nr50)?;
+        out.write_u8(self.nr51)?;
+        savestate::write_bool(out, self.power)?;
+        out.write_u8(self.frame_step)?;
+        savestate::write_bool(out, self.div_bit)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.square1.load_state(input)?;
+        self.square2.load_state(input)?;
+        self.wave.load_state(input)?;
+        self.noise.load_state(input)?;
+        self.nr50 = input.read_u8()?;
+        self.nr51 = input.read_u8()?;
+        self.power = savestate::read_bool(input)?;
+        self.frame_step = input.read_u8()? & 0b111;
+        self.div_bit = savestate::read_bool(input)?;
+
+        Ok(())
+    }
+}
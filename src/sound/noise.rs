@@ -0,0 +1,152 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+// Base divisors selected by the lower 3 bits of NR43
+const DIVISORS: [usize; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Generates noise with a linear feedback shift register.
+// NR43 register layout: SSSS WDDD
+// SSSS is the clock shift, W switches the LFSR to 7 bit mode, DDD selects the divisor
+pub struct Noise {
+    pub enabled: bool,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    polynomial: u8,
+    lfsr: u16,
+    timer: usize
+}
+
+impl Noise {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            polynomial: 0,
+            lfsr: 0x7FFF,
+            timer: 8
+        }
+    }
+
+    // Registers are numbered 0-4 for NR40 (unused) - NR44
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 | 1 => 0,
+            2 => self.envelope.read(),
+            3 => self.polynomial,
+            4 => (self.length.enabled as u8) << 6,
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => { },
+            1 => self.length.load(val & 0x3F),
+            2 => {
+                self.envelope.write(val);
+
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.polynomial = val,
+            4 => {
+                self.length.enabled = val & 0b0100_0000 != 0;
+
+                if val & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.lfsr = 0x7FFF;
+        self.timer = self.period();
+    }
+
+    fn period(&self) -> usize {
+        DIVISORS[(self.polynomial & 0b111) as usize] << (self.polynomial >> 4)
+    }
+
+    pub fn step(&mut self, cycles: usize) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+
+            // XOR the lower two bits and shift the result in at the top.
+            // In 7 bit mode the result is also placed in bit 6.
+            let xor = (self.lfsr & 1) ^ ((self.lfsr >> 1) & 1);
+            self.lfsr = (self.lfsr >> 1) | (xor << 14);
+
+            if self.polynomial & 0b1000 != 0 {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (xor << 6);
+            }
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // Current digital output (0-15). The channel is high when bit 0 of the LFSR is clear.
+    pub fn output(&self) -> u8 {
+        if self.enabled && self.lfsr & 1 == 0 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0x3F);
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new();
+    }
+}
+
+impl Savestate for Noise {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.enabled)?;
+        self.length.save_state(out)?;
+        self.envelope.save_state(out)?;
+        out.write_u8(self.polynomial)?;
+        out.write_u16::<LittleEndian>(self.lfsr)?;
+        savestate::write_usize(out, self.timer)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.enabled = savestate::read_bool(input)?;
+        self.length.load_state(input)?;
+        self.envelope.load_state(input)?;
+        self.polynomial = input.read_u8()?;
+        self.lfsr = input.read_u16::<LittleEndian>()? & 0x7FFF;
+        self.timer = savestate::read_usize(input)?.max(1);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,62 @@
+use std::io;
+
+// The APU runs off of the CPU clock
+const CYCLES_PER_SECOND: f64 = 4_194_304.0;
+
+// Receives the mixed output of the APU as stereo samples in the range -1.0 to 1.0
+pub trait AudioSink {
+    // Rate in Hz the sink wants samples at
+    fn sample_rate(&self) -> u32;
+
+    fn push_sample(&mut self, left: f32, right: f32);
+
+    // Called once when emulation ends. Sinks that write to files report any errors here.
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Downsamples the APU output from the CPU clock to the sink's sample rate.
+// Every output sample is the average of the APU output over the cycles it covers,
+// which acts as a simple low pass filter.
+pub struct Resampler {
+    cycles_per_sample: f64,
+    cycles: f64,                // Cycles accumulated towards the next sample
+    left: f64,                  // Sum of the output weighted by the cycles it was held for
+    right: f64
+}
+
+impl Resampler {
+    pub fn new(sample_rate: u32) -> Self {
+        Self {
+            cycles_per_sample: CYCLES_PER_SECOND / sample_rate as f64,
+            cycles: 0.0,
+            left: 0.0,
+            right: 0.0
+        }
+    }
+
+    // Feeds the output held for the given number of cycles, pushing any completed samples to the sink
+    pub fn step(&mut self, cycles: usize, output: (f32, f32), sink: &mut dyn AudioSink) {
+        let (left, right) = (output.0 as f64, output.1 as f64);
+        let mut cycles = cycles as f64;
+
+        while self.cycles + cycles >= self.cycles_per_sample {
+            let used = self.cycles_per_sample - self.cycles;
+            cycles -= used;
+
+            self.left += left * used;
+            self.right += right * used;
+
+            sink.push_sample((self.left / self.cycles_per_sample) as f32, (self.right / self.cycles_per_sample) as f32);
+
+            self.cycles = 0.0;
+            self.left = 0.0;
+            self.right = 0.0;
+        }
+
+        self.cycles += cycles;
+        self.left += left * cycles;
+        self.right += right * cycles;
+    }
+}
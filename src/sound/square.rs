@@ -0,0 +1,286 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use super::envelope::Envelope;
+use super::length::LengthCounter;
+
+// Waveforms for the four duty cycles (12.5%, 25%, 50%, 75%). Each bit is one of the 8 steps of the wave.
+const DUTY_PATTERNS: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+// Frequency sweep. Only channel 1 has one.
+// NR10 register layout: -PPP NSSS
+// PPP is the period in 128Hz ticks, N negates the change, SSS is the shift
+struct Sweep {
+    register: u8,
+    enabled: bool,
+    timer: u8,
+    shadow: u16,                // Copy of the frequency the sweep calculations work off of
+    negated: bool               // A calculation in negate mode has been made since the last trigger
+}
+
+impl Sweep {
+    fn new() -> Self {
+        Self {
+            register: 0,
+            enabled: false,
+            timer: 0,
+            shadow: 0,
+            negated: false
+        }
+    }
+
+    fn period(&self) -> u8 {
+        (self.register >> 4) & 0b111
+    }
+
+    fn negate(&self) -> bool {
+        self.register & 0b1000 != 0
+    }
+
+    fn shift(&self) -> u8 {
+        self.register & 0b111
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = match self.period() {
+            0 => 8,
+            period => period
+        };
+    }
+
+    // Calculates the next frequency. Anything above 2047 overflows and disables the channel.
+    fn next_frequency(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift();
+
+        if self.negate() {
+            self.negated = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+}
+
+pub struct Square {
+    pub enabled: bool,
+    sweep: Option<Sweep>,
+    duty: u8,
+    duty_step: u8,
+    pub length: LengthCounter,
+    envelope: Envelope,
+    frequency: u16,             // 11 bit value. The tone is 131072/(2048-frequency) Hz.
+    timer: usize                // Cycles until the next duty step
+}
+
+impl Square {
+    pub fn new(sweep: bool) -> Self {
+        Self {
+            enabled: false,
+            sweep: if sweep { Some(Sweep::new()) } else { None },
+            duty: 0,
+            duty_step: 0,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            frequency: 0,
+            timer: 8192
+        }
+    }
+
+    // Registers are numbered 0-4 for NRx0-NRx4. Write-only bits read back as 1 via masks applied by the caller.
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => self.sweep.as_ref().map_or(0, |sweep| sweep.register),
+            1 => self.duty << 6,
+            2 => self.envelope.read(),
+            3 => 0,
+            4 => (self.length.enabled as u8) << 6,
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                if let Some(ref mut sweep) = self.sweep {
+                    // Switching out of negate mode after a negated calculation disables the channel
+                    if sweep.negated && sweep.negate() && val & 0b1000 == 0 {
+                        self.enabled = false;
+                    }
+
+                    sweep.register = val & 0x7F;
+                }
+            },
+            1 => {
+                self.duty = val >> 6;
+                self.length.load(val & 0x3F);
+            },
+            2 => {
+                self.envelope.write(val);
+
+                if !self.envelope.dac_enabled() {
+                    self.enabled = false;
+                }
+            },
+            3 => self.frequency = (self.frequency & 0x700) | (val as u16),
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((val & 0b111) as u16) << 8);
+                self.length.enabled = val & 0b0100_0000 != 0;
+
+                if val & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+
+    // Only the length part of NRx1 can be written while the APU is powered off
+    pub fn write_length(&mut self, val: u8) {
+        self.length.load(val & 0x3F);
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger();
+        self.envelope.trigger();
+        self.timer = self.period();
+
+        let frequency = self.frequency;
+
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.shadow = frequency;
+            sweep.negated = false;
+            sweep.reload_timer();
+            sweep.enabled = sweep.period() != 0 || sweep.shift() != 0;
+
+            // Overflow is checked immediately if there is a shift
+            if sweep.shift() != 0 && sweep.next_frequency() > 2047 {
+                self.enabled = false;
+            }
+        }
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.frequency as usize) * 4
+    }
+
+    pub fn step(&mut self, cycles: usize) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.duty_step = (self.duty_step + 1) & 0b111;
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn clock_envelope(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_sweep(&mut self) {
+        let mut disable = false;
+        let mut new_frequency = None;
+
+        if let Some(ref mut sweep) = self.sweep {
+            if sweep.timer > 0 {
+                sweep.timer -= 1;
+            }
+
+            if sweep.timer == 0 {
+                sweep.reload_timer();
+
+                if sweep.enabled && sweep.period() != 0 {
+                    let frequency = sweep.next_frequency();
+
+                    if frequency > 2047 {
+                        disable = true;
+                    } else if sweep.shift() != 0 {
+                        sweep.shadow = frequency;
+                        new_frequency = Some(frequency);
+
+                        // The new frequency is run through the overflow check a second time
+                        if sweep.next_frequency() > 2047 {
+                            disable = true;
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(frequency) = new_frequency {
+            self.frequency = frequency;
+        }
+
+        if disable {
+            self.enabled = false;
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.envelope.dac_enabled()
+    }
+
+    // Current digital output (0-15)
+    pub fn output(&self) -> u8 {
+        if self.enabled && (DUTY_PATTERNS[self.duty as usize] >> (7 - self.duty_step)) & 1 == 1 {
+            self.envelope.volume
+        } else {
+            0
+        }
+    }
+
+    pub fn reset(&mut self) {
+        *self = Self::new(self.sweep.is_some());
+    }
+}
+
+impl Savestate for Square {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.enabled)?;
+        out.write_u8(self.duty)?;
+        out.write_u8(self.duty_step)?;
+        self.length.save_state(out)?;
+        self.envelope.save_state(out)?;
+        out.write_u16::<LittleEndian>(self.frequency)?;
+        savestate::write_usize(out, self.timer)?;
+
+        if let Some(ref sweep) = self.sweep {
+            out.write_u8(sweep.register)?;
+            savestate::write_bool(out, sweep.enabled)?;
+            out.write_u8(sweep.timer)?;
+            out.write_u16::<LittleEndian>(sweep.shadow)?;
+            savestate::write_bool(out, sweep.negated)?;
+        }
+
+        Ok(())
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.enabled = savestate::read_bool(input)?;
+        self.duty = input.read_u8()? & 0b11;
+        self.duty_step = input.read_u8()? & 0b111;
+        self.length.load_state(input)?;
+        self.envelope.load_state(input)?;
+        self.frequency = input.read_u16::<LittleEndian>()? & 0x7FF;
+        self.timer = savestate::read_usize(input)?.max(1);
+
+        if let Some(ref mut sweep) = self.sweep {
+            sweep.register = input.read_u8()? & 0x7F;
+            sweep.enabled = savestate::read_bool(input)?;
+            sweep.timer = input.read_u8()?;
+            sweep.shadow = input.read_u16::<LittleEndian>()? & 0x7FF;
+            sweep.negated = savestate::read_bool(input)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,194 @@
+use bus::Addressable;
+use std::io::Cursor;
+use super::{AudioSink, Resampler, Sound, WavWriter};
+
+struct Samples {
+    samples: Vec<(f32, f32)>
+}
+
+impl AudioSink for Samples {
+    fn sample_rate(&self) -> u32 {
+        44100
+    }
+
+    fn push_sample(&mut self, left: f32, right: f32) {
+        self.samples.push((left, right));
+    }
+}
+
+fn powered_on() -> Sound {
+    let mut sound = Sound::new();
+    sound.write(0xFF26, 0x80);
+    sound
+}
+
+// Steps through one frame sequencer step by toggling DIV bit 4
+fn clock_frame_sequencer(sound: &mut Sound) {
+    sound.step(0, 0x10);
+    sound.step(0, 0x00);
+}
+
+#[test]
+fn register_read_masks() {
+    let mut sound = powered_on();
+
+    for addr in 0xFF10..0xFF26 {
+        sound.write(addr, 0);
+    }
+
+    assert_eq!(sound.read(0xFF10), 0x80);
+    assert_eq!(sound.read(0xFF11), 0x3F);
+    assert_eq!(sound.read(0xFF13), 0xFF);
+    assert_eq!(sound.read(0xFF14), 0xBF);
+    assert_eq!(sound.read(0xFF1A), 0x7F);
+    assert_eq!(sound.read(0xFF1C), 0x9F);
+    assert_eq!(sound.read(0xFF20), 0xFF);
+    assert_eq!(sound.read(0xFF26), 0xF0);
+    assert_eq!(sound.read(0xFF27), 0xFF);
+
+    sound.write(0xFF11, 0b1011_1111);
+    assert_eq!(sound.read(0xFF11), 0b1011_1111);
+}
+
+#[test]
+fn reset_matches_boot_rom() {
+    let mut sound = Sound::new();
+    assert_eq!(sound.read(0xFF26), 0x70);
+
+    sound.reset();
+    assert_eq!(sound.read(0xFF26), 0xF1);
+    assert_eq!(sound.read(0xFF24), 0x77);
+    assert_eq!(sound.read(0xFF25), 0xF3);
+    assert_eq!(sound.read(0xFF10), 0x80);
+    assert_eq!(sound.read(0xFF11), 0xBF);
+    assert_eq!(sound.read(0xFF12), 0xF3);
+    assert_eq!(sound.read(0xFF14), 0xBF);
+}
+
+#[test]
+fn power_off_clears_registers() {
+    let mut sound = powered_on();
+
+    sound.write(0xFF24, 0x77);
+    sound.write(0xFF12, 0xF0);
+    sound.write(0xFF14, 0x80);
+    sound.write(0xFF30, 0x12);
+    assert_eq!(sound.read(0xFF26), 0xF1);
+
+    sound.write(0xFF26, 0x00);
+    assert_eq!(sound.read(0xFF26), 0x70);
+    assert_eq!(sound.read(0xFF24), 0x00);
+    assert_eq!(sound.read(0xFF12), 0x00);
+
+    // Writes are ignored while powered off, but wave RAM is still accessible
+    sound.write(0xFF24, 0x77);
+    assert_eq!(sound.read(0xFF24), 0x00);
+    assert_eq!(sound.read(0xFF30), 0x12);
+}
+
+#[test]
+fn length_counter_disables_channel() {
+    let mut sound = powered_on();
+
+    // Two steps remaining with the length counter enabled
+    sound.write(0xFF12, 0xF0);
+    sound.write(0xFF11, 62);
+    sound.write(0xFF14, 0xC0);
+    assert_eq!(sound.read(0xFF26) & 1, 1);
+
+    clock_frame_sequencer(&mut sound);
+    assert_eq!(sound.read(0xFF26) & 1, 1);
+
+    // Step 1 doesn't clock the length counters
+    clock_frame_sequencer(&mut sound);
+    assert_eq!(sound.read(0xFF26) & 1, 1);
+
+    clock_frame_sequencer(&mut sound);
+    assert_eq!(sound.read(0xFF26) & 1, 0);
+}
+
+#[test]
+fn dac_off_prevents_trigger() {
+    let mut sound = powered_on();
+
+    sound.write(0xFF1A, 0x00);
+    sound.write(0xFF1E, 0x80);
+    assert_eq!(sound.read(0xFF26) & 0b100, 0);
+
+    sound.write(0xFF1A, 0x80);
+    sound.write(0xFF1E, 0x80);
+    assert_eq!(sound.read(0xFF26) & 0b100, 0b100);
+}
+
+#[test]
+fn sweep_overflow_disables_channel() {
+    let mut sound = powered_on();
+
+    // Shift of 1 on frequency 0x7FF overflows on trigger
+    sound.write(0xFF10, 0x11);
+    sound.write(0xFF12, 0xF0);
+    sound.write(0xFF13, 0xFF);
+    sound.write(0xFF14, 0x87);
+    assert_eq!(sound.read(0xFF26) & 1, 0);
+}
+
+#[test]
+fn output_follows_panning() {
+    let mut sound = powered_on();
+
+    sound.write(0xFF24, 0x77);
+    sound.write(0xFF25, 0x01);
+    sound.write(0xFF12, 0xF0);
+    sound.write(0xFF11, 0xC0);
+    sound.write(0xFF13, 0xFF);
+    sound.write(0xFF14, 0x87);
+
+    // Step until the 75% duty wave is high
+    sound.step(4, 0);
+
+    let (left, right) = sound.output();
+    assert_eq!(left, 0.0);
+    assert!(right > 0.0);
+}
+
+#[test]
+fn resampler_averages_output() {
+    let mut sink = Samples { samples: Vec::new() };
+    let mut resampler = Resampler::new(sink.sample_rate());
+
+    // A second of emulated time produces a second of samples
+    for _ in 0..(4_194_304 / 4) {
+        resampler.step(4, (0.5, -0.5), &mut sink);
+    }
+
+    assert!((sink.samples.len() as i32 - 44100).abs() <= 1);
+    assert!(sink.samples.iter().all(|&(left, right)| (left - 0.5).abs() < 1e-4 && (right + 0.5).abs() < 1e-4));
+
+    // A sample half way between two levels averages them
+    let mut sink = Samples { samples: Vec::new() };
+    let mut resampler = Resampler::new(sink.sample_rate());
+
+    resampler.step(47, (1.0, 1.0), &mut sink);
+    resampler.step(49, (0.0, 0.0), &mut sink);
+
+    assert_eq!(sink.samples.len(), 1);
+    assert!((sink.samples[0].0 - 0.5).abs() < 0.02);
+}
+
+#[test]
+fn wav_header_sizes() {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), 48000).unwrap();
+
+    writer.push_sample(1.0, -1.0);
+    writer.push_sample(0.0, 0.0);
+    writer.finish().unwrap();
+
+    let data = writer.into_inner().into_inner();
+
+    assert_eq!(data.len(), 44 + 8);
+    assert_eq!(&data[0..4], b"RIFF");
+    assert_eq!(&data[4..8], &(36u32 + 8).to_le_bytes());
+    assert_eq!(&data[24..28], &48000u32.to_le_bytes());
+    assert_eq!(&data[40..44], &8u32.to_le_bytes());
+    assert_eq!(&data[44..48], &[0xFF, 0x7F, 0x01, 0x80]);
+}
@@ -0,0 +1,90 @@
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::{self, Seek, SeekFrom, Write};
+use super::sink::AudioSink;
+
+const CHANNELS: u16 = 2;
+const BITS_PER_SAMPLE: u16 = 16;
+const HEADER_SIZE: u32 = 44;
+
+// Writes samples to a 16 bit stereo PCM WAV file.
+// The sizes in the header aren't known until the end, so they are filled in by finish().
+pub struct WavWriter<W: Write + Seek> {
+    out: W,
+    sample_rate: u32,
+    data_size: u32,
+    error: Option<io::Error>    // First write error. Reported by finish() since push_sample can't fail.
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut out: W, sample_rate: u32) -> io::Result<Self> {
+        write_header(&mut out, sample_rate, 0)?;
+
+        Ok(Self {
+            out,
+            sample_rate,
+            data_size: 0,
+            error: None
+        })
+    }
+
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+
+    fn write_sample(&mut self, left: f32, right: f32) -> io::Result<()> {
+        self.out.write_i16::<LittleEndian>(to_i16(left))?;
+        self.out.write_i16::<LittleEndian>(to_i16(right))?;
+        self.data_size += (CHANNELS * BITS_PER_SAMPLE / 8) as u32;
+
+        Ok(())
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavWriter<W> {
+    fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    fn push_sample(&mut self, left: f32, right: f32) {
+        if self.error.is_none() {
+            if let Err(err) = self.write_sample(left, right) {
+                self.error = Some(err);
+            }
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        self.out.seek(SeekFrom::Start(0))?;
+        write_header(&mut self.out, self.sample_rate, self.data_size)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn write_header(out: &mut dyn Write, sample_rate: u32, data_size: u32) -> io::Result<()> {
+    let block_align = CHANNELS * BITS_PER_SAMPLE / 8;
+
+    out.write_all(b"RIFF")?;
+    out.write_u32::<LittleEndian>(HEADER_SIZE - 8 + data_size)?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_u32::<LittleEndian>(16)?;                                     // Chunk size
+    out.write_u16::<LittleEndian>(1)?;                                      // PCM
+    out.write_u16::<LittleEndian>(CHANNELS)?;
+    out.write_u32::<LittleEndian>(sample_rate)?;
+    out.write_u32::<LittleEndian>(sample_rate * block_align as u32)?;       // Bytes per second
+    out.write_u16::<LittleEndian>(block_align)?;
+    out.write_u16::<LittleEndian>(BITS_PER_SAMPLE)?;
+
+    out.write_all(b"data")?;
+    out.write_u32::<LittleEndian>(data_size)
+}
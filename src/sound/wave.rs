@@ -0,0 +1,149 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
+use super::length::LengthCounter;
+
+pub const WAVE_RAM_SIZE: usize = 16;
+
+// Volume code in NR32 is applied by shifting the 4 bit sample right.
+// Code 0 mutes the channel, 1 is full volume, 2 is half and 3 is a quarter.
+const VOLUME_SHIFT: [u8; 4] = [4, 0, 1, 2];
+
+// Plays back 32 4-bit samples stored in wave RAM (0xFF30-0xFF3F)
+pub struct Wave {
+    pub enabled: bool,
+    dac_enabled: bool,
+    pub length: LengthCounter,
+    volume_code: u8,
+    frequency: u16,             // 11 bit value. Each sample plays for (2048-frequency)*2 cycles.
+    timer: usize,
+    position: u8,               // Index (0-31) of the sample being played
+    pub ram: [u8; WAVE_RAM_SIZE]
+}
+
+impl Wave {
+    pub fn new() -> Self {
+        Self {
+            enabled: false,
+            dac_enabled: false,
+            length: LengthCounter::new(256),
+            volume_code: 0,
+            frequency: 0,
+            timer: 4096,
+            position: 0,
+            ram: [0; WAVE_RAM_SIZE]
+        }
+    }
+
+    // Registers are numbered 0-4 for NR30-NR34
+    pub fn read(&self, reg: u16) -> u8 {
+        match reg {
+            0 => (self.dac_enabled as u8) << 7,
+            1 => 0,
+            2 => self.volume_code << 5,
+            3 => 0,
+            4 => (self.length.enabled as u8) << 6,
+            _ => unreachable!()
+        }
+    }
+
+    pub fn write(&mut self, reg: u16, val: u8) {
+        match reg {
+            0 => {
+                self.dac_enabled = val & 0b1000_0000 != 0;
+
+                if !self.dac_enabled {
+                    self.enabled = false;
+                }
+            },
+            1 => self.length.load(val),
+            2 => self.volume_code = (val >> 5) & 0b11,
+            3 => self.frequency = (self.frequency & 0x700) | (val as u16),
+            4 => {
+                self.frequency = (self.frequency & 0xFF) | (((val & 0b111) as u16) << 8);
+                self.length.enabled = val & 0b0100_0000 != 0;
+
+                if val & 0b1000_0000 != 0 {
+                    self.trigger();
+                }
+            },
+            _ => unreachable!()
+        }
+    }
+
+    fn trigger(&mut self) {
+        self.enabled = self.dac_enabled;
+        self.length.trigger();
+        self.timer = self.period();
+        self.position = 0;
+    }
+
+    fn period(&self) -> usize {
+        (2048 - self.frequency as usize) * 2
+    }
+
+    pub fn step(&mut self, cycles: usize) {
+        let mut cycles = cycles;
+
+        while cycles >= self.timer {
+            cycles -= self.timer;
+            self.timer = self.period();
+            self.position = (self.position + 1) & 31;
+        }
+
+        self.timer -= cycles;
+    }
+
+    pub fn clock_length(&mut self) {
+        if self.length.clock() {
+            self.enabled = false;
+        }
+    }
+
+    pub fn dac_enabled(&self) -> bool {
+        self.dac_enabled
+    }
+
+    // Current digital output (0-15). Samples are stored two per byte, upper nibble first.
+    pub fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        let byte = self.ram[(self.position / 2) as usize];
+        let sample = if self.position & 1 == 0 { byte >> 4 } else { byte & 0x0F };
+
+        sample >> VOLUME_SHIFT[self.volume_code as usize]
+    }
+
+    // Powering off the APU clears the registers, but not wave RAM
+    pub fn reset(&mut self) {
+        let ram = self.ram;
+        *self = Self::new();
+        self.ram = ram;
+    }
+}
+
+impl Savestate for Wave {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        savestate::write_bool(out, self.enabled)?;
+        savestate::write_bool(out, self.dac_enabled)?;
+        self.length.save_state(out)?;
+        out.write_u8(self.volume_code)?;
+        out.write_u16::<LittleEndian>(self.frequency)?;
+        savestate::write_usize(out, self.timer)?;
+        out.write_u8(self.position)?;
+        out.write_all(&self.ram)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.enabled = savestate::read_bool(input)?;
+        self.dac_enabled = savestate::read_bool(input)?;
+        self.length.load_state(input)?;
+        self.volume_code = input.read_u8()? & 0b11;
+        self.frequency = input.read_u16::<LittleEndian>()? & 0x7FF;
+        self.timer = savestate::read_usize(input)?.max(1);
+        self.position = input.read_u8()? & 31;
+        input.read_exact(&mut self.ram)
+    }
+}
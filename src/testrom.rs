@@ -0,0 +1,188 @@
+use cpu::{Cpu, Lockup};
+use emulator::{Emulator, CYCLES_PER_FRAME};
+use screenshot::Image;
+use std::fmt;
+
+const CYCLES_PER_SECOND: u64 = 4_194_304;
+
+// Two minutes of emulated time. Enough for Blargg's cpu_instrs, the slowest of the common suites.
+pub const DEFAULT_MAX_CYCLES: u64 = CYCLES_PER_SECOND * 120;
+
+// Blargg's ROMs keep printing details after "Failed", so keep running a little to capture them
+const FAILED_GRACE_CYCLES: u64 = CYCLES_PER_FRAME as u64 * 60;
+
+// Screenshot tests are compared after two seconds unless told otherwise
+pub const DEFAULT_FRAMES: usize = 120;
+
+// Mooneye's ROMs execute LD B,B once the test is over
+const OPCODE_LD_B_B: u8 = 0x40;
+const MOONEYE_PASSED: [u8; 6] = [3, 5, 8, 13, 21, 34];
+const MOONEYE_FAILED: [u8; 6] = [0x42; 6];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    Timeout                     // Ran out of cycles without the ROM reporting a result
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Outcome::Passed => write!(f, "Passed"),
+            Outcome::Failed => write!(f, "Failed"),
+            Outcome::Timeout => write!(f, "Timed out")
+        }
+    }
+}
+
+pub struct TestReport {
+    pub outcome: Outcome,
+    pub serial: String,         // Text the ROM sent out the serial port
+    pub cycles: u64,
+    pub lockup: Option<Lockup>  // Set if the test failed by running into an illegal opcode
+}
+
+// Runs a test ROM without a frontend until it reports a result or max_cycles have passed.
+// Blargg's ROMs print "Passed" or "Failed" through the serial port.
+// Mooneye's ROMs load the Fibonacci numbers into B, C, D, E, H and L on success, or 0x42 on failure, then run LD B,B.
+pub fn run(emulator: &mut Emulator, max_cycles: u64) -> TestReport {
+    let mut serial = Vec::new();
+    let mut outcome = Outcome::Timeout;
+    let mut deadline = max_cycles;
+    let mut cycles = 0;
+
+    while cycles < deadline {
+        let pc = emulator.cpu().regs.pc();
+        let ld_b_b = !emulator.cpu().halted() && emulator.bus().peek(pc) == OPCODE_LD_B_B;
+
+        let result = emulator.step_instruction();
+        cycles += result.cycles as u64;
+
+        if ld_b_b {
+            if let Some(mooneye_outcome) = mooneye_outcome(emulator.cpu()) {
+                outcome = mooneye_outcome;
+                break;
+            }
+        }
+
+        // Nothing more can happen once the CPU locks up
+        if emulator.cpu().lockup().is_some() {
+            outcome = Outcome::Failed;
+            break;
+        }
+
+        if let Some(byte) = result.serial {
+            serial.push(byte);
+
+            let text = String::from_utf8_lossy(&serial);
+
+            if text.contains("Passed") {
+                outcome = Outcome::Passed;
+                break;
+            } else if outcome != Outcome::Failed && text.contains("Failed") {
+                outcome = Outcome::Failed;
+                deadline = deadline.min(cycles + FAILED_GRACE_CYCLES);
+            }
+        }
+    }
+
+    TestReport {
+        outcome,
+        serial: String::from_utf8_lossy(&serial).into_owned(),
+        cycles,
+        lockup: emulator.cpu().lockup()
+    }
+}
+
+// Runs a ROM without a frontend for a number of frames and returns the last one drawn.
+// For test ROMs that only show their results on screen, and headless screenshots.
+pub fn run_frames(emulator: &mut Emulator, frames: usize) -> Image {
+    for _ in 0..frames {
+        emulator.run_frame();
+    }
+
+    Image::from_frame(emulator.framebuffer())
+}
+
+fn mooneye_outcome(cpu: &Cpu) -> Option<Outcome> {
+    let regs = &cpu.regs;
+    let values = [regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l()];
+
+    if values == MOONEYE_PASSED {
+        Some(Outcome::Passed)
+    } else if values == MOONEYE_FAILED {
+        Some(Outcome::Failed)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::Cartridge;
+
+    fn run_rom(mut cartridge: Cartridge, max_cycles: u64) -> TestReport {
+        run(&mut Emulator::new(&mut cartridge), max_cycles)
+    }
+
+    // 32KB ROM without a mapper. The entry point jumps over the header to the code.
+    fn rom(code: &[u8]) -> Cartridge {
+        let mut rom = vec![0; 0x8000];
+        rom[0x100..0x103].copy_from_slice(&[0xC3, 0x50, 0x01]);
+        rom[0x150..0x150 + code.len()].copy_from_slice(code);
+
+        Cartridge::from_vec(rom)
+    }
+
+    // LD B,n; LD C,n; LD D,n; LD E,n; LD H,n; LD L,n; LD B,B; JR -2
+    fn mooneye_rom(values: [u8; 6]) -> Cartridge {
+        rom(&[0x06, values[0], 0x0E, values[1], 0x16, values[2], 0x1E, values[3], 0x26, values[4], 0x2E, values[5], 0x40, 0x18, 0xFE])
+    }
+
+    // Sends each character with LD A,n; LDH (SB),A; LD A,0x81; LDH (SC),A then loops forever
+    fn serial_rom(text: &str) -> Cartridge {
+        let mut code = Vec::new();
+
+        for byte in text.bytes() {
+            code.extend_from_slice(&[0x3E, byte, 0xE0, 0x01, 0x3E, 0x81, 0xE0, 0x02]);
+        }
+
+        code.extend_from_slice(&[0x18, 0xFE]);
+        rom(&code)
+    }
+
+    #[test]
+    fn mooneye_signatures() {
+        assert_eq!(run_rom(mooneye_rom(MOONEYE_PASSED), CYCLES_PER_SECOND).outcome, Outcome::Passed);
+        assert_eq!(run_rom(mooneye_rom(MOONEYE_FAILED), CYCLES_PER_SECOND).outcome, Outcome::Failed);
+        assert_eq!(run_rom(mooneye_rom([1, 2, 3, 4, 5, 6]), CYCLES_PER_SECOND).outcome, Outcome::Timeout);
+    }
+
+    #[test]
+    fn blargg_serial_output() {
+        let report = run_rom(serial_rom("cpu\nPassed\n"), CYCLES_PER_SECOND);
+        assert_eq!(report.outcome, Outcome::Passed);
+        assert_eq!(report.serial, "cpu\nPassed");
+
+        // Output after the failure is still captured
+        let report = run_rom(serial_rom("Failed #2\n"), CYCLES_PER_SECOND);
+        assert_eq!(report.outcome, Outcome::Failed);
+        assert_eq!(report.serial, "Failed #2\n");
+
+        let report = run_rom(serial_rom("Running"), CYCLES_PER_SECOND);
+        assert_eq!(report.outcome, Outcome::Timeout);
+        assert!(report.cycles >= CYCLES_PER_SECOND);
+    }
+
+    #[test]
+    fn lockup_fails_immediately() {
+        // NOP; 0xDD
+        let report = run_rom(rom(&[0x00, 0xDD]), CYCLES_PER_SECOND);
+
+        assert_eq!(report.outcome, Outcome::Failed);
+        assert_eq!(report.lockup, Some(Lockup { addr: 0x151, opcode: 0xDD }));
+        assert!(report.cycles < 100);
+    }
+}
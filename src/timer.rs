@@ -1,6 +1,9 @@
 use bus::Addressable;
+use byteorder::{ReadBytesExt, WriteBytesExt};
 use enum_primitive::FromPrimitive;
 use log::warn;
+use savestate::{self, Savestate};
+use std::io::{self, Read, Write};
 
 const ADDR_DIV: u16  = 0xFF04;
 const ADDR_TIMA: u16 = 0xFF05;
@@ -44,6 +47,12 @@ pub struct Timer {
     tima_cycles: usize
 }
 
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Timer {
     pub fn new() -> Self {
         Self {
@@ -57,6 +66,10 @@ impl Timer {
         }
     }
 
+    pub fn div(&self) -> u8 {
+        self.div
+    }
+
     // TODO: implement odd Timer behaviors
     pub fn step(&mut self, cycles: usize) -> TimerResult {
         let mut result = TimerResult::default();
@@ -121,4 +134,28 @@ impl Addressable for Timer {
             _ => warn!("Timer write unimplemented {:#X} -> {:#X}", val, addr)
         }
     }
-}
\ No newline at end of file
+}
+
+impl Savestate for Timer {
+    fn save_state(&self, out: &mut dyn Write) -> io::Result<()> {
+        out.write_u8(self.div)?;
+        out.write_u8(self.tima)?;
+        out.write_u8(self.tma)?;
+        savestate::write_bool(out, self.tac_enabled)?;
+        out.write_u8(self.tac_freq as u8)?;
+        savestate::write_usize(out, self.div_cycles)?;
+        savestate::write_usize(out, self.tima_cycles)
+    }
+
+    fn load_state(&mut self, input: &mut dyn Read) -> io::Result<()> {
+        self.div = input.read_u8()?;
+        self.tima = input.read_u8()?;
+        self.tma = input.read_u8()?;
+        self.tac_enabled = savestate::read_bool(input)?;
+        self.tac_freq = TacFrequency::from_u8(input.read_u8()?).ok_or_else(|| savestate::invalid_data("Invalid TAC frequency"))?;
+        self.div_cycles = savestate::read_usize(input)?;
+        self.tima_cycles = savestate::read_usize(input)?;
+
+        Ok(())
+    }
+}
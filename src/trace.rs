@@ -0,0 +1,164 @@
+use bus::Bus;
+use cpu::Cpu;
+use std::io::{self, Write};
+
+// Limits which instructions end up in the trace
+#[derive(Copy, Clone, Default)]
+pub struct TraceFilter {
+    pub pc_range: Option<(u16, u16)>,   // Inclusive
+    pub bank: Option<usize>,            // ROM bank mapped at 0x4000-0x7FFF
+    pub skip: u64                       // Instructions executed before tracing starts
+}
+
+impl TraceFilter {
+    fn matches(&self, pc: u16, bank: usize) -> bool {
+        let in_range = self.pc_range.is_none_or(|(start, end)| pc >= start && pc <= end);
+        let in_bank = self.bank.is_none_or(|filter_bank| bank == filter_bank);
+
+        in_range && in_bank
+    }
+}
+
+// Writes the CPU state before every instruction in the format used by gameboy-doctor:
+// A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+pub struct Tracer<'a> {
+    out: Box<dyn Write + 'a>,
+    filter: TraceFilter,
+    executed: u64,
+    error: Option<io::Error>            // First write error. Tracing stops once one happens.
+}
+
+impl<'a> Tracer<'a> {
+    pub fn new(out: Box<dyn Write + 'a>, filter: TraceFilter) -> Self {
+        Self {
+            out,
+            filter,
+            executed: 0,
+            error: None
+        }
+    }
+
+    // Called before the CPU steps
+    pub fn trace(&mut self, cpu: &Cpu, bus: &Bus) {
+        // Steps that dispatch an interrupt, or where the CPU is halted, stopped or locked up, don't execute anything
+        if self.error.is_some() || !cpu.executes_next(bus) {
+            return;
+        }
+
+        self.executed += 1;
+
+        if self.executed <= self.filter.skip {
+            return;
+        }
+
+        let pc = cpu.regs.pc();
+
+        if !self.filter.matches(pc, bus.cartridge().rom_bank()) {
+            return;
+        }
+
+        if let Err(err) = write_line(&mut self.out, cpu, bus) {
+            self.error = Some(err);
+        }
+    }
+
+    // Flushes the output. Returns the first error hit while tracing.
+    pub fn finish(&mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush()
+        }
+    }
+}
+
+fn write_line(out: &mut dyn Write, cpu: &Cpu, bus: &Bus) -> io::Result<()> {
+    let regs = &cpu.regs;
+    let pc = regs.pc();
+
+    writeln!(out, "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        regs.a(), regs.f(), regs.b(), regs.c(), regs.d(), regs.e(), regs.h(), regs.l(), regs.sp(), pc,
+        bus.peek(pc), bus.peek(pc.wrapping_add(1)), bus.peek(pc.wrapping_add(2)), bus.peek(pc.wrapping_add(3)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cartridge::Cartridge;
+    use emulator::Emulator;
+
+    fn trace(filter: TraceFilter, instructions: usize) -> String {
+        trace_rom(&[], filter, instructions)
+    }
+
+    // Traces a ROM with the given code at 0x0100, with RETI at the timer interrupt vector
+    fn trace_rom(code: &[u8], filter: TraceFilter, instructions: usize) -> String {
+        let mut rom = vec![0; 0x8000];
+        rom[0x50] = 0xD9;
+        rom[0x100..0x100 + code.len()].copy_from_slice(code);
+
+        let mut cartridge = Cartridge::from_vec(rom);
+        let mut log = Vec::new();
+
+        {
+            let mut emulator = Emulator::new(&mut cartridge);
+            emulator.set_trace(Box::new(&mut log), filter);
+
+            for _ in 0..instructions {
+                emulator.step_instruction();
+            }
+
+            emulator.finish_trace().unwrap();
+        }
+
+        String::from_utf8(log).unwrap()
+    }
+
+    #[test]
+    fn trace_matches_gameboy_doctor_format() {
+        let log = trace(TraceFilter::default(), 2);
+        let lines: Vec<_> = log.lines().collect();
+
+        assert_eq!(lines, [
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,00,00,00",
+            "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0101 PCMEM:00,00,00,00"
+        ]);
+    }
+
+    #[test]
+    fn trace_filters() {
+        let filter = TraceFilter { pc_range: Some((0x104, 0x105)), bank: None, skip: 0 };
+        let log = trace(filter, 10);
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.lines().next().unwrap().contains("PC:0104"));
+
+        let filter = TraceFilter { pc_range: None, bank: None, skip: 8 };
+        let log = trace(filter, 10);
+        assert_eq!(log.lines().count(), 2);
+        assert!(log.lines().next().unwrap().contains("PC:0108"));
+
+        // The ROM has no mapper, so bank 1 is always mapped
+        let filter = TraceFilter { pc_range: None, bank: Some(2), skip: 0 };
+        assert!(trace(filter, 10).is_empty());
+    }
+
+    fn traced_pcs(log: &str) -> Vec<&str> {
+        log.lines().map(|line| &line[line.find("PC:").unwrap() + 3..][..4]).collect()
+    }
+
+    #[test]
+    fn trace_skips_interrupt_dispatch() {
+        // LD A,4; LDH (IE),A; LDH (IF),A; EI; NOP; NOP
+        let code = [0x3E, 0x04, 0xE0, 0xFF, 0xE0, 0x0F, 0xFB, 0x00, 0x00];
+        let log = trace_rom(&code, TraceFilter::default(), 8);
+
+        // The interrupted instruction is only logged when the handler returns to it
+        assert_eq!(traced_pcs(&log), ["0100", "0102", "0104", "0106", "0107", "0050", "0108"]);
+    }
+
+    #[test]
+    fn trace_skips_stopped_cpu() {
+        let log = trace_rom(&[0x10, 0x00], TraceFilter::default(), 5);
+
+        assert_eq!(traced_pcs(&log), ["0100"]);
+    }
+}
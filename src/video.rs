@@ -0,0 +1,170 @@
+use emulator::CYCLES_PER_FRAME;
+use lcd::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use sound::{AudioSink, Resampler};
+use std::io::{self, Write};
+
+const CYCLES_PER_SECOND: usize = 4_194_304;
+
+// Writes uncompressed YUV4MPEG2 video, which ffmpeg and most players read directly.
+// Frames are stored as full range 4:4:4 YCbCr, so no color detail is lost to chroma subsampling.
+// The frame rate is the LCD's exact refresh rate of 4194304 / 70224 Hz.
+pub struct Y4mWriter<W: Write> {
+    out: W
+}
+
+impl<W: Write> Y4mWriter<W> {
+    pub fn new(mut out: W) -> io::Result<Self> {
+        writeln!(out, "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444 XCOLORRANGE=FULL", SCREEN_WIDTH, SCREEN_HEIGHT, CYCLES_PER_SECOND, CYCLES_PER_FRAME)?;
+
+        Ok(Self { out })
+    }
+
+    pub fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        let mut planes = vec![0; pixels.len() * 3];
+        let (y_plane, chroma) = planes.split_at_mut(pixels.len());
+        let (cb_plane, cr_plane) = chroma.split_at_mut(pixels.len());
+
+        for (i, &rgb) in pixels.iter().enumerate() {
+            let (y, cb, cr) = ycbcr(rgb);
+
+            y_plane[i] = y;
+            cb_plane[i] = cb;
+            cr_plane[i] = cr;
+        }
+
+        self.out.write_all(b"FRAME\n")?;
+        self.out.write_all(&planes)
+    }
+
+    pub fn finish(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+// BT.601 conversion as used by JPEG, without the studio swing
+fn ycbcr(rgb: u32) -> (u8, u8, u8) {
+    let r = ((rgb >> 16) & 0xFF) as f32;
+    let g = ((rgb >> 8) & 0xFF) as f32;
+    let b = (rgb & 0xFF) as f32;
+
+    let y = 0.299 * r + 0.587 * g + 0.114 * b;
+    let cb = 128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b;
+
+    (y.round().clamp(0.0, 255.0) as u8, cb.round().clamp(0.0, 255.0) as u8, cr.round().clamp(0.0, 255.0) as u8)
+}
+
+// Records the screen, and optionally the sound, as the emulator runs.
+// A video frame is written for every frame's worth of emulated cycles, so the recording plays back at the speed
+// the game ran regardless of how fast the host was. While the LCD is off the last frame it drew is repeated.
+pub struct Recorder<'a> {
+    video: Y4mWriter<Box<dyn Write + 'a>>,
+    audio: Option<(Resampler, Box<dyn AudioSink + 'a>)>,
+    frame: Vec<u32>,                    // Last complete frame drawn by the LCD
+    cycles: usize,                      // Cycles since the last video frame was written
+    error: Option<io::Error>            // First write error. Recording stops once one happens.
+}
+
+impl<'a> Recorder<'a> {
+    pub fn new(video: Box<dyn Write + 'a>, audio: Option<Box<dyn AudioSink + 'a>>) -> io::Result<Self> {
+        Ok(Self {
+            video: Y4mWriter::new(video)?,
+            audio: audio.map(|sink| (Resampler::new(sink.sample_rate()), sink)),
+            frame: vec![0xFFFFFF; SCREEN_WIDTH * SCREEN_HEIGHT],
+            cycles: 0,
+            error: None
+        })
+    }
+
+    // Called after every instruction with the cycles it took and the hardware's output
+    pub fn step(&mut self, cycles: usize, sound: (f32, f32), vblank: bool, framebuffer: &[u32]) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Some((ref mut resampler, ref mut sink)) = self.audio {
+            resampler.step(cycles, sound, sink.as_mut());
+        }
+
+        if vblank {
+            self.frame.copy_from_slice(framebuffer);
+        }
+
+        self.cycles += cycles;
+
+        while self.cycles >= CYCLES_PER_FRAME {
+            self.cycles -= CYCLES_PER_FRAME;
+
+            if let Err(err) = self.video.write_frame(&self.frame) {
+                self.error = Some(err);
+                return;
+            }
+        }
+    }
+
+    // Flushes both streams. Returns the first error hit while recording.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if let Some(err) = self.error.take() {
+            return Err(err);
+        }
+
+        let audio_result = match self.audio {
+            Some((_, ref mut sink)) => sink.finish(),
+            None => Ok(())
+        };
+
+        self.video.finish().and(audio_result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME_BYTES: usize = 6 + SCREEN_WIDTH * SCREEN_HEIGHT * 3;
+
+    struct CountingSink(usize);
+
+    impl AudioSink for &mut CountingSink {
+        fn sample_rate(&self) -> u32 {
+            44100
+        }
+
+        fn push_sample(&mut self, _left: f32, _right: f32) {
+            self.0 += 1;
+        }
+    }
+
+    #[test]
+    fn frames_follow_emulated_time() {
+        let mut out = Vec::new();
+        let mut sink = CountingSink(0);
+        let framebuffer = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT];
+
+        {
+            let mut recorder = Recorder::new(Box::new(&mut out), Some(Box::new(&mut sink))).unwrap();
+
+            // Three and a half frames of cycles without VBlank, like when the LCD is off
+            for _ in 0..(CYCLES_PER_FRAME * 7 / 8) {
+                recorder.step(4, (0.0, 0.0), false, &framebuffer);
+            }
+
+            recorder.finish().unwrap();
+        }
+
+        let header = b"YUV4MPEG2 W160 H144 F4194304:70224 Ip A1:1 C444 XCOLORRANGE=FULL\n";
+        assert!(out.starts_with(header));
+        assert_eq!(out.len(), header.len() + FRAME_BYTES * 3);
+        assert_eq!(&out[header.len()..header.len() + 6], b"FRAME\n");
+
+        // 3.5 frames of audio at 44.1KHz
+        assert_eq!(sink.0, (CYCLES_PER_FRAME * 7 / 2) * 44100 / CYCLES_PER_SECOND);
+    }
+
+    #[test]
+    fn rgb_to_ycbcr() {
+        assert_eq!(ycbcr(0xFFFFFF), (255, 128, 128));
+        assert_eq!(ycbcr(0x000000), (0, 128, 128));
+        assert_eq!(ycbcr(0xFF0000), (76, 85, 255));
+    }
+}
@@ -0,0 +1,82 @@
+// Runs Blargg and Mooneye test ROMs through the headless runner.
+// The ROMs aren't distributed with the emulator. Point RUSTBOY_TEST_ROMS at a directory holding them,
+// e.g. RUSTBOY_TEST_ROMS=roms/blargg/cpu_instrs/individual cargo test --release --test test_roms
+// Every .gb file found under the directory is run. Without the variable the test does nothing.
+// ROMs with a .png of the same name next to them are screenshot tests. Their screen is compared against the image
+// after 120 frames, and a .diff.png is written next to the ROM if it doesn't match.
+extern crate rustboy;
+
+use rustboy::{Cartridge, Emulator};
+use rustboy::screenshot::{self, DmgPalette};
+use rustboy::testrom::{self, Outcome};
+use std::env;
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+fn find_roms(dir: &Path, roms: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(dir).unwrap_or_else(|err| panic!("Unable to read {}: {}", dir.display(), err));
+
+    for entry in entries {
+        let path = entry.unwrap().path();
+
+        if path.is_dir() {
+            find_roms(&path, roms);
+        } else if path.extension().is_some_and(|ext| ext == "gb") {
+            roms.push(path);
+        }
+    }
+}
+
+// Returns a description of the failure if the screen doesn't match the reference
+fn screenshot_test(rom: &Path, reference: &Path) -> Option<String> {
+    let reference = screenshot::read_png(File::open(reference).unwrap()).unwrap();
+
+    let mut cartridge = Cartridge::new(rom.to_str().unwrap());
+    let frame = testrom::run_frames(&mut Emulator::new(&mut cartridge), testrom::DEFAULT_FRAMES);
+    let comparison = screenshot::compare(&frame, &reference, &DmgPalette::default()).unwrap();
+
+    if comparison.mismatched == 0 {
+        return None;
+    }
+
+    let diff_path = rom.with_extension("diff.png");
+    screenshot::write_png(File::create(&diff_path).unwrap(), &comparison.diff).unwrap();
+
+    Some(format!("{}: {} pixels differ, see {}", rom.display(), comparison.mismatched, diff_path.display()))
+}
+
+#[test]
+fn test_roms() {
+    let dir = match env::var_os("RUSTBOY_TEST_ROMS") {
+        Some(dir) => PathBuf::from(dir),
+        None => return
+    };
+
+    let mut roms = Vec::new();
+    find_roms(&dir, &mut roms);
+    roms.sort();
+
+    let mut failures = Vec::new();
+
+    for rom in &roms {
+        let reference = rom.with_extension("png");
+
+        if reference.is_file() {
+            let failure = screenshot_test(rom, &reference);
+            println!("{}: {}", rom.display(), if failure.is_some() { "Failed" } else { "Passed" });
+            failures.extend(failure);
+            continue;
+        }
+
+        let mut cartridge = Cartridge::new(rom.to_str().unwrap());
+        let report = testrom::run(&mut Emulator::new(&mut cartridge), testrom::DEFAULT_MAX_CYCLES);
+
+        println!("{}: {}", rom.display(), report.outcome);
+
+        if report.outcome != Outcome::Passed {
+            failures.push(format!("{}: {}\n{}", rom.display(), report.outcome, report.serial.trim_end()));
+        }
+    }
+
+    assert!(failures.is_empty(), "{} of {} test ROMs didn't pass:\n{}", failures.len(), roms.len(), failures.join("\n"));
+}